@@ -1,17 +1,72 @@
 use core::cell::Cell;
 use tape::Tape;
-use module::Opcode;
+use module::{Opcode, TableEntry};
+use mmio::MmioRegion;
+use vm::Limits;
 use error::*;
+#[cfg(feature = "guard-memory")]
+use guard_memory::GuardedMemory;
+#[cfg(feature = "shared-memory")]
+use shared_memory::SharedMemory;
 
 pub trait Environment {
     fn get_memory(&self) -> &[u8];
     fn get_memory_mut(&mut self) -> &mut [u8];
     fn grow_memory(&mut self, len_inc: usize) -> ExecuteResult<()>;
 
+    // Environments backed by a `GuardedMemory` should return it here (and its slices
+    // from `get_memory` / `get_memory_mut`) so that loads and stores skip bounds checks.
+    #[cfg(feature = "guard-memory")]
+    fn get_guarded_memory(&self) -> Option<&GuardedMemory> { None }
+    #[cfg(feature = "guard-memory")]
+    fn get_guarded_memory_mut(&mut self) -> Option<&mut GuardedMemory> { None }
+
+    // Environments whose memory is shared with other instances should return it here.
+    // `get_memory` / `get_memory_mut` are not used for linear memory access then.
+    #[cfg(feature = "shared-memory")]
+    fn get_shared_memory(&self) -> Option<SharedMemory<'_>> { None }
+
+    // Called by `MemoryAtomicWait32` / `MemoryAtomicWait64` with a checked address.
+    // If the `width`-byte value at `addr` equals `expected`, blocks until woken by
+    // `atomic_notify` or until `timeout_ns` (if not negative) has passed. The comparison
+    // and going to sleep must be atomic with respect to `atomic_notify`.
+    // Returns 0 if woken, 1 if the value was not equal and 2 on timeout.
+    // `SharedMemoryBuffer` implements this and `atomic_notify` for shared memory.
+    fn atomic_wait(&mut self, _addr: usize, _expected: u64, _width: usize, _timeout_ns: i64) -> ExecuteResult<u32> {
+        Err(ExecuteError::NotSupported)
+    }
+
+    // Wakes up to `count` waiters on `addr` and returns how many were woken.
+    fn atomic_notify(&mut self, _addr: usize, _count: u32) -> ExecuteResult<u32> {
+        Ok(0)
+    }
+
+    // Address ranges whose loads and stores call `mmio_read` / `mmio_write` instead of
+    // accessing memory, with the offset within the region and the width in bytes. Must not
+    // overlap. Loads and stores only check whether this is empty if it always is. See `mmio`
+    // for how other memory operations treat them.
+    fn get_mmio_regions(&self) -> &[MmioRegion] { &[] }
+
+    fn mmio_read(&mut self, _region: MmioRegion, _offset: usize, _width: usize) -> ExecuteResult<u64> {
+        Err(ExecuteError::NotSupported)
+    }
+
+    fn mmio_write(&mut self, _region: MmioRegion, _offset: usize, _width: usize, _val: u64) -> ExecuteResult<()> {
+        Err(ExecuteError::NotSupported)
+    }
+
+    // Initial `VirtualMachine::limits` of a VM created over this environment.
+    fn get_limits(&self) -> Limits { Limits::default() }
+
     fn get_slots(&self) -> &[i64];
     fn get_slots_mut(&mut self) -> &mut [i64];
     fn reset_slots(&mut self, len: usize) -> ExecuteResult<()>;
 
+    // Function table used by `CallIndirect`, filled from the module by
+    // `VirtualMachine::run_table_initializers`. Hosts may update entries at any time.
+    fn get_table(&self) -> &[Option<TableEntry>] { &[] }
+    fn get_table_mut(&mut self) -> &mut [Option<TableEntry>] { &mut [] }
+
     fn get_stack(&self) -> &Tape<Cell<i64>>;
 
     // Frame layout (from top to bottom):
@@ -20,10 +75,21 @@ pub trait Environment {
     // - [all_locals]
     fn get_call_stack(&self) -> &Tape<Cell<i64>>;
 
+    // Operand stack position after popping the arguments of each frame on the call stack,
+    // pushed by calls and popped by returns, so that `Throw` can unwind the operand stack
+    // to a handler in a called function. Only used by modules with handlers, which need
+    // a cell per frame. Without it, only handlers in the function `run` started in can
+    // catch; a `Throw` caught further in fails with `ExecuteError::NotSupported`.
+    fn get_frame_stack(&self) -> Option<&Tape<Cell<i64>>> { None }
+
     fn do_native_invoke(&mut self, _id: usize) -> ExecuteResult<Option<i64>> {
         Err(ExecuteError::InvalidNativeInvoke)
     }
 
+    // Whether the `trace_*` hooks look at or change the operand stack. If not, the VM can
+    // keep the top of the stack in a register across them.
+    const TRACE_STACK: bool = true;
+
     fn trace_mem_init(&self, _start: usize, _data: &[u8]) {}
     fn trace_opcode(&self, _op: &Opcode) -> ExecuteResult<()> { Ok(()) }
     fn trace_call(&self, _target: usize, _n_locals: usize) {}
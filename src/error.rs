@@ -1,4 +1,4 @@
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u8)]
 pub enum ExecuteError {
     Generic = 1,
@@ -13,13 +13,60 @@ pub enum ExecuteError {
     SlotLimit,
     FatalSignal,
     Fuse,
-    DivideByZero
+    DivideByZero,
+    InvalidConversion, // float to int conversion of NaN or an out-of-range value
+    NullTableEntry,
+    SignatureMismatch,
+    MisalignedAtomic,
+    IntegerOverflow,
+    UncaughtException(u32), // tag
+    NativeArgumentMismatch, // too few operands or one of the wrong type for a typed native function
+    Exit(i32), // the `exit` syscall, with the guest's status
+    Abort { message_base: u32, message_len: u32 }, // the `abort` syscall, with its UTF-8 message in linear memory
+    MmioAccess // a bulk memory or atomic operation on an MMIO region
 }
 
 pub type ExecuteResult<T> = Result<T, ExecuteError>;
 
 impl ExecuteError {
+    // The message of an `Abort`, looked up in the memory it was raised with.
+    pub fn abort_message<'a>(&self, memory: &'a [u8]) -> Option<&'a str> {
+        match *self {
+            ExecuteError::Abort { message_base, message_len } => {
+                let start = message_base as usize;
+                let end = start.checked_add(message_len as usize)?;
+                memory.get(start..end).and_then(|v| ::core::str::from_utf8(v).ok())
+            },
+            _ => None
+        }
+    }
+
     pub fn status(&self) -> i32 {
-        -(*self as u8 as i32)
+        let code = match *self {
+            ExecuteError::Generic => 1,
+            ExecuteError::Bounds => 2,
+            ExecuteError::Unreachable => 3,
+            ExecuteError::IllegalOpcode => 4,
+            ExecuteError::InvalidNativeInvoke => 5,
+            ExecuteError::NotSupported => 6,
+            ExecuteError::InvalidInput => 7,
+            ExecuteError::ExecutionLimit => 8,
+            ExecuteError::MemoryLimit => 9,
+            ExecuteError::SlotLimit => 10,
+            ExecuteError::FatalSignal => 11,
+            ExecuteError::Fuse => 12,
+            ExecuteError::DivideByZero => 13,
+            ExecuteError::InvalidConversion => 14,
+            ExecuteError::NullTableEntry => 15,
+            ExecuteError::SignatureMismatch => 16,
+            ExecuteError::MisalignedAtomic => 17,
+            ExecuteError::IntegerOverflow => 18,
+            ExecuteError::UncaughtException(_) => 19,
+            ExecuteError::NativeArgumentMismatch => 20,
+            ExecuteError::Exit(_) => 21,
+            ExecuteError::Abort { .. } => 22,
+            ExecuteError::MmioAccess => 23
+        };
+        -code
     }
 }
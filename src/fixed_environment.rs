@@ -0,0 +1,152 @@
+// An `Environment` over caller-provided buffers, for targets without an allocator.
+//
+// Linear memory and slots use a prefix of their buffers; the rest is capacity for
+// `GrowMemory` and `ResetSlots`, which fail with `ExecuteError::MemoryLimit` and
+// `ExecuteError::SlotLimit` beyond it.
+
+use core::cell::Cell;
+use environment::Environment;
+use module::TableEntry;
+use tape::Tape;
+use error::*;
+
+pub type NativeHandler<'a> = fn(&mut FixedEnvironment<'a>, usize) -> ExecuteResult<Option<i64>>;
+
+pub struct FixedEnvironment<'a> {
+    memory: &'a mut [u8],
+    memory_len: usize,
+    slots: &'a mut [i64],
+    n_slots: usize,
+    table: &'a mut [Option<TableEntry>],
+    stack: Tape<'a, Cell<i64>>,
+    call_stack: Tape<'a, Cell<i64>>,
+    frame_stack: Option<Tape<'a, Cell<i64>>>,
+    native_handler: Option<NativeHandler<'a>>
+}
+
+impl<'a> FixedEnvironment<'a> {
+    // The first `memory_len` bytes of `memory` are used as they are. All of `slots`
+    // is in use until the first `ResetSlots`.
+    pub fn new(
+        memory: &'a mut [u8],
+        memory_len: usize,
+        slots: &'a mut [i64],
+        stack: &'a [Cell<i64>],
+        call_stack: &'a [Cell<i64>]
+    ) -> ExecuteResult<FixedEnvironment<'a>> {
+        if memory_len > memory.len() {
+            return Err(ExecuteError::MemoryLimit);
+        }
+
+        let n_slots = slots.len();
+
+        Ok(FixedEnvironment {
+            memory: memory,
+            memory_len: memory_len,
+            slots: slots,
+            n_slots: n_slots,
+            table: &mut [],
+            stack: Tape::from(stack),
+            call_stack: Tape::from(call_stack),
+            frame_stack: None,
+            native_handler: None
+        })
+    }
+
+    // Function table used by `CallIndirect`.
+    pub fn with_table(mut self, table: &'a mut [Option<TableEntry>]) -> FixedEnvironment<'a> {
+        self.table = table;
+        self
+    }
+
+    // Needed by modules whose handlers catch exceptions thrown in called functions, with
+    // a cell per frame.
+    pub fn with_frame_stack(mut self, frame_stack: &'a [Cell<i64>]) -> FixedEnvironment<'a> {
+        self.frame_stack = Some(Tape::from(frame_stack));
+        self
+    }
+
+    // Called with the id of every `NativeInvoke`.
+    pub fn with_native_handler(mut self, f: NativeHandler<'a>) -> FixedEnvironment<'a> {
+        self.native_handler = Some(f);
+        self
+    }
+
+    pub fn memory_capacity(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn slots_capacity(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl<'a> Environment for FixedEnvironment<'a> {
+    const TRACE_STACK: bool = false;
+
+    fn get_memory(&self) -> &[u8] {
+        &self.memory[..self.memory_len]
+    }
+
+    fn get_memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory[..self.memory_len]
+    }
+
+    fn grow_memory(&mut self, len_inc: usize) -> ExecuteResult<()> {
+        let new_len = match self.memory_len.checked_add(len_inc) {
+            Some(v) if v <= self.memory.len() => v,
+            _ => return Err(ExecuteError::MemoryLimit)
+        };
+        for b in &mut self.memory[self.memory_len..new_len] {
+            *b = 0;
+        }
+        self.memory_len = new_len;
+        Ok(())
+    }
+
+    fn get_slots(&self) -> &[i64] {
+        &self.slots[..self.n_slots]
+    }
+
+    fn get_slots_mut(&mut self) -> &mut [i64] {
+        &mut self.slots[..self.n_slots]
+    }
+
+    fn reset_slots(&mut self, len: usize) -> ExecuteResult<()> {
+        if len > self.slots.len() {
+            return Err(ExecuteError::SlotLimit);
+        }
+        for v in &mut self.slots[..len] {
+            *v = 0;
+        }
+        self.n_slots = len;
+        Ok(())
+    }
+
+    fn get_table(&self) -> &[Option<TableEntry>] {
+        self.table
+    }
+
+    fn get_table_mut(&mut self) -> &mut [Option<TableEntry>] {
+        self.table
+    }
+
+    fn get_stack(&self) -> &Tape<Cell<i64>> {
+        &self.stack
+    }
+
+    fn get_call_stack(&self) -> &Tape<Cell<i64>> {
+        &self.call_stack
+    }
+
+    fn get_frame_stack(&self) -> Option<&Tape<Cell<i64>>> {
+        self.frame_stack.as_ref()
+    }
+
+    fn do_native_invoke(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        match self.native_handler {
+            Some(f) => f(self, id),
+            None => Err(ExecuteError::InvalidNativeInvoke)
+        }
+    }
+}
@@ -0,0 +1,374 @@
+// Optional file system syscalls, confined to a host directory. They are handled by
+// `syscall::dispatch` and fail with `ExecuteError::NotSupported` unless
+// `SyscallEnvironment::fs` returns a sandbox.
+//
+// Guest paths are resolved relative to the root of the `FsSandbox`; a leading `/` is
+// ignored. Paths that leave the root through `..` or through a symlink are refused. The
+// checks are made when a path is resolved, so concurrent changes to the directory by the
+// host are not guarded against.
+//
+// Files and directories are referred to by integer handles. Failures of the host file
+// system are returned to the guest as negative results (the `FS_ERR_*` constants), while
+// buffers outside linear memory fail with `ExecuteError::Bounds` and paths that are not
+// valid UTF-8 with `ExecuteError::InvalidInput`. Data is copied through `get_memory_mut`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str;
+use std::string::String;
+use std::vec;
+use std::vec::Vec;
+use syscall::{read_memory, write_memory, SyscallEnvironment};
+use verifier::NativeSignature;
+use error::*;
+
+pub const SYSCALL_FS_OPEN: usize = 16;
+pub const SYSCALL_FS_READ: usize = 17;
+pub const SYSCALL_FS_WRITE: usize = 18;
+pub const SYSCALL_FS_SEEK: usize = 19;
+pub const SYSCALL_FS_CLOSE: usize = 20;
+pub const SYSCALL_FS_STAT: usize = 21;
+pub const SYSCALL_FS_READDIR: usize = 22;
+
+// Flags of `open`.
+pub const FS_READ: u32 = 1;
+pub const FS_WRITE: u32 = 2;
+pub const FS_CREATE: u32 = 4;
+pub const FS_TRUNCATE: u32 = 8;
+pub const FS_APPEND: u32 = 16;
+
+// `whence` of `seek`.
+pub const FS_SEEK_START: u32 = 0;
+pub const FS_SEEK_CURRENT: u32 = 1;
+pub const FS_SEEK_END: u32 = 2;
+
+// Kinds reported by `stat`.
+pub const FS_KIND_OTHER: u32 = 0;
+pub const FS_KIND_FILE: u32 = 1;
+pub const FS_KIND_DIR: u32 = 2;
+
+pub const FS_ERR_NOT_FOUND: i64 = -1;
+pub const FS_ERR_ACCESS: i64 = -2; // also for paths outside the root
+pub const FS_ERR_EXISTS: i64 = -3;
+pub const FS_ERR_BAD_HANDLE: i64 = -4;
+pub const FS_ERR_INVALID: i64 = -5;
+pub const FS_ERR_IO: i64 = -6;
+
+// Size in bytes of what `stat` writes: the size as u64, then the kind as u32 and 4 bytes
+// of padding, little-endian.
+pub const FS_STAT_SIZE: usize = 16;
+
+pub type FsResult<T> = Result<T, i64>;
+
+fn error_code(e: &io::Error) -> i64 {
+    match e.kind() {
+        io::ErrorKind::NotFound => FS_ERR_NOT_FOUND,
+        io::ErrorKind::PermissionDenied => FS_ERR_ACCESS,
+        io::ErrorKind::AlreadyExists => FS_ERR_EXISTS,
+        io::ErrorKind::InvalidInput => FS_ERR_INVALID,
+        _ => FS_ERR_IO
+    }
+}
+
+enum Handle {
+    File(File),
+    // Entry names, sorted, and the position of the next one.
+    Dir(Vec<String>, usize)
+}
+
+pub struct FsSandbox {
+    root: PathBuf,
+    handles: BTreeMap<u32, Handle>,
+    next_handle: u32
+}
+
+impl FsSandbox {
+    // `root` must be an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<FsSandbox> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "sandbox root is not a directory"));
+        }
+
+        Ok(FsSandbox {
+            root: root,
+            handles: BTreeMap::new(),
+            next_handle: 1
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn resolve(&self, path: &str) -> FsResult<PathBuf> {
+        let mut ret = self.root.clone();
+        let mut depth: usize = 0;
+
+        for c in Path::new(path).components() {
+            match c {
+                Component::RootDir | Component::CurDir => {},
+                Component::Normal(v) => {
+                    ret.push(v);
+                    depth += 1;
+                },
+                Component::ParentDir => {
+                    if depth == 0 {
+                        return Err(FS_ERR_ACCESS);
+                    }
+                    ret.pop();
+                    depth -= 1;
+                },
+                Component::Prefix(_) => return Err(FS_ERR_ACCESS)
+            }
+        }
+
+        // The deepest part of the path that exists must not lead out of the root through
+        // a symlink. Dangling symlinks are refused as they could be created through.
+        let mut existing: &Path = &ret;
+        loop {
+            if fs::symlink_metadata(existing).is_ok() {
+                return match fs::canonicalize(existing) {
+                    Ok(ref v) if v.starts_with(&self.root) => Ok(ret.clone()),
+                    _ => Err(FS_ERR_ACCESS)
+                };
+            }
+            existing = existing.parent().ok_or(FS_ERR_ACCESS)?;
+        }
+    }
+
+    fn add_handle(&mut self, handle: Handle) -> FsResult<u32> {
+        // Handles stay positive as i32.
+        if self.next_handle > i32::MAX as u32 {
+            return Err(FS_ERR_IO);
+        }
+        let id = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(id, handle);
+        Ok(id)
+    }
+
+    fn file(&mut self, handle: u32) -> FsResult<&mut File> {
+        match self.handles.get_mut(&handle) {
+            Some(Handle::File(f)) => Ok(f),
+            _ => Err(FS_ERR_BAD_HANDLE)
+        }
+    }
+
+    // Directories can only be opened with `FS_READ` and are then read with `readdir`.
+    pub fn open(&mut self, path: &str, flags: u32) -> FsResult<u32> {
+        if flags & !(FS_READ | FS_WRITE | FS_CREATE | FS_TRUNCATE | FS_APPEND) != 0 {
+            return Err(FS_ERR_INVALID);
+        }
+
+        let path = self.resolve(path)?;
+        let handle = if path.is_dir() {
+            if flags != FS_READ {
+                return Err(FS_ERR_INVALID);
+            }
+            let mut names = Vec::new();
+            for entry in fs::read_dir(&path).map_err(|e| error_code(&e))? {
+                let entry = entry.map_err(|e| error_code(&e))?;
+                // Names that are not valid UTF-8 cannot be opened by the guest anyway.
+                if let Ok(name) = entry.file_name().into_string() {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            Handle::Dir(names, 0)
+        } else {
+            let file = OpenOptions::new()
+                .read(flags & FS_READ != 0)
+                .write(flags & FS_WRITE != 0)
+                .create(flags & FS_CREATE != 0)
+                .truncate(flags & FS_TRUNCATE != 0)
+                .append(flags & FS_APPEND != 0)
+                .open(&path)
+                .map_err(|e| error_code(&e))?;
+            Handle::File(file)
+        };
+
+        self.add_handle(handle)
+    }
+
+    pub fn read(&mut self, handle: u32, buf: &mut [u8]) -> FsResult<usize> {
+        self.file(handle)?.read(buf).map_err(|e| error_code(&e))
+    }
+
+    pub fn write(&mut self, handle: u32, buf: &[u8]) -> FsResult<usize> {
+        self.file(handle)?.write(buf).map_err(|e| error_code(&e))
+    }
+
+    pub fn seek(&mut self, handle: u32, offset: i64, whence: u32) -> FsResult<u64> {
+        let pos = match whence {
+            FS_SEEK_START if offset >= 0 => SeekFrom::Start(offset as u64),
+            FS_SEEK_CURRENT => SeekFrom::Current(offset),
+            FS_SEEK_END => SeekFrom::End(offset),
+            _ => return Err(FS_ERR_INVALID)
+        };
+        self.file(handle)?.seek(pos).map_err(|e| error_code(&e))
+    }
+
+    pub fn close(&mut self, handle: u32) -> FsResult<()> {
+        match self.handles.remove(&handle) {
+            Some(_) => Ok(()),
+            None => Err(FS_ERR_BAD_HANDLE)
+        }
+    }
+
+    // Returns the size and the kind.
+    pub fn stat(&self, path: &str) -> FsResult<(u64, u32)> {
+        let meta = fs::metadata(self.resolve(path)?).map_err(|e| error_code(&e))?;
+        let kind = if meta.is_file() {
+            FS_KIND_FILE
+        } else if meta.is_dir() {
+            FS_KIND_DIR
+        } else {
+            FS_KIND_OTHER
+        };
+        Ok((meta.len(), kind))
+    }
+
+    // Returns the name of the next entry, or `None` at the end. The directory only
+    // advances if `advance` returns true for the name.
+    pub fn readdir<F: FnOnce(&str) -> bool>(&mut self, handle: u32, advance: F) -> FsResult<Option<usize>> {
+        match self.handles.get_mut(&handle) {
+            Some(Handle::Dir(names, pos)) => {
+                let name = match names.get(*pos) {
+                    Some(v) => v,
+                    None => return Ok(None)
+                };
+                if advance(name) {
+                    *pos += 1;
+                }
+                Ok(Some(name.len()))
+            },
+            _ => Err(FS_ERR_BAD_HANDLE)
+        }
+    }
+}
+
+pub fn is_fs_syscall(id: usize) -> bool {
+    (SYSCALL_FS_OPEN..=SYSCALL_FS_READDIR).contains(&id)
+}
+
+pub fn signature(id: usize) -> Option<NativeSignature> {
+    match id {
+        SYSCALL_FS_CLOSE => Some(NativeSignature { n_args: 1, n_results: 1 }),
+        _ if is_fs_syscall(id) => Some(NativeSignature { n_args: 3, n_results: 1 }),
+        _ => None
+    }
+}
+
+fn to_result<T: Into<i64>>(r: FsResult<T>) -> ExecuteResult<Option<i64>> {
+    Ok(Some(match r {
+        Ok(v) => v.into(),
+        Err(e) => e
+    }))
+}
+
+fn sandbox<E: SyscallEnvironment>(env: &mut E) -> ExecuteResult<&mut FsSandbox> {
+    env.fs().ok_or(ExecuteError::NotSupported)
+}
+
+fn pop_args<E: SyscallEnvironment>(env: &E, n: usize) -> ExecuteResult<()> {
+    env.get_stack().prev_many(n).map(|_| ())
+}
+
+fn read_path<E: SyscallEnvironment>(env: &E, base: usize, len: usize) -> ExecuteResult<String> {
+    let path = str::from_utf8(read_memory(env, base, len)?)
+        .map_err(|_| ExecuteError::InvalidInput)?;
+    Ok(String::from(path))
+}
+
+// Arguments are popped with the last one on top, once the sandbox, paths and buffers
+// have been checked, so that a call failing with an `ExecuteError` leaves the operand
+// stack and linear memory as they were. Results are an `i64` that is negative on failure:
+// - open(path_base, path_len, flags) -> handle
+// - read(handle, buf_base, buf_len) -> number of bytes read, 0 at the end of the file
+// - write(handle, buf_base, buf_len) -> number of bytes written
+// - seek(handle, offset: i64, whence) -> new position
+// - close(handle) -> 0
+// - stat(path_base, path_len, stat_base) -> 0, with `FS_STAT_SIZE` bytes written to `stat_base`
+// - readdir(handle, buf_base, buf_len) -> length of the next entry name, 0 at the end. The
+//   name is written and the directory advances only if it fits in the buffer.
+pub fn dispatch<E: SyscallEnvironment>(env: &mut E, id: usize) -> ExecuteResult<Option<i64>> {
+    let n_args = signature(id).ok_or(ExecuteError::InvalidNativeInvoke)?.n_args;
+    let (a, b, c) = {
+        let args = env.get_stack().tail_many(n_args)?;
+        if n_args == 1 {
+            (args[0].get(), 0, 0)
+        } else {
+            (args[0].get(), args[1].get(), args[2].get())
+        }
+    };
+    sandbox(env)?;
+
+    match id {
+        SYSCALL_FS_OPEN => {
+            let path = read_path(env, a as u32 as usize, b as u32 as usize)?;
+            pop_args(env, n_args)?;
+            to_result(sandbox(env)?.open(&path, c as u32))
+        },
+        SYSCALL_FS_READ => {
+            let (base, len) = (b as u32 as usize, c as u32 as usize);
+            write_memory(env, base, len)?;
+            pop_args(env, n_args)?;
+
+            let mut buf = vec![0; len];
+            let r = sandbox(env)?.read(a as u32, &mut buf);
+            if let Ok(n) = r {
+                write_memory(env, base, n)?.copy_from_slice(&buf[..n]);
+            }
+            to_result(r.map(|n| n as i64))
+        },
+        SYSCALL_FS_WRITE => {
+            let buf = read_memory(env, b as u32 as usize, c as u32 as usize)?.to_vec();
+            pop_args(env, n_args)?;
+            to_result(sandbox(env)?.write(a as u32, &buf).map(|n| n as i64))
+        },
+        SYSCALL_FS_SEEK => {
+            pop_args(env, n_args)?;
+            to_result(sandbox(env)?.seek(a as u32, b, c as u32).map(|n| n as i64))
+        },
+        SYSCALL_FS_CLOSE => {
+            pop_args(env, n_args)?;
+            to_result(sandbox(env)?.close(a as u32).map(|_| 0))
+        },
+        SYSCALL_FS_STAT => {
+            let path = read_path(env, a as u32 as usize, b as u32 as usize)?;
+            let stat_base = c as u32 as usize;
+            write_memory(env, stat_base, FS_STAT_SIZE)?;
+            pop_args(env, n_args)?;
+
+            let r = sandbox(env)?.stat(&path);
+            if let Ok((size, kind)) = r {
+                let out = write_memory(env, stat_base, FS_STAT_SIZE)?;
+                out[0..8].copy_from_slice(&size.to_le_bytes());
+                out[8..12].copy_from_slice(&kind.to_le_bytes());
+                out[12..16].copy_from_slice(&[0; 4]);
+            }
+            to_result(r.map(|_| 0))
+        },
+        SYSCALL_FS_READDIR => {
+            let (base, len) = (b as u32 as usize, c as u32 as usize);
+            write_memory(env, base, len)?;
+            pop_args(env, n_args)?;
+
+            let mut name = Vec::new();
+            let r = sandbox(env)?.readdir(a as u32, |v| {
+                if v.len() <= len {
+                    name.extend_from_slice(v.as_bytes());
+                    true
+                } else {
+                    false
+                }
+            });
+            write_memory(env, base, name.len())?.copy_from_slice(&name);
+            to_result(r.map(|n| n.unwrap_or(0) as i64))
+        },
+        _ => Err(ExecuteError::InvalidNativeInvoke)
+    }
+}
@@ -0,0 +1,458 @@
+// Linear memory backed by a reservation that covers every address a load or store can
+// form (u32 address + u32 offset + access width). Only the first `len` bytes are
+// accessible; touching the rest faults.
+//
+// Every load and store is a single unaligned access, inlined into its caller. When one
+// faults inside a reservation, the SIGSEGV handler resumes execution at the code that
+// fails the access with `ExecuteError::Bounds`. A faulting x86-64 store writes none of
+// its bytes, so a store that would cross `len` leaves memory untouched.
+
+use core::{mem, ptr, slice};
+use core::arch::{asm, global_asm};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use libc;
+use error::*;
+
+const RESERVED_SIZE: usize = (1 << 33) + 65536;
+const MAX_MEMORY_SIZE: usize = 1 << 32;
+
+// Reservations are registered in a list of chunks that the signal handler walks without
+// locking. Chunks are allocated with `mmap` when the existing ones are full and are
+// never freed; region entries are reused.
+const REGIONS_PER_CHUNK: usize = 64;
+
+struct Region {
+    base: AtomicUsize // 0 if unused
+}
+
+struct RegionChunk {
+    regions: [Region; REGIONS_PER_CHUNK],
+    next: AtomicPtr<RegionChunk>
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const UNUSED_REGION: Region = Region {
+    base: AtomicUsize::new(0)
+};
+
+static FIRST_CHUNK: RegionChunk = RegionChunk {
+    regions: [UNUSED_REGION; REGIONS_PER_CHUNK],
+    next: AtomicPtr::new(ptr::null_mut())
+};
+
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+static HANDLER_STATE: AtomicUsize = AtomicUsize::new(0); // 0: none, 1: installing, 2: installed
+
+// Written once by `install_handler` before our handler is installed, and only read by
+// the handler afterwards.
+struct PrevAction(UnsafeCell<Option<libc::sigaction>>);
+
+unsafe impl Sync for PrevAction {}
+
+static PREV_ACTION: PrevAction = PrevAction(UnsafeCell::new(None));
+
+// Each guarded access records its address and the code that reports its fault in
+// `hexagon_e_guard_fixups`, where the SIGSEGV handler looks up the faulting PC.
+#[repr(C)]
+struct Fixup {
+    pc: usize,
+    landing: usize
+}
+
+extern "C" {
+    static __start_hexagon_e_guard_fixups: Fixup;
+    static __stop_hexagon_e_guard_fixups: Fixup;
+}
+
+// Keeps the section, and so the symbols above, around when nothing accesses guarded memory.
+global_asm!(
+    ".pushsection hexagon_e_guard_fixups,\"awR\",@progbits",
+    ".popsection"
+);
+
+macro_rules! guarded {
+    ($insn:expr, $($operands:tt)*) => {
+        asm!(
+            concat!("2: ", $insn),
+            ".pushsection hexagon_e_guard_fixups,\"awR\",@progbits",
+            ".balign 8",
+            ".quad 2b, {fault}",
+            ".popsection",
+            $($operands)*
+        )
+    }
+}
+
+pub struct GuardedMemory {
+    base: *mut u8,
+    len: usize,
+    region: &'static Region
+}
+
+unsafe impl Send for GuardedMemory {}
+
+impl GuardedMemory {
+    // `len` is rounded up to whole pages.
+    pub fn new(len: usize) -> ExecuteResult<GuardedMemory> {
+        install_handler()?;
+
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                RESERVED_SIZE,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+                -1,
+                0
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(ExecuteError::MemoryLimit);
+        }
+
+        let region = match register_region(base as usize) {
+            Some(v) => v,
+            None => {
+                unsafe { libc::munmap(base, RESERVED_SIZE); }
+                return Err(ExecuteError::MemoryLimit);
+            }
+        };
+
+        let mut mem = GuardedMemory {
+            base: base as *mut u8,
+            len: 0,
+            region: region
+        };
+        mem.grow(len)?;
+
+        Ok(mem)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.base, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.base, self.len) }
+    }
+
+    // Grows by `len_inc` bytes rounded up to whole pages.
+    pub fn grow(&mut self, len_inc: usize) -> ExecuteResult<()> {
+        let page_size = PAGE_SIZE.load(Ordering::Relaxed);
+
+        let new_len = match self.len.checked_add(len_inc) {
+            Some(v) if v <= MAX_MEMORY_SIZE => (v + page_size - 1) & !(page_size - 1),
+            _ => return Err(ExecuteError::MemoryLimit)
+        };
+        if new_len == self.len {
+            return Ok(());
+        }
+
+        let ret = unsafe {
+            libc::mprotect(
+                self.base.add(self.len) as *mut libc::c_void,
+                new_len - self.len,
+                libc::PROT_READ | libc::PROT_WRITE
+            )
+        };
+        if ret != 0 {
+            return Err(ExecuteError::MemoryLimit);
+        }
+
+        self.len = new_len;
+        Ok(())
+    }
+
+    // No bounds checks: every `ra` a u32 address plus a u32 offset can form lies
+    // inside the reservation.
+    #[inline]
+    pub fn read_u8(&self, ra: usize) -> ExecuteResult<u8> {
+        let v: u32;
+        unsafe {
+            guarded!(
+                "movzx {v:e}, byte ptr [{p}]",
+                p = in(reg) self.base.add(ra),
+                v = lateout(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, readonly, preserves_flags)
+            );
+        }
+        Ok(v as u8)
+    }
+
+    #[inline]
+    pub fn read_u16(&self, ra: usize) -> ExecuteResult<u16> {
+        let v: u32;
+        unsafe {
+            guarded!(
+                "movzx {v:e}, word ptr [{p}]",
+                p = in(reg) self.base.add(ra),
+                v = lateout(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, readonly, preserves_flags)
+            );
+        }
+        Ok(v as u16)
+    }
+
+    #[inline]
+    pub fn read_u32(&self, ra: usize) -> ExecuteResult<u32> {
+        let v: u32;
+        unsafe {
+            guarded!(
+                "mov {v:e}, dword ptr [{p}]",
+                p = in(reg) self.base.add(ra),
+                v = lateout(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, readonly, preserves_flags)
+            );
+        }
+        Ok(v)
+    }
+
+    #[inline]
+    pub fn read_u64(&self, ra: usize) -> ExecuteResult<u64> {
+        let v: u64;
+        unsafe {
+            guarded!(
+                "mov {v}, qword ptr [{p}]",
+                p = in(reg) self.base.add(ra),
+                v = lateout(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, readonly, preserves_flags)
+            );
+        }
+        Ok(v)
+    }
+
+    #[inline]
+    pub fn write_u8(&mut self, ra: usize, v: u8) -> ExecuteResult<()> {
+        unsafe {
+            guarded!(
+                "mov byte ptr [{p}], {v}",
+                p = in(reg) self.base.add(ra),
+                v = in(reg_byte) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, preserves_flags)
+            );
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn write_u16(&mut self, ra: usize, v: u16) -> ExecuteResult<()> {
+        unsafe {
+            guarded!(
+                "mov word ptr [{p}], {v:x}",
+                p = in(reg) self.base.add(ra),
+                v = in(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, preserves_flags)
+            );
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn write_u32(&mut self, ra: usize, v: u32) -> ExecuteResult<()> {
+        unsafe {
+            guarded!(
+                "mov dword ptr [{p}], {v:e}",
+                p = in(reg) self.base.add(ra),
+                v = in(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, preserves_flags)
+            );
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn write_u64(&mut self, ra: usize, v: u64) -> ExecuteResult<()> {
+        unsafe {
+            guarded!(
+                "mov qword ptr [{p}], {v}",
+                p = in(reg) self.base.add(ra),
+                v = in(reg) v,
+                fault = label { return Err(ExecuteError::Bounds); },
+                options(nostack, preserves_flags)
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for GuardedMemory {
+    fn drop(&mut self) {
+        // Unregistered first, so that the handler never claims a mapping that reuses the
+        // range once it is unmapped.
+        self.region.base.store(0, Ordering::SeqCst);
+        unsafe { libc::munmap(self.base as *mut libc::c_void, RESERVED_SIZE); }
+    }
+}
+
+// Visits the chunks in order. Safe to call from the signal handler.
+fn for_each_chunk<F: FnMut(&'static RegionChunk) -> bool>(mut f: F) {
+    let mut chunk: &'static RegionChunk = &FIRST_CHUNK;
+    while f(chunk) {
+        let next = chunk.next.load(Ordering::Acquire);
+        if next.is_null() {
+            return;
+        }
+        chunk = unsafe { &*next };
+    }
+}
+
+fn register_region(base: usize) -> Option<&'static Region> {
+    let mut chunk: &'static RegionChunk = &FIRST_CHUNK;
+    loop {
+        for region in chunk.regions.iter() {
+            if region.base.compare_exchange(0, base, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return Some(region);
+            }
+        }
+
+        let mut next = chunk.next.load(Ordering::Acquire);
+        if next.is_null() {
+            let new_chunk = alloc_chunk()?;
+            match chunk.next.compare_exchange(ptr::null_mut(), new_chunk, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => next = new_chunk,
+                Err(other) => {
+                    unsafe { libc::munmap(new_chunk as *mut libc::c_void, mem::size_of::<RegionChunk>()); }
+                    next = other;
+                }
+            }
+        }
+        chunk = unsafe { &*next };
+    }
+}
+
+fn alloc_chunk() -> Option<*mut RegionChunk> {
+    // Anonymous mappings are zeroed, which is an empty chunk.
+    let p = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            mem::size_of::<RegionChunk>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0
+        )
+    };
+    if p == libc::MAP_FAILED {
+        None
+    } else {
+        Some(p as *mut RegionChunk)
+    }
+}
+
+fn install_handler() -> ExecuteResult<()> {
+    loop {
+        match HANDLER_STATE.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => break,
+            Err(2) => return Ok(()),
+            Err(_) => continue
+        }
+    }
+
+    unsafe {
+        let page_size = libc::sysconf(libc::_SC_PAGESIZE);
+        if page_size <= 0 {
+            HANDLER_STATE.store(0, Ordering::SeqCst);
+            return Err(ExecuteError::Generic);
+        }
+        PAGE_SIZE.store(page_size as usize, Ordering::SeqCst);
+
+        let mut prev: libc::sigaction = mem::zeroed();
+        if libc::sigaction(libc::SIGSEGV, ptr::null(), &mut prev) != 0 {
+            HANDLER_STATE.store(0, Ordering::SeqCst);
+            return Err(ExecuteError::Generic);
+        }
+        *PREV_ACTION.0.get() = Some(prev);
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_segv as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_NODEFER;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(libc::SIGSEGV, &action, ptr::null_mut()) != 0 {
+            HANDLER_STATE.store(0, Ordering::SeqCst);
+            return Err(ExecuteError::Generic);
+        }
+    }
+
+    HANDLER_STATE.store(2, Ordering::SeqCst);
+    Ok(())
+}
+
+// Whether `addr` lies in a registered reservation. Safe to call from the signal handler.
+fn is_reserved(addr: usize) -> bool {
+    let mut found = false;
+    for_each_chunk(|chunk| {
+        found = chunk.regions.iter().any(|region| {
+            let base = region.base.load(Ordering::SeqCst);
+            base != 0 && addr >= base && addr < base + RESERVED_SIZE
+        });
+        !found
+    });
+    found
+}
+
+// Where the guarded access at `pc` continues when it faults, if `pc` is one.
+fn fixup_landing(pc: usize) -> Option<usize> {
+    let mut fixup = ptr::addr_of!(__start_hexagon_e_guard_fixups);
+    let end = ptr::addr_of!(__stop_hexagon_e_guard_fixups);
+    while fixup < end {
+        let f = unsafe { &*fixup };
+        if f.pc == pc {
+            return Some(f.landing);
+        }
+        fixup = fixup.wrapping_add(1);
+    }
+    None
+}
+
+extern "C" fn handle_segv(sig: libc::c_int, info: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    let addr = unsafe { (*info).si_addr() } as usize;
+    let gregs = unsafe { &mut (*(ctx as *mut libc::ucontext_t)).uc_mcontext.gregs };
+    let pc = gregs[libc::REG_RIP as usize] as usize;
+
+    if is_reserved(addr) {
+        if let Some(landing) = fixup_landing(pc) {
+            gregs[libc::REG_RIP as usize] = landing as libc::greg_t;
+            return;
+        }
+    }
+
+    // Not ours. Hand it over to whoever was installed before us.
+    unsafe {
+        match *PREV_ACTION.0.get() {
+            Some(ref prev) if prev.sa_sigaction != libc::SIG_DFL && prev.sa_sigaction != libc::SIG_IGN => {
+                if prev.sa_flags & libc::SA_SIGINFO != 0 {
+                    let f: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                        mem::transmute(prev.sa_sigaction);
+                    f(sig, info, ctx);
+                } else {
+                    let f: extern "C" fn(libc::c_int) = mem::transmute(prev.sa_sigaction);
+                    f(sig);
+                }
+            },
+            _ => {
+                // Returning re-executes the faulting access, which now gets the default action.
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = libc::SIG_DFL;
+                libc::sigaction(libc::SIGSEGV, &action, ptr::null_mut());
+            }
+        }
+    }
+}
@@ -0,0 +1,236 @@
+// Environment middleware. A `Layer` wraps an environment and forwards every method to
+// it, except for the ones that `Hooks` can override: memory and slot growth, native
+// invocation and tracing. Layers wrap each other, so a tracer, a fuel meter and a syscall
+// handler can be stacked on top of any base environment:
+//
+//     Layer::new(Layer::new(Layer::new(base, syscalls), fuel), tracer)
+//
+// The outermost layer's hooks run first and decide whether to forward inwards. Layers
+// also forward `SyscallEnvironment` and `WasiEnvironment`, so hooks can dispatch syscalls
+// on an inner layer.
+
+use core::cell::Cell;
+use environment::Environment;
+use module::{Opcode, TableEntry};
+use mmio::MmioRegion;
+use syscall::{Clock, LogLevel, RandomSource, SyscallEnvironment};
+use wasi::{Errno, WasiEnvironment};
+use tape::Tape;
+use vm::Limits;
+use error::*;
+#[cfg(feature = "std")]
+use fs_syscall::FsSandbox;
+#[cfg(feature = "guard-memory")]
+use guard_memory::GuardedMemory;
+#[cfg(feature = "shared-memory")]
+use shared_memory::SharedMemory;
+
+// Each hook receives the wrapped environment and forwards to it by default.
+pub trait Hooks<E: Environment> {
+    // Whether the `trace_*` hooks look at or change the operand stack, as
+    // `Environment::TRACE_STACK`. Hooks that never do can clear it; a layer only traces
+    // the stack if its hooks or the wrapped environment do.
+    const TRACE_STACK: bool = true;
+
+    fn grow_memory(&mut self, env: &mut E, len_inc: usize) -> ExecuteResult<()> {
+        env.grow_memory(len_inc)
+    }
+
+    fn reset_slots(&mut self, env: &mut E, len: usize) -> ExecuteResult<()> {
+        env.reset_slots(len)
+    }
+
+    fn do_native_invoke(&mut self, env: &mut E, id: usize) -> ExecuteResult<Option<i64>> {
+        env.do_native_invoke(id)
+    }
+
+    fn trace_mem_init(&self, env: &E, start: usize, data: &[u8]) {
+        env.trace_mem_init(start, data)
+    }
+
+    fn trace_opcode(&self, env: &E, op: &Opcode) -> ExecuteResult<()> {
+        env.trace_opcode(op)
+    }
+
+    fn trace_call(&self, env: &E, target: usize, n_locals: usize) {
+        env.trace_call(target, n_locals)
+    }
+
+    fn trace_load(&self, env: &E, offset: usize, addr: usize, val: u64) {
+        env.trace_load(offset, addr, val)
+    }
+
+    fn trace_branch(&self, env: &E, target: usize) -> ExecuteResult<()> {
+        env.trace_branch(target)
+    }
+}
+
+pub struct Layer<E: Environment, H: Hooks<E>> {
+    pub inner: E,
+    pub hooks: H
+}
+
+impl<E: Environment, H: Hooks<E>> Layer<E, H> {
+    pub fn new(inner: E, hooks: H) -> Layer<E, H> {
+        Layer {
+            inner: inner,
+            hooks: hooks
+        }
+    }
+
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: Environment, H: Hooks<E>> Environment for Layer<E, H> {
+    fn get_memory(&self) -> &[u8] {
+        self.inner.get_memory()
+    }
+
+    fn get_memory_mut(&mut self) -> &mut [u8] {
+        self.inner.get_memory_mut()
+    }
+
+    fn grow_memory(&mut self, len_inc: usize) -> ExecuteResult<()> {
+        self.hooks.grow_memory(&mut self.inner, len_inc)
+    }
+
+    #[cfg(feature = "guard-memory")]
+    fn get_guarded_memory(&self) -> Option<&GuardedMemory> {
+        self.inner.get_guarded_memory()
+    }
+
+    #[cfg(feature = "guard-memory")]
+    fn get_guarded_memory_mut(&mut self) -> Option<&mut GuardedMemory> {
+        self.inner.get_guarded_memory_mut()
+    }
+
+    #[cfg(feature = "shared-memory")]
+    fn get_shared_memory(&self) -> Option<SharedMemory<'_>> {
+        self.inner.get_shared_memory()
+    }
+
+    fn atomic_wait(&mut self, addr: usize, expected: u64, width: usize, timeout_ns: i64) -> ExecuteResult<u32> {
+        self.inner.atomic_wait(addr, expected, width, timeout_ns)
+    }
+
+    fn atomic_notify(&mut self, addr: usize, count: u32) -> ExecuteResult<u32> {
+        self.inner.atomic_notify(addr, count)
+    }
+
+    fn get_mmio_regions(&self) -> &[MmioRegion] {
+        self.inner.get_mmio_regions()
+    }
+
+    fn mmio_read(&mut self, region: MmioRegion, offset: usize, width: usize) -> ExecuteResult<u64> {
+        self.inner.mmio_read(region, offset, width)
+    }
+
+    fn mmio_write(&mut self, region: MmioRegion, offset: usize, width: usize, val: u64) -> ExecuteResult<()> {
+        self.inner.mmio_write(region, offset, width, val)
+    }
+
+    fn get_limits(&self) -> Limits {
+        self.inner.get_limits()
+    }
+
+    fn get_slots(&self) -> &[i64] {
+        self.inner.get_slots()
+    }
+
+    fn get_slots_mut(&mut self) -> &mut [i64] {
+        self.inner.get_slots_mut()
+    }
+
+    fn reset_slots(&mut self, len: usize) -> ExecuteResult<()> {
+        self.hooks.reset_slots(&mut self.inner, len)
+    }
+
+    fn get_table(&self) -> &[Option<TableEntry>] {
+        self.inner.get_table()
+    }
+
+    fn get_table_mut(&mut self) -> &mut [Option<TableEntry>] {
+        self.inner.get_table_mut()
+    }
+
+    fn get_stack(&self) -> &Tape<Cell<i64>> {
+        self.inner.get_stack()
+    }
+
+    fn get_call_stack(&self) -> &Tape<Cell<i64>> {
+        self.inner.get_call_stack()
+    }
+
+    fn get_frame_stack(&self) -> Option<&Tape<Cell<i64>>> {
+        self.inner.get_frame_stack()
+    }
+
+    fn do_native_invoke(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        self.hooks.do_native_invoke(&mut self.inner, id)
+    }
+
+    const TRACE_STACK: bool = E::TRACE_STACK || H::TRACE_STACK;
+
+    fn trace_mem_init(&self, start: usize, data: &[u8]) {
+        self.hooks.trace_mem_init(&self.inner, start, data)
+    }
+
+    fn trace_opcode(&self, op: &Opcode) -> ExecuteResult<()> {
+        self.hooks.trace_opcode(&self.inner, op)
+    }
+
+    fn trace_call(&self, target: usize, n_locals: usize) {
+        self.hooks.trace_call(&self.inner, target, n_locals)
+    }
+
+    fn trace_load(&self, offset: usize, addr: usize, val: u64) {
+        self.hooks.trace_load(&self.inner, offset, addr, val)
+    }
+
+    fn trace_branch(&self, target: usize) -> ExecuteResult<()> {
+        self.hooks.trace_branch(&self.inner, target)
+    }
+}
+
+impl<E: SyscallEnvironment, H: Hooks<E>> SyscallEnvironment for Layer<E, H> {
+    fn log(&self, level: LogLevel, text: &str) {
+        self.inner.log(level, text)
+    }
+
+    fn clock(&mut self) -> Option<&mut dyn Clock> {
+        self.inner.clock()
+    }
+
+    fn random(&mut self) -> Option<&mut dyn RandomSource> {
+        self.inner.random()
+    }
+
+    #[cfg(feature = "std")]
+    fn fs(&mut self) -> Option<&mut FsSandbox> {
+        self.inner.fs()
+    }
+
+    fn user_syscall(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        self.inner.user_syscall(id)
+    }
+}
+
+impl<E: WasiEnvironment, H: Hooks<E>> WasiEnvironment for Layer<E, H> {
+    fn wasi_arg(&self, index: usize) -> Option<&[u8]> {
+        self.inner.wasi_arg(index)
+    }
+
+    fn wasi_env_var(&self, index: usize) -> Option<&[u8]> {
+        self.inner.wasi_env_var(index)
+    }
+
+    fn wasi_read(&mut self, fd: u32, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.inner.wasi_read(fd, buf)
+    }
+
+    fn wasi_write(&mut self, fd: u32, buf: &[u8]) -> Result<usize, Errno> {
+        self.inner.wasi_write(fd, buf)
+    }
+}
@@ -1,10 +1,35 @@
 #![no_std]
 #![feature(core_intrinsics)]
+#![cfg_attr(feature = "guard-memory", feature(asm_goto_with_outputs))]
 
+#[cfg(feature = "std")]
+extern crate std;
 extern crate byteorder;
+#[cfg(feature = "guard-memory")]
+extern crate libc;
+
+#[cfg(all(feature = "guard-memory", not(all(target_os = "linux", target_arch = "x86_64"))))]
+compile_error!("The `guard-memory` feature is only supported on x86-64 Linux");
 
 pub mod module;
 pub mod environment;
 pub mod vm;
+pub mod verifier;
 pub mod error;
 pub mod tape;
+pub mod shared_memory;
+pub mod fixed_environment;
+pub mod layer;
+pub mod mmio;
+pub mod syscall;
+pub mod wasi;
+#[cfg(feature = "simd")]
+pub mod simd;
+#[cfg(feature = "guard-memory")]
+pub mod guard_memory;
+#[cfg(feature = "std")]
+pub mod native;
+#[cfg(feature = "std")]
+pub mod std_environment;
+#[cfg(feature = "std")]
+pub mod fs_syscall;
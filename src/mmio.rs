@@ -0,0 +1,82 @@
+// Memory-mapped I/O. Environments can map address ranges of linear memory to host
+// handlers through `Environment::get_mmio_regions`, `mmio_read` and `mmio_write`.
+//
+// Plain loads and stores (`I32Load`, `I64Store8`, `F64Load`, etc.) call the handlers with
+// their width, and `V128Load` / `V128Store` with one 8-byte access per half, the high
+// half first. Bulk memory and atomic operations that touch a region fail with
+// `ExecuteError::MmioAccess` instead, before accessing anything. Syscalls access the
+// bytes underneath.
+
+use error::*;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MmioRegion {
+    pub start: usize,
+    pub len: usize,
+    pub id: usize // chosen by the environment, e.g. to identify the device
+}
+
+impl MmioRegion {
+    pub fn new(start: usize, len: usize, id: usize) -> MmioRegion {
+        MmioRegion {
+            start: start,
+            len: len,
+            id: id
+        }
+    }
+
+    // `None` if the region wraps around the address space.
+    pub fn end(&self) -> Option<usize> {
+        self.start.checked_add(self.len)
+    }
+
+    pub fn overlaps(&self, start: usize, len: usize) -> bool {
+        match (self.end(), start.checked_add(len)) {
+            (Some(end), Some(other_end)) => self.start < other_end && start < end,
+            _ => true
+        }
+    }
+}
+
+// A host handler for a region. `offset` is relative to the start of the region and
+// `width` is 1, 2, 4 or 8 bytes. Loads of narrower values only use the low bits of the
+// result, and stores pass the value zero-extended.
+pub trait MmioDevice {
+    fn read(&mut self, offset: usize, width: usize) -> ExecuteResult<u64>;
+    fn write(&mut self, offset: usize, width: usize, val: u64) -> ExecuteResult<()>;
+}
+
+// Returns the region an access of `width` bytes at `addr` falls into. Accesses that are
+// only partially within a region fail with `ExecuteError::Bounds`. Regions are searched
+// in order.
+pub fn find_region(regions: &[MmioRegion], addr: usize, width: usize) -> ExecuteResult<Option<MmioRegion>> {
+    for r in regions {
+        if r.overlaps(addr, width) {
+            return match (r.end(), addr.checked_add(width)) {
+                (Some(end), Some(access_end)) if addr >= r.start && access_end <= end => Ok(Some(*r)),
+                _ => Err(ExecuteError::Bounds)
+            };
+        }
+    }
+    Ok(None)
+}
+
+// Whether any of `len` bytes at `addr` is within a region.
+pub fn overlaps_any(regions: &[MmioRegion], addr: usize, len: usize) -> bool {
+    len > 0 && regions.iter().any(|r| r.overlaps(addr, len))
+}
+
+// Fails with `ExecuteError::InvalidInput` if regions overlap or wrap around.
+pub fn check_regions(regions: &[MmioRegion]) -> ExecuteResult<()> {
+    for (i, r) in regions.iter().enumerate() {
+        if r.end().is_none() {
+            return Err(ExecuteError::InvalidInput);
+        }
+        for other in &regions[..i] {
+            if other.overlaps(r.start, r.len) {
+                return Err(ExecuteError::InvalidInput);
+            }
+        }
+    }
+    Ok(())
+}
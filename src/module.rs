@@ -4,30 +4,144 @@ use byteorder::{LittleEndian, ByteOrder};
 #[derive(Copy, Clone, Debug)]
 pub struct Module<'a> {
     pub memory_initializers: &'a [u8], // Serialized
+    pub data_segments: &'a [u8], // Serialized; copied into memory by `MemoryInit` only
+    pub table: &'a [u8], // Serialized; initial function table for `CallIndirect`
+    pub handlers: &'a [u8], // Serialized; exception handlers for `Throw`
     pub code: &'a [u8] // Raw opcodes & immediates
 }
 
+// A function that `CallIndirect` can call. `n_args` and `n_locals` must match the
+// immediates at the call site.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TableEntry {
+    pub target: u32,
+    pub n_args: u32,
+    pub n_locals: u32
+}
+
+// Catches `Throw`s with a matching tag raised within `start..end`, either directly
+// or in a function called from there. A `tag` of `None` catches any tag.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Handler {
+    pub start: u32,
+    pub end: u32,
+    pub target: u32,
+    pub tag: Option<u32>
+}
+
 impl<'a> Module<'a> {
     pub fn from_raw(mut s: &'a [u8]) -> ExecuteResult<Module<'a>> {
-        if s.len() < 4 {
-            return Err(ExecuteError::Bounds);
-        }
-        let initializers_len = LittleEndian::read_u32(s) as usize;
-        s = &s[4..];
+        let memory_initializers = take_section(&mut s)?;
+        let code = s;
 
-        if s.len() < initializers_len {
-            return Err(ExecuteError::Bounds);
-        }
-        let memory_initializers = &s[0..initializers_len];
-        s = &s[initializers_len..];
+        Ok(Module {
+            memory_initializers: memory_initializers,
+            data_segments: &[],
+            table: &[],
+            handlers: &[],
+            code: code
+        })
+    }
 
+    // Same as `from_raw`, with length-prefixed data segment, table and handler
+    // sections between the memory initializers and the code.
+    pub fn from_raw_extended(mut s: &'a [u8]) -> ExecuteResult<Module<'a>> {
+        let memory_initializers = take_section(&mut s)?;
+        let data_segments = take_section(&mut s)?;
+        let table = take_section(&mut s)?;
+        let handlers = take_section(&mut s)?;
         let code = s;
 
         Ok(Module {
             memory_initializers: memory_initializers,
+            data_segments: data_segments,
+            table: table,
+            handlers: handlers,
             code: code
         })
     }
+
+    // Each data segment is serialized as a u32 length followed by the data.
+    pub fn data_segment(&self, id: usize) -> ExecuteResult<&'a [u8]> {
+        let mut s = self.data_segments;
+        for _ in 0..id {
+            take_section(&mut s)?;
+        }
+        take_section(&mut s)
+    }
+
+    pub fn table_len(&self) -> usize {
+        self.table.len() / 12
+    }
+
+    // Each table entry is serialized as three u32s: target, n_args and n_locals.
+    // A target of 0xffffffff denotes a null entry.
+    pub fn table_entry(&self, id: usize) -> ExecuteResult<Option<TableEntry>> {
+        if id >= self.table_len() {
+            return Err(ExecuteError::Bounds);
+        }
+
+        let raw = &self.table[id * 12 .. id * 12 + 12];
+        let target = LittleEndian::read_u32(raw);
+        if target == 0xffffffff {
+            return Ok(None);
+        }
+
+        Ok(Some(TableEntry {
+            target: target,
+            n_args: LittleEndian::read_u32(&raw[4..]),
+            n_locals: LittleEndian::read_u32(&raw[8..])
+        }))
+    }
+
+    pub fn handlers_len(&self) -> usize {
+        self.handlers.len() / 16
+    }
+
+    // Each handler is serialized as four u32s: start, end, target and tag.
+    // A tag of 0xffffffff catches any tag.
+    pub fn handler(&self, id: usize) -> ExecuteResult<Handler> {
+        if id >= self.handlers_len() {
+            return Err(ExecuteError::Bounds);
+        }
+
+        let raw = &self.handlers[id * 16 .. id * 16 + 16];
+        let tag = LittleEndian::read_u32(&raw[12..]);
+
+        Ok(Handler {
+            start: LittleEndian::read_u32(raw),
+            end: LittleEndian::read_u32(&raw[4..]),
+            target: LittleEndian::read_u32(&raw[8..]),
+            tag: if tag == 0xffffffff { None } else { Some(tag) }
+        })
+    }
+
+    // Handlers are tried in order, so inner regions must come before outer ones.
+    pub fn find_handler(&self, ip: usize, tag: u32) -> ExecuteResult<Option<usize>> {
+        for i in 0..self.handlers_len() {
+            let h = self.handler(i)?;
+            if ip >= h.start as usize && ip < h.end as usize && h.tag.is_none_or(|t| t == tag) {
+                return Ok(Some(h.target as usize));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn take_section<'a>(s: &mut &'a [u8]) -> ExecuteResult<&'a [u8]> {
+    if s.len() < 4 {
+        return Err(ExecuteError::Bounds);
+    }
+    let section_len = LittleEndian::read_u32(s) as usize;
+    *s = &s[4..];
+
+    if s.len() < section_len {
+        return Err(ExecuteError::Bounds);
+    }
+    let section = &s[0..section_len];
+    *s = &s[section_len..];
+
+    Ok(section)
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -153,6 +267,166 @@ pub enum Opcode {
     I64ExtendI32U,
     I64ExtendI32S,
 
+    // Opcodes below are appended to keep the encoding of existing ones stable.
+    CallDirect,
+
+    // f32 and f64 values are kept as their bit patterns in the low 32 or 64 bits of a stack cell.
+    F32Load,
+    F32Store,
+    F32Const,
+    F32Abs,
+    F32Neg,
+    F32Ceil,
+    F32Floor,
+    F32Trunc,
+    F32Nearest,
+    F32Sqrt,
+    F32Add,
+    F32Sub,
+    F32Mul,
+    F32Div,
+    F32Min,
+    F32Max,
+    F32Copysign,
+    F32Eq,
+    F32Ne,
+    F32Lt,
+    F32Gt,
+    F32Le,
+    F32Ge,
+
+    F64Load,
+    F64Store,
+    F64Const,
+    F64Abs,
+    F64Neg,
+    F64Ceil,
+    F64Floor,
+    F64Trunc,
+    F64Nearest,
+    F64Sqrt,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Min,
+    F64Max,
+    F64Copysign,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+
+    I32TruncF32S,
+    I32TruncF32U,
+    I32TruncF64S,
+    I32TruncF64U,
+    I64TruncF32S,
+    I64TruncF32U,
+    I64TruncF64S,
+    I64TruncF64U,
+    F32ConvertI32S,
+    F32ConvertI32U,
+    F32ConvertI64S,
+    F32ConvertI64U,
+    F32DemoteF64,
+    F64ConvertI32S,
+    F64ConvertI32U,
+    F64ConvertI64S,
+    F64ConvertI64U,
+    F64PromoteF32,
+    I32ReinterpretF32,
+    I64ReinterpretF64,
+    F32ReinterpretI32,
+    F64ReinterpretI64,
+
+    MemCopy,
+    MemFill,
+    MemoryInit,
+
+    I32Extend8S,
+    I32Extend16S,
+    I64Extend8S,
+    I64Extend16S,
+    I64Extend32S,
+
+    CallIndirect,
+
+    I32AtomicLoad,
+    I64AtomicLoad,
+    I32AtomicStore,
+    I64AtomicStore,
+    I32AtomicRmwAdd,
+    I32AtomicRmwSub,
+    I32AtomicRmwAnd,
+    I32AtomicRmwOr,
+    I32AtomicRmwXor,
+    I32AtomicRmwXchg,
+    I32AtomicRmwCmpxchg,
+    I64AtomicRmwAdd,
+    I64AtomicRmwSub,
+    I64AtomicRmwAnd,
+    I64AtomicRmwOr,
+    I64AtomicRmwXor,
+    I64AtomicRmwXchg,
+    I64AtomicRmwCmpxchg,
+    MemoryAtomicWait32,
+    MemoryAtomicWait64,
+    MemoryAtomicNotify,
+    AtomicFence,
+
+    Simd, // Followed by a `simd::SimdOpcode`
+
+    // Trap with `ExecuteError::IntegerOverflow`
+    I32AddCheckedS,
+    I32AddCheckedU,
+    I32SubCheckedS,
+    I32SubCheckedU,
+    I32MulCheckedS,
+    I32MulCheckedU,
+    I64AddCheckedS,
+    I64AddCheckedU,
+    I64SubCheckedS,
+    I64SubCheckedU,
+    I64MulCheckedS,
+    I64MulCheckedU,
+
+    // Push the wrapped result, then 1 if it overflowed or 0 otherwise
+    I32AddOverflowingS,
+    I32AddOverflowingU,
+    I32SubOverflowingS,
+    I32SubOverflowingU,
+    I32MulOverflowingS,
+    I32MulOverflowingU,
+    I64AddOverflowingS,
+    I64AddOverflowingU,
+    I64SubOverflowingS,
+    I64SubOverflowingU,
+    I64MulOverflowingS,
+    I64MulOverflowingU,
+
+    // Push the low 64 bits, then the high 64 bits of the 128-bit product
+    I64MulWideS,
+    I64MulWideU,
+    // Pop a, b and an incoming carry / borrow (any non-zero value counts as 1),
+    // then push the wrapped result and the outgoing carry / borrow
+    I64AddWithCarry,
+    I64SubWithBorrow,
+
+    // Like `CallDirect` / `CallIndirect`, but replace the current frame instead of pushing
+    // a new one; the callee returns to the caller's return_ip. The arguments must be
+    // the only values on the caller's operand stack.
+    ReturnCall,
+    ReturnCallIndirect,
+
+    // Pop a payload and, below it, a tag (u32), then unwind to the first matching `Handler`.
+    // The handler starts with only the tag and the payload on its function's operand stack,
+    // or, in the function `run` was entered in, above what was there at entry. Only frames
+    // pushed by the current `run` are unwound.
+    Throw,
+
     Never
 }
 
@@ -0,0 +1,204 @@
+// Native functions with typed signatures.
+//
+// A closure such as `Fn(&mut C, i32, i64) -> i32` is turned into a `NativeFn<C>` that pops
+// its arguments from `C`'s operand stack (the last argument on top), converts them and
+// returns the converted result to `NativeInvoke`. Too few operands, or an operand that
+// does not fit the parameter type, fail with `ExecuteError::NativeArgumentMismatch`.
+//
+// 32-bit values are accepted both zero- and sign-extended and returned zero-extended,
+// as `I32Const` pushes them.
+
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use environment::Environment;
+use verifier::NativeSignature;
+use error::*;
+
+pub type NativeFn<C> = Rc<dyn Fn(&mut C) -> ExecuteResult<Option<i64>>>;
+
+pub trait NativeArg: Sized {
+    fn from_cell(v: i64) -> ExecuteResult<Self>;
+}
+
+pub trait NativeReturn {
+    const N_RESULTS: usize;
+
+    fn into_result(self) -> ExecuteResult<Option<i64>>;
+}
+
+fn check_32(v: i64) -> ExecuteResult<u32> {
+    if v as u64 >> 32 == 0 || v as i32 as i64 == v {
+        Ok(v as u32)
+    } else {
+        Err(ExecuteError::NativeArgumentMismatch)
+    }
+}
+
+impl NativeArg for i32 {
+    fn from_cell(v: i64) -> ExecuteResult<i32> {
+        Ok(check_32(v)? as i32)
+    }
+}
+
+impl NativeArg for u32 {
+    fn from_cell(v: i64) -> ExecuteResult<u32> {
+        check_32(v)
+    }
+}
+
+impl NativeArg for i64 {
+    fn from_cell(v: i64) -> ExecuteResult<i64> {
+        Ok(v)
+    }
+}
+
+impl NativeArg for u64 {
+    fn from_cell(v: i64) -> ExecuteResult<u64> {
+        Ok(v as u64)
+    }
+}
+
+impl NativeArg for f32 {
+    fn from_cell(v: i64) -> ExecuteResult<f32> {
+        Ok(f32::from_bits(check_32(v)?))
+    }
+}
+
+impl NativeArg for f64 {
+    fn from_cell(v: i64) -> ExecuteResult<f64> {
+        Ok(f64::from_bits(v as u64))
+    }
+}
+
+impl NativeArg for bool {
+    fn from_cell(v: i64) -> ExecuteResult<bool> {
+        match v {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ExecuteError::NativeArgumentMismatch)
+        }
+    }
+}
+
+macro_rules! impl_native_return {
+    ($t:ty, $to_cell:expr) => {
+        impl NativeReturn for $t {
+            const N_RESULTS: usize = 1;
+
+            fn into_result(self) -> ExecuteResult<Option<i64>> {
+                Ok(Some(($to_cell)(self)))
+            }
+        }
+    }
+}
+
+impl_native_return!(i32, |v: i32| v as u32 as i64);
+impl_native_return!(u32, |v: u32| v as i64);
+impl_native_return!(i64, |v: i64| v);
+impl_native_return!(u64, |v: u64| v as i64);
+impl_native_return!(f32, |v: f32| v.to_bits() as i64);
+impl_native_return!(f64, |v: f64| v.to_bits() as i64);
+impl_native_return!(bool, |v: bool| v as i64);
+
+impl NativeReturn for () {
+    const N_RESULTS: usize = 0;
+
+    fn into_result(self) -> ExecuteResult<Option<i64>> {
+        Ok(None)
+    }
+}
+
+impl<T: NativeReturn> NativeReturn for ExecuteResult<T> {
+    const N_RESULTS: usize = T::N_RESULTS;
+
+    fn into_result(self) -> ExecuteResult<Option<i64>> {
+        self?.into_result()
+    }
+}
+
+pub trait IntoNative<C, Args> {
+    fn signature() -> NativeSignature;
+    fn into_native(self) -> NativeFn<C>;
+}
+
+macro_rules! impl_into_native {
+    ($n:expr; $($t:ident $v:ident),*) => {
+        impl<C, F, R, $($t),*> IntoNative<C, ($($t,)*)> for F
+            where C: Environment, F: Fn(&mut C, $($t),*) -> R + 'static, R: NativeReturn, $($t: NativeArg),* {
+            fn signature() -> NativeSignature {
+                NativeSignature {
+                    n_args: $n,
+                    n_results: R::N_RESULTS
+                }
+            }
+
+            #[allow(unused_variables, unused_mut, clippy::unused_unit)]
+            fn into_native(self) -> NativeFn<C> {
+                Rc::new(move |ctx: &mut C| {
+                    let ($($v,)*) = {
+                        let args = ctx.get_stack().prev_many($n)
+                            .map_err(|_| ExecuteError::NativeArgumentMismatch)?;
+                        let mut args = args.iter();
+                        ($($t::from_cell(args.next().unwrap().get())?,)*)
+                    };
+                    self(ctx, $($v),*).into_result()
+                })
+            }
+        }
+    }
+}
+
+impl_into_native!(0;);
+impl_into_native!(1; A a);
+impl_into_native!(2; A a, B b);
+impl_into_native!(3; A a, B b, D d);
+impl_into_native!(4; A a, B b, D d, E e);
+impl_into_native!(5; A a, B b, D d, E e, G g);
+impl_into_native!(6; A a, B b, D d, E e, G g, H h);
+
+// Maps `NativeInvoke` ids to native functions.
+pub struct NativeRegistry<C> {
+    functions: BTreeMap<usize, (NativeFn<C>, Option<NativeSignature>)>
+}
+
+impl<C> Default for NativeRegistry<C> {
+    fn default() -> NativeRegistry<C> {
+        NativeRegistry {
+            functions: BTreeMap::new()
+        }
+    }
+}
+
+impl<C: Environment> NativeRegistry<C> {
+    pub fn new() -> NativeRegistry<C> {
+        NativeRegistry::default()
+    }
+
+    pub fn register<A, F: IntoNative<C, A>>(&mut self, id: usize, f: F) {
+        self.functions.insert(id, (f.into_native(), Some(F::signature())));
+    }
+
+    // Registers a function that pops its own arguments. Its signature is unknown
+    // to the verifier.
+    pub fn register_raw<F>(&mut self, id: usize, f: F)
+        where F: Fn(&mut C) -> ExecuteResult<Option<i64>> + 'static {
+        self.functions.insert(id, (Rc::new(f), None));
+    }
+
+    pub fn unregister(&mut self, id: usize) {
+        self.functions.remove(&id);
+    }
+
+    // For use as the `native_sig` argument of `verifier::verify`.
+    pub fn signature(&self, id: usize) -> Option<NativeSignature> {
+        self.functions.get(&id).and_then(|v| v.1)
+    }
+
+    // Returns a handle that can be called while `C` is borrowed mutably.
+    pub fn get(&self, id: usize) -> ExecuteResult<NativeFn<C>> {
+        match self.functions.get(&id) {
+            Some(v) => Ok(v.0.clone()),
+            None => Err(ExecuteError::InvalidNativeInvoke)
+        }
+    }
+}
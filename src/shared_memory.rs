@@ -0,0 +1,464 @@
+// Linear memory that several `VirtualMachine`s, possibly on different threads, can use
+// at the same time. It borrows its storage, so the host decides how it is allocated
+// and how long it lives.
+//
+// Every access goes through the `AtomicU64` cells, since Rust does not allow racing
+// atomic accesses of different sizes to the same bytes. Atomic opcodes are sequentially
+// consistent operations on the cell containing them; 32-bit ones are a compare-and-swap
+// loop on it. All other accesses, including plain loads and stores, are relaxed operations
+// on each cell they touch, with stores of less than a cell as a compare-and-swap loop.
+// Concurrent guests thus never race in the Rust sense but may observe values torn across
+// cells, as in the WebAssembly threads proposal. Cells hold their bytes little-endian, so
+// on big-endian hosts 64-bit atomics swap bytes and add or subtract with a compare-and-swap
+// loop.
+
+#[cfg(feature = "shared-memory")]
+use core::cmp;
+#[cfg(feature = "shared-memory")]
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "shared-memory")]
+use error::*;
+
+#[derive(Copy, Clone, Debug)]
+pub enum AtomicRmwOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Xchg
+}
+
+impl AtomicRmwOp {
+    pub fn apply_u32(&self, old: u32, v: u32) -> u32 {
+        match *self {
+            AtomicRmwOp::Add => old.wrapping_add(v),
+            AtomicRmwOp::Sub => old.wrapping_sub(v),
+            AtomicRmwOp::And => old & v,
+            AtomicRmwOp::Or => old | v,
+            AtomicRmwOp::Xor => old ^ v,
+            AtomicRmwOp::Xchg => v
+        }
+    }
+
+    pub fn apply_u64(&self, old: u64, v: u64) -> u64 {
+        match *self {
+            AtomicRmwOp::Add => old.wrapping_add(v),
+            AtomicRmwOp::Sub => old.wrapping_sub(v),
+            AtomicRmwOp::And => old & v,
+            AtomicRmwOp::Or => old | v,
+            AtomicRmwOp::Xor => old ^ v,
+            AtomicRmwOp::Xchg => v
+        }
+    }
+}
+
+// Applies `$op` to the little-endian `$t` in `$word` and returns the previous value.
+// Bitwise operations and exchanges commute with swapping bytes; arithmetic does not.
+#[cfg(feature = "shared-memory")]
+macro_rules! atomic_rmw {
+    ($word:expr, $t:ty, $apply:ident, $op:expr, $v:expr) => {
+        {
+            let word = $word;
+            let v: $t = $v;
+            let old = match $op {
+                AtomicRmwOp::Add if cfg!(target_endian = "little") => word.fetch_add(v, Ordering::SeqCst),
+                AtomicRmwOp::Sub if cfg!(target_endian = "little") => word.fetch_sub(v, Ordering::SeqCst),
+                AtomicRmwOp::Add | AtomicRmwOp::Sub => {
+                    let op = $op;
+                    match word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |old: $t| {
+                        Some(<$t>::to_le(op.$apply(<$t>::from_le(old), v)))
+                    }) {
+                        Ok(old) | Err(old) => old
+                    }
+                },
+                AtomicRmwOp::And => word.fetch_and(v.to_le(), Ordering::SeqCst),
+                AtomicRmwOp::Or => word.fetch_or(v.to_le(), Ordering::SeqCst),
+                AtomicRmwOp::Xor => word.fetch_xor(v.to_le(), Ordering::SeqCst),
+                AtomicRmwOp::Xchg => word.swap(v.to_le(), Ordering::SeqCst)
+            };
+            <$t>::from_le(old)
+        }
+    }
+}
+
+#[cfg(feature = "shared-memory")]
+#[derive(Copy, Clone, Debug)]
+pub struct SharedMemory<'a> {
+    cells: &'a [AtomicU64],
+    len: &'a AtomicUsize
+}
+
+#[cfg(feature = "shared-memory")]
+impl<'a> SharedMemory<'a> {
+    // `len` holds the current size in bytes and is shared by every user of `cells`;
+    // the size of `cells` is the limit for `grow`.
+    pub fn new(cells: &'a [AtomicU64], len: &'a AtomicUsize) -> ExecuteResult<SharedMemory<'a>> {
+        if len.load(Ordering::SeqCst) > cells.len() * 8 {
+            return Err(ExecuteError::MemoryLimit);
+        }
+
+        Ok(SharedMemory {
+            cells: cells,
+            len: len
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.cells.len() * 8
+    }
+
+    pub fn grow(&self, len_inc: usize) -> ExecuteResult<()> {
+        let mut len = self.len();
+        loop {
+            let new_len = match len.checked_add(len_inc) {
+                Some(v) if v <= self.capacity() => v,
+                _ => return Err(ExecuteError::MemoryLimit)
+            };
+            match self.len.compare_exchange(len, new_len, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return Ok(()),
+                Err(v) => len = v
+            }
+        }
+    }
+
+    fn check(&self, ra: usize, width: usize) -> ExecuteResult<()> {
+        match ra.checked_add(width) {
+            Some(end) if end <= self.len() => Ok(()),
+            _ => Err(ExecuteError::Bounds)
+        }
+    }
+
+    pub fn check_atomic_address(&self, ra: usize, width: usize) -> ExecuteResult<()> {
+        self.check(ra, width)?;
+        if !ra.is_multiple_of(width) {
+            return Err(ExecuteError::MisalignedAtomic);
+        }
+        Ok(())
+    }
+
+    // Mask of the low `n` bytes, for `n` up to 8.
+    fn byte_mask(n: usize) -> u64 {
+        if n >= 8 { !0 } else { (1u64 << (n * 8)) - 1 }
+    }
+
+    // Loads the `width` bytes at `ra`, which must have been checked, cell by cell.
+    fn load_le(&self, ra: usize, width: usize, order: Ordering) -> u64 {
+        let mut v: u64 = 0;
+        let mut i = 0;
+        while i < width {
+            let addr = ra + i;
+            let shift = addr % 8;
+            let n = cmp::min(8 - shift, width - i);
+            let cell = u64::from_le(self.cells[addr / 8].load(order));
+            v |= ((cell >> (shift * 8)) & Self::byte_mask(n)) << (i * 8);
+            i += n;
+        }
+        v
+    }
+
+    // Stores the `width` bytes of `v` at `ra`, which must have been checked, cell by cell.
+    // Returns the bytes that were there before.
+    fn store_le(&self, ra: usize, width: usize, v: u64, order: Ordering) -> u64 {
+        let mut old: u64 = 0;
+        let mut i = 0;
+        while i < width {
+            let addr = ra + i;
+            let shift = addr % 8;
+            let n = cmp::min(8 - shift, width - i);
+            let cell = &self.cells[addr / 8];
+            let bits = (v >> (i * 8)) & Self::byte_mask(n);
+
+            let prev = if n == 8 {
+                cell.swap(bits.to_le(), order)
+            } else {
+                let mask = Self::byte_mask(n) << (shift * 8);
+                match cell.fetch_update(order, Ordering::Relaxed, |c| {
+                    Some(((u64::from_le(c) & !mask) | (bits << (shift * 8))).to_le())
+                }) {
+                    Ok(c) | Err(c) => c
+                }
+            };
+            old |= ((u64::from_le(prev) >> (shift * 8)) & Self::byte_mask(n)) << (i * 8);
+            i += n;
+        }
+        old
+    }
+
+    fn read_le(&self, ra: usize, width: usize) -> ExecuteResult<u64> {
+        self.check(ra, width)?;
+        Ok(self.load_le(ra, width, Ordering::Relaxed))
+    }
+
+    fn write_le(&self, ra: usize, width: usize, v: u64) -> ExecuteResult<()> {
+        self.check(ra, width)?;
+        self.store_le(ra, width, v, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn read_u8(&self, ra: usize) -> ExecuteResult<u8> {
+        Ok(self.read_le(ra, 1)? as u8)
+    }
+
+    pub fn read_u16(&self, ra: usize) -> ExecuteResult<u16> {
+        Ok(self.read_le(ra, 2)? as u16)
+    }
+
+    pub fn read_u32(&self, ra: usize) -> ExecuteResult<u32> {
+        Ok(self.read_le(ra, 4)? as u32)
+    }
+
+    pub fn read_u64(&self, ra: usize) -> ExecuteResult<u64> {
+        self.read_le(ra, 8)
+    }
+
+    pub fn write_u8(&self, ra: usize, v: u8) -> ExecuteResult<()> {
+        self.write_le(ra, 1, v as u64)
+    }
+
+    pub fn write_u16(&self, ra: usize, v: u16) -> ExecuteResult<()> {
+        self.write_le(ra, 2, v as u64)
+    }
+
+    pub fn write_u32(&self, ra: usize, v: u32) -> ExecuteResult<()> {
+        self.write_le(ra, 4, v as u64)
+    }
+
+    pub fn write_u64(&self, ra: usize, v: u64) -> ExecuteResult<()> {
+        self.write_le(ra, 8, v)
+    }
+
+    // Copies up to a cell at a time. Chunks are read before they are written and go
+    // towards the end the copy starts from, so overlapping ranges are copied correctly.
+    pub fn copy_range(&self, src: usize, dest: usize, len: usize) -> ExecuteResult<()> {
+        self.check(src, len)?;
+        self.check(dest, len)?;
+
+        let mut done = 0;
+        while done < len {
+            let n = cmp::min(8, len - done);
+            let offset = if dest <= src { done } else { len - done - n };
+            let v = self.load_le(src + offset, n, Ordering::Relaxed);
+            self.store_le(dest + offset, n, v, Ordering::Relaxed);
+            done += n;
+        }
+        Ok(())
+    }
+
+    pub fn fill_range(&self, dest: usize, val: u8, len: usize) -> ExecuteResult<()> {
+        self.check(dest, len)?;
+
+        let v = (val as u64) * 0x0101010101010101;
+        let mut i = 0;
+        while i < len {
+            let n = cmp::min(8, len - i);
+            self.store_le(dest + i, n, v, Ordering::Relaxed);
+            i += n;
+        }
+        Ok(())
+    }
+
+    pub fn write_bytes(&self, dest: usize, data: &[u8]) -> ExecuteResult<()> {
+        self.check(dest, data.len())?;
+
+        for (i, chunk) in data.chunks(8).enumerate() {
+            let v = chunk.iter().rev().fold(0u64, |v, &b| (v << 8) | b as u64);
+            self.store_le(dest + i * 8, chunk.len(), v, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    pub fn atomic_load_u32(&self, ra: usize) -> ExecuteResult<u32> {
+        self.check_atomic_address(ra, 4)?;
+        Ok(self.load_le(ra, 4, Ordering::SeqCst) as u32)
+    }
+
+    pub fn atomic_load_u64(&self, ra: usize) -> ExecuteResult<u64> {
+        self.check_atomic_address(ra, 8)?;
+        Ok(u64::from_le(self.cells[ra / 8].load(Ordering::SeqCst)))
+    }
+
+    pub fn atomic_store_u32(&self, ra: usize, v: u32) -> ExecuteResult<()> {
+        self.check_atomic_address(ra, 4)?;
+        self.store_le(ra, 4, v as u64, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn atomic_store_u64(&self, ra: usize, v: u64) -> ExecuteResult<()> {
+        self.check_atomic_address(ra, 8)?;
+        self.cells[ra / 8].store(v.to_le(), Ordering::SeqCst);
+        Ok(())
+    }
+
+    // Replaces the 32-bit word at the checked and aligned `ra` with `f` of its value,
+    // unless `f` returns `None`. Returns the previous value.
+    fn update_u32<F: Fn(u32) -> Option<u32>>(&self, ra: usize, f: F) -> u32 {
+        let shift = (ra % 8) * 8;
+        let mask = 0xffffffffu64 << shift;
+        let old = match self.cells[ra / 8].fetch_update(Ordering::SeqCst, Ordering::SeqCst, |c| {
+            let c = u64::from_le(c);
+            f((c >> shift) as u32).map(|v| ((c & !mask) | ((v as u64) << shift)).to_le())
+        }) {
+            Ok(c) | Err(c) => u64::from_le(c)
+        };
+        (old >> shift) as u32
+    }
+
+    // Returns the previous value.
+    pub fn atomic_rmw_u32(&self, ra: usize, op: AtomicRmwOp, v: u32) -> ExecuteResult<u32> {
+        self.check_atomic_address(ra, 4)?;
+        Ok(self.update_u32(ra, |old| Some(op.apply_u32(old, v))))
+    }
+
+    // Returns the previous value.
+    pub fn atomic_rmw_u64(&self, ra: usize, op: AtomicRmwOp, v: u64) -> ExecuteResult<u64> {
+        self.check_atomic_address(ra, 8)?;
+        Ok(atomic_rmw!(&self.cells[ra / 8], u64, apply_u64, op, v))
+    }
+
+    // Returns the previous value; the store happened if it equals `expected`.
+    pub fn atomic_cmpxchg_u32(&self, ra: usize, expected: u32, replacement: u32) -> ExecuteResult<u32> {
+        self.check_atomic_address(ra, 4)?;
+        Ok(self.update_u32(ra, |old| if old == expected { Some(replacement) } else { None }))
+    }
+
+    // Returns the previous value; the store happened if it equals `expected`.
+    pub fn atomic_cmpxchg_u64(&self, ra: usize, expected: u64, replacement: u64) -> ExecuteResult<u64> {
+        self.check_atomic_address(ra, 8)?;
+
+        Ok(match self.cells[ra / 8].compare_exchange(expected.to_le(), replacement.to_le(), Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(v) | Err(v) => u64::from_le(v)
+        })
+    }
+}
+
+// Heap-allocated shared memory with a parking table for `atomic_wait` / `atomic_notify`,
+// for environments on different threads to share through an `Arc`.
+#[cfg(all(feature = "shared-memory", feature = "std"))]
+pub struct SharedMemoryBuffer {
+    cells: ::std::boxed::Box<[AtomicU64]>,
+    len: AtomicUsize,
+    // Waiters by address, in the order they started waiting.
+    waiters: ::std::sync::Mutex<::std::collections::HashMap<usize, ::std::collections::VecDeque<::std::sync::Arc<Waiter>>>>
+}
+
+#[cfg(all(feature = "shared-memory", feature = "std"))]
+struct Waiter {
+    thread: ::std::thread::Thread,
+    woken: ::core::sync::atomic::AtomicBool
+}
+
+#[cfg(all(feature = "shared-memory", feature = "std"))]
+impl SharedMemoryBuffer {
+    // `len` zeroed bytes that can grow up to `capacity` bytes.
+    pub fn new(len: usize, capacity: usize) -> ExecuteResult<SharedMemoryBuffer> {
+        if len > capacity {
+            return Err(ExecuteError::MemoryLimit);
+        }
+
+        Ok(SharedMemoryBuffer {
+            cells: (0..capacity.div_ceil(8)).map(|_| AtomicU64::new(0)).collect(),
+            len: AtomicUsize::new(len),
+            waiters: ::std::sync::Mutex::new(::std::collections::HashMap::new())
+        })
+    }
+
+    pub fn memory(&self) -> SharedMemory<'_> {
+        SharedMemory {
+            cells: &self.cells,
+            len: &self.len
+        }
+    }
+
+    // As `Environment::atomic_wait`. The value is compared while holding the parking
+    // table's lock, which `notify` takes as well, so no wakeup is missed.
+    pub fn wait(&self, addr: usize, expected: u64, width: usize, timeout_ns: i64) -> ExecuteResult<u32> {
+        use std::sync::Arc;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let waiter = {
+            let mut waiters = self.waiters.lock().map_err(|_| ExecuteError::Generic)?;
+            let current = match width {
+                4 => self.memory().atomic_load_u32(addr)? as u64,
+                8 => self.memory().atomic_load_u64(addr)?,
+                _ => return Err(ExecuteError::InvalidInput)
+            };
+            if current != expected {
+                return Ok(1);
+            }
+
+            let waiter = Arc::new(Waiter {
+                thread: thread::current(),
+                woken: ::core::sync::atomic::AtomicBool::new(false)
+            });
+            waiters.entry(addr).or_default().push_back(waiter.clone());
+            waiter
+        };
+
+        let deadline = if timeout_ns < 0 {
+            None
+        } else {
+            Instant::now().checked_add(Duration::from_nanos(timeout_ns as u64))
+        };
+        while !waiter.woken.load(Ordering::SeqCst) {
+            match deadline {
+                None => thread::park(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+            }
+        }
+        if waiter.woken.load(Ordering::SeqCst) {
+            return Ok(0);
+        }
+
+        // Timed out, unless a notification came in before we got the lock.
+        let mut waiters = self.waiters.lock().map_err(|_| ExecuteError::Generic)?;
+        if waiter.woken.load(Ordering::SeqCst) {
+            return Ok(0);
+        }
+        if let Some(queue) = waiters.get_mut(&addr) {
+            queue.retain(|w| !Arc::ptr_eq(w, &waiter));
+            if queue.is_empty() {
+                waiters.remove(&addr);
+            }
+        }
+        Ok(2)
+    }
+
+    // As `Environment::atomic_notify`.
+    pub fn notify(&self, addr: usize, count: u32) -> ExecuteResult<u32> {
+        let mut waiters = self.waiters.lock().map_err(|_| ExecuteError::Generic)?;
+
+        let mut n_woken = 0;
+        if let Some(queue) = waiters.get_mut(&addr) {
+            while n_woken < count {
+                match queue.pop_front() {
+                    Some(w) => {
+                        w.woken.store(true, Ordering::SeqCst);
+                        w.thread.unpark();
+                        n_woken += 1;
+                    },
+                    None => break
+                }
+            }
+            if queue.is_empty() {
+                waiters.remove(&addr);
+            }
+        }
+        Ok(n_woken)
+    }
+}
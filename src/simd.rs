@@ -0,0 +1,278 @@
+// 128-bit SIMD. A v128 takes two operand stack cells: the low 64 bits are pushed first
+// and the high 64 bits on top of them. Lane 0 is the least significant lane.
+//
+// All SIMD instructions share the `Opcode::Simd` prefix, followed by a `SimdOpcode` byte
+// and the immediates of the instruction.
+
+use error::*;
+
+#[derive(Copy, Clone, Debug)]
+#[repr(u8)]
+pub enum SimdOpcode {
+    V128Load = 1, // offset: u32
+    V128Store, // offset: u32
+    V128Const, // value: 16 bytes
+
+    I8x16Splat,
+    I16x8Splat,
+    I32x4Splat,
+    I64x2Splat,
+
+    // lane: u8
+    I8x16ExtractLaneS,
+    I8x16ExtractLaneU,
+    I16x8ExtractLaneS,
+    I16x8ExtractLaneU,
+    I32x4ExtractLane,
+    I64x2ExtractLane,
+    I8x16ReplaceLane,
+    I16x8ReplaceLane,
+    I32x4ReplaceLane,
+    I64x2ReplaceLane,
+
+    I8x16Shuffle, // lanes: 16 bytes, each selecting one of the 32 lanes of both operands
+    I8x16Swizzle,
+
+    V128Not,
+    V128And,
+    V128Or,
+    V128Xor,
+    V128AndNot,
+    V128AnyTrue,
+
+    I8x16Add,
+    I8x16Sub,
+    I8x16Neg,
+    I8x16Eq,
+    I8x16Ne,
+    I8x16LtS,
+    I8x16LtU,
+    I8x16GtS,
+    I8x16GtU,
+    I8x16MinS,
+    I8x16MinU,
+    I8x16MaxS,
+    I8x16MaxU,
+    I8x16Shl,
+    I8x16ShrS,
+    I8x16ShrU,
+    I8x16AllTrue,
+
+    I16x8Add,
+    I16x8Sub,
+    I16x8Mul,
+    I16x8Neg,
+    I16x8Eq,
+    I16x8Ne,
+    I16x8LtS,
+    I16x8LtU,
+    I16x8GtS,
+    I16x8GtU,
+    I16x8MinS,
+    I16x8MinU,
+    I16x8MaxS,
+    I16x8MaxU,
+    I16x8Shl,
+    I16x8ShrS,
+    I16x8ShrU,
+    I16x8AllTrue,
+
+    I32x4Add,
+    I32x4Sub,
+    I32x4Mul,
+    I32x4Neg,
+    I32x4Eq,
+    I32x4Ne,
+    I32x4LtS,
+    I32x4LtU,
+    I32x4GtS,
+    I32x4GtU,
+    I32x4MinS,
+    I32x4MinU,
+    I32x4MaxS,
+    I32x4MaxU,
+    I32x4Shl,
+    I32x4ShrS,
+    I32x4ShrU,
+    I32x4AllTrue,
+
+    I64x2Add,
+    I64x2Sub,
+    I64x2Mul,
+    I64x2Neg,
+    I64x2Eq,
+    I64x2Ne,
+    I64x2Shl,
+    I64x2ShrS,
+    I64x2ShrU,
+    I64x2AllTrue,
+
+    Never
+}
+
+impl SimdOpcode {
+    #[inline]
+    pub fn from_raw(v: u8) -> ExecuteResult<SimdOpcode> {
+        if v > 0 && v < SimdOpcode::Never as u8 {
+            Ok(unsafe { ::core::mem::transmute::<u8, SimdOpcode>(v) })
+        } else {
+            Err(ExecuteError::IllegalOpcode)
+        }
+    }
+
+    pub fn immediate_len(&self) -> usize {
+        use self::SimdOpcode::*;
+
+        match *self {
+            V128Load | V128Store => 4,
+            V128Const | I8x16Shuffle => 16,
+            I8x16ExtractLaneS | I8x16ExtractLaneU | I16x8ExtractLaneS | I16x8ExtractLaneU
+                | I32x4ExtractLane | I64x2ExtractLane
+                | I8x16ReplaceLane | I16x8ReplaceLane | I32x4ReplaceLane | I64x2ReplaceLane => 1,
+            _ => 0
+        }
+    }
+
+    // Number of operand stack cells popped and pushed.
+    pub fn stack_effect(&self) -> (u32, u32) {
+        use self::SimdOpcode::*;
+
+        match *self {
+            V128Load => (1, 2),
+            V128Store => (3, 0),
+            V128Const => (0, 2),
+            I8x16Splat | I16x8Splat | I32x4Splat | I64x2Splat => (1, 2),
+            I8x16ExtractLaneS | I8x16ExtractLaneU | I16x8ExtractLaneS | I16x8ExtractLaneU
+                | I32x4ExtractLane | I64x2ExtractLane => (2, 1),
+            I8x16ReplaceLane | I16x8ReplaceLane | I32x4ReplaceLane | I64x2ReplaceLane => (3, 2),
+            V128AnyTrue | I8x16AllTrue | I16x8AllTrue | I32x4AllTrue | I64x2AllTrue => (2, 1),
+            V128Not | I8x16Neg | I16x8Neg | I32x4Neg | I64x2Neg => (2, 2),
+            I8x16Shl | I8x16ShrS | I8x16ShrU
+                | I16x8Shl | I16x8ShrS | I16x8ShrU
+                | I32x4Shl | I32x4ShrS | I32x4ShrU
+                | I64x2Shl | I64x2ShrS | I64x2ShrU => (3, 2),
+            Never => (0, 0),
+            _ => (4, 2)
+        }
+    }
+}
+
+pub trait Lane: Copy {
+    const BITS: u32;
+
+    fn get(v: u128, i: u32) -> Self;
+    fn to_bits(self) -> u128;
+}
+
+macro_rules! impl_lane {
+    ($t:ty, $unsigned:ty, $bits:expr) => {
+        impl Lane for $t {
+            const BITS: u32 = $bits;
+
+            #[inline]
+            fn get(v: u128, i: u32) -> $t {
+                (v >> (i * $bits)) as $unsigned as $t
+            }
+
+            #[inline]
+            fn to_bits(self) -> u128 {
+                self as $unsigned as u128
+            }
+        }
+    }
+}
+
+impl_lane!(i8, u8, 8);
+impl_lane!(u8, u8, 8);
+impl_lane!(i16, u16, 16);
+impl_lane!(u16, u16, 16);
+impl_lane!(i32, u32, 32);
+impl_lane!(u32, u32, 32);
+impl_lane!(i64, u64, 64);
+impl_lane!(u64, u64, 64);
+
+#[inline]
+pub fn lanes<T: Lane>() -> u32 {
+    128 / T::BITS
+}
+
+#[inline]
+pub fn replace_lane<T: Lane>(v: u128, i: u32, x: T) -> u128 {
+    let mask = (!0u128 >> (128 - T::BITS)) << (i * T::BITS);
+    (v & !mask) | (x.to_bits() << (i * T::BITS))
+}
+
+pub fn check_lane<T: Lane>(lane: u8) -> ExecuteResult<u32> {
+    if (lane as u32) < lanes::<T>() {
+        Ok(lane as u32)
+    } else {
+        Err(ExecuteError::Bounds)
+    }
+}
+
+pub fn splat<T: Lane>(x: T) -> u128 {
+    let mut v = 0;
+    for i in 0..lanes::<T>() {
+        v = replace_lane(v, i, x);
+    }
+    v
+}
+
+pub fn map1<T: Lane, F: Fn(T) -> T>(a: u128, f: F) -> u128 {
+    let mut v = 0;
+    for i in 0..lanes::<T>() {
+        v = replace_lane(v, i, f(T::get(a, i)));
+    }
+    v
+}
+
+pub fn map2<T: Lane, F: Fn(T, T) -> T>(a: u128, b: u128, f: F) -> u128 {
+    let mut v = 0;
+    for i in 0..lanes::<T>() {
+        v = replace_lane(v, i, f(T::get(a, i), T::get(b, i)));
+    }
+    v
+}
+
+// Lanes where `f` holds are set to all ones, others to zero.
+pub fn compare<T: Lane, F: Fn(T, T) -> bool>(a: u128, b: u128, f: F) -> u128 {
+    let ones = !0u128 >> (128 - T::BITS);
+
+    let mut v = 0;
+    for i in 0..lanes::<T>() {
+        if f(T::get(a, i), T::get(b, i)) {
+            v |= ones << (i * T::BITS);
+        }
+    }
+    v
+}
+
+pub fn all_true<T: Lane>(a: u128) -> bool {
+    (0..lanes::<T>()).all(|i| (a >> (i * T::BITS)) & (!0u128 >> (128 - T::BITS)) != 0)
+}
+
+pub fn shuffle(a: u128, b: u128, selectors: &[u8]) -> ExecuteResult<u128> {
+    let mut v = 0;
+    for (i, &s) in selectors.iter().enumerate() {
+        let lane: u8 = match s {
+            0..=15 => Lane::get(a, s as u32),
+            16..=31 => Lane::get(b, s as u32 - 16),
+            _ => return Err(ExecuteError::Bounds)
+        };
+        v = replace_lane(v, i as u32, lane);
+    }
+    Ok(v)
+}
+
+// Out-of-range selectors produce zero lanes.
+pub fn swizzle(a: u128, s: u128) -> u128 {
+    let mut v = 0;
+    for i in 0..16 {
+        let sel: u8 = Lane::get(s, i);
+        if sel < 16 {
+            let lane: u8 = Lane::get(a, sel as u32);
+            v = replace_lane(v, i, lane);
+        }
+    }
+    v
+}
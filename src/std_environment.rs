@@ -0,0 +1,513 @@
+// A ready-made `Environment` backed by heap-allocated buffers.
+//
+// Native functions are looked up by the id of the `NativeInvoke` in a `NativeRegistry`,
+// either typed or receiving the environment itself and popping their arguments from
+// `get_stack()`. With `standard_syscalls`, reserved ids go to `syscall::dispatch` or, for
+// the WASI subset, `wasi::dispatch` instead, and only user syscall ids are looked up.
+// MMIO regions are backed by `MmioDevice`s, identified by their index. With the
+// `shared-memory` feature, linear memory can be a `SharedMemoryBuffer` shared with
+// environments on other threads, which also handles `atomic_wait` / `atomic_notify`.
+// Syscalls that access memory fail with `ExecuteError::NotSupported` then. Trace callbacks
+// do not see the operand stack, so the VM keeps caching its top across them.
+//
+// The builder's `Limits` become those of VMs created over the environment, which enforce
+// them; the environment itself only checks the initial memory and slots against them.
+
+use std::boxed::Box;
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::string::String;
+#[cfg(feature = "shared-memory")]
+use std::sync::Arc;
+use std::vec;
+use std::vec::Vec;
+use environment::Environment;
+use fs_syscall::FsSandbox;
+use mmio::{self, MmioDevice, MmioRegion};
+use module::{Opcode, TableEntry};
+use native::{IntoNative, NativeRegistry};
+#[cfg(feature = "shared-memory")]
+use shared_memory::{SharedMemory, SharedMemoryBuffer};
+use syscall::{self, Clock, LogLevel, RandomSource, SeededRandom, SyscallEnvironment, SystemClock, SystemRandom, VirtualClock};
+use tape::Tape;
+use vm::Limits;
+use wasi::{self, Errno, WasiEnvironment, ERRNO_BADF, ERRNO_IO, FD_STDERR, FD_STDIN, FD_STDOUT};
+use error::*;
+
+type MemInitTracer = Box<dyn Fn(usize, &[u8])>;
+type OpcodeTracer = Box<dyn Fn(&Opcode) -> ExecuteResult<()>>;
+type CallTracer = Box<dyn Fn(usize, usize)>;
+type LoadTracer = Box<dyn Fn(usize, usize, u64)>;
+type BranchTracer = Box<dyn Fn(usize) -> ExecuteResult<()>>;
+
+#[derive(Default)]
+struct Tracers {
+    mem_init: Option<MemInitTracer>,
+    opcode: Option<OpcodeTracer>,
+    call: Option<CallTracer>,
+    load: Option<LoadTracer>,
+    branch: Option<BranchTracer>
+}
+
+pub struct StdEnvironmentBuilder {
+    memory_size: usize,
+    #[cfg(feature = "shared-memory")]
+    shared_memory: Option<Arc<SharedMemoryBuffer>>,
+    n_slots: usize,
+    stack_size: usize,
+    call_stack_size: usize,
+    table_size: usize,
+    limits: Limits,
+    natives: NativeRegistry<StdEnvironment>,
+    standard_syscalls: bool,
+    clock: Box<dyn Clock>,
+    random: Box<dyn RandomSource>,
+    fs: Option<FsSandbox>,
+    wasi_args: Vec<String>,
+    wasi_env: Vec<String>,
+    mmio_regions: Vec<MmioRegion>,
+    mmio_devices: Vec<Box<dyn MmioDevice>>,
+    tracers: Tracers
+}
+
+impl Default for StdEnvironmentBuilder {
+    fn default() -> StdEnvironmentBuilder {
+        StdEnvironmentBuilder {
+            memory_size: 1048576,
+            #[cfg(feature = "shared-memory")]
+            shared_memory: None,
+            n_slots: 65536,
+            stack_size: 1024,
+            call_stack_size: 1024,
+            table_size: 0,
+            limits: Limits::default(),
+            natives: NativeRegistry::new(),
+            standard_syscalls: false,
+            clock: Box::new(SystemClock::new()),
+            random: Box::new(SystemRandom::new()),
+            fs: None,
+            wasi_args: Vec::new(),
+            wasi_env: Vec::new(),
+            mmio_regions: Vec::new(),
+            mmio_devices: Vec::new(),
+            tracers: Tracers::default()
+        }
+    }
+}
+
+impl StdEnvironmentBuilder {
+    pub fn new() -> StdEnvironmentBuilder {
+        StdEnvironmentBuilder::default()
+    }
+
+    // Initial size of linear memory in bytes.
+    pub fn memory_size(mut self, len: usize) -> Self {
+        self.memory_size = len;
+        self
+    }
+
+    // Uses `memory` as linear memory instead of a local buffer of `memory_size` bytes.
+    #[cfg(feature = "shared-memory")]
+    pub fn shared_memory(mut self, memory: Arc<SharedMemoryBuffer>) -> Self {
+        self.shared_memory = Some(memory);
+        self
+    }
+
+    pub fn slots(mut self, n: usize) -> Self {
+        self.n_slots = n;
+        self
+    }
+
+    // Operand stack size in cells.
+    pub fn stack_size(mut self, n: usize) -> Self {
+        self.stack_size = n;
+        self
+    }
+
+    // Call stack size in cells.
+    pub fn call_stack_size(mut self, n: usize) -> Self {
+        self.call_stack_size = n;
+        self
+    }
+
+    // Number of entries in the function table used by `CallIndirect`.
+    pub fn table_size(mut self, n: usize) -> Self {
+        self.table_size = n;
+        self
+    }
+
+    // Returned by `get_limits`, so VMs created over the environment start with them.
+    // `build` fails with `ExecuteError::MemoryLimit` / `SlotLimit` if the initial memory
+    // or slots exceed them.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn native<F>(mut self, id: usize, f: F) -> Self
+        where F: Fn(&mut StdEnvironment) -> ExecuteResult<Option<i64>> + 'static {
+        self.natives.register_raw(id, f);
+        self
+    }
+
+    pub fn typed_native<A, F: IntoNative<StdEnvironment, A>>(mut self, id: usize, f: F) -> Self {
+        self.natives.register(id, f);
+        self
+    }
+
+    // Handles the standard syscalls. Natives must then use ids from
+    // `syscall::USER_SYSCALL_BASE` on.
+    pub fn standard_syscalls(mut self) -> Self {
+        self.standard_syscalls = true;
+        self
+    }
+
+    // Used by the clock syscalls. Defaults to `SystemClock`.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    // Used by the `random` syscall. Defaults to `SystemRandom`.
+    pub fn random<R: RandomSource + 'static>(mut self, random: R) -> Self {
+        self.random = Box::new(random);
+        self
+    }
+
+    // A virtual clock starting at the Unix epoch and advancing by 1us per read, and
+    // randomness seeded with `seed`, for reproducible runs.
+    pub fn deterministic(self, seed: u64) -> Self {
+        self.clock(VirtualClock::new(0, 1000)).random(SeededRandom::new(seed))
+    }
+
+    // Enables the file system syscalls, confined to the sandbox's root. Needs
+    // `standard_syscalls`.
+    pub fn fs(mut self, sandbox: FsSandbox) -> Self {
+        self.fs = Some(sandbox);
+        self
+    }
+
+    // Command line arguments seen through WASI `args_get`.
+    pub fn wasi_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.wasi_args.push(arg.into());
+        self
+    }
+
+    // An environment variable seen through WASI `environ_get`.
+    pub fn wasi_env<K: AsRef<str>, V: AsRef<str>>(mut self, key: K, value: V) -> Self {
+        let mut var = String::from(key.as_ref());
+        var.push('=');
+        var.push_str(value.as_ref());
+        self.wasi_env.push(var);
+        self
+    }
+
+    // Maps `len` bytes from `start` to `device`. `build` fails with
+    // `ExecuteError::InvalidInput` if regions overlap.
+    pub fn mmio_device<D: MmioDevice + 'static>(mut self, start: usize, len: usize, device: D) -> Self {
+        self.mmio_regions.push(MmioRegion::new(start, len, self.mmio_devices.len()));
+        self.mmio_devices.push(Box::new(device));
+        self
+    }
+
+    pub fn trace_mem_init<F: Fn(usize, &[u8]) + 'static>(mut self, f: F) -> Self {
+        self.tracers.mem_init = Some(Box::new(f));
+        self
+    }
+
+    pub fn trace_opcode<F: Fn(&Opcode) -> ExecuteResult<()> + 'static>(mut self, f: F) -> Self {
+        self.tracers.opcode = Some(Box::new(f));
+        self
+    }
+
+    pub fn trace_call<F: Fn(usize, usize) + 'static>(mut self, f: F) -> Self {
+        self.tracers.call = Some(Box::new(f));
+        self
+    }
+
+    pub fn trace_load<F: Fn(usize, usize, u64) + 'static>(mut self, f: F) -> Self {
+        self.tracers.load = Some(Box::new(f));
+        self
+    }
+
+    pub fn trace_branch<F: Fn(usize) -> ExecuteResult<()> + 'static>(mut self, f: F) -> Self {
+        self.tracers.branch = Some(Box::new(f));
+        self
+    }
+
+    pub fn build(self) -> ExecuteResult<StdEnvironment> {
+        mmio::check_regions(&self.mmio_regions)?;
+
+        // Initial and local memory size. No local memory is allocated if it is shared.
+        #[cfg(feature = "shared-memory")]
+        let (memory_size, local_memory_size) = match self.shared_memory {
+            Some(ref mem) => (mem.memory().len(), 0),
+            None => (self.memory_size, self.memory_size)
+        };
+        #[cfg(not(feature = "shared-memory"))]
+        let (memory_size, local_memory_size) = (self.memory_size, self.memory_size);
+
+        if memory_size > self.limits.max_memory {
+            return Err(ExecuteError::MemoryLimit);
+        }
+        if self.n_slots > self.limits.max_slots {
+            return Err(ExecuteError::SlotLimit);
+        }
+
+        Ok(StdEnvironment {
+            memory: vec![0; local_memory_size],
+            #[cfg(feature = "shared-memory")]
+            shared_memory: self.shared_memory,
+            slots: vec![0; self.n_slots],
+            table: vec![None; self.table_size],
+            limits: self.limits,
+            stack: cells(self.stack_size),
+            call_stack: cells(self.call_stack_size),
+            // Every frame takes at least two call stack cells.
+            frame_stack: cells(self.call_stack_size / 2),
+            natives: self.natives,
+            standard_syscalls: self.standard_syscalls,
+            clock: self.clock,
+            random: self.random,
+            fs: self.fs,
+            wasi_args: self.wasi_args,
+            wasi_env: self.wasi_env,
+            mmio_regions: self.mmio_regions,
+            mmio_devices: self.mmio_devices,
+            tracers: self.tracers
+        })
+    }
+}
+
+fn cells(n: usize) -> Tape<'static, Cell<i64>> {
+    Tape::from(vec![Cell::new(0); n].into_boxed_slice())
+}
+
+pub struct StdEnvironment {
+    pub memory: Vec<u8>,
+    #[cfg(feature = "shared-memory")]
+    shared_memory: Option<Arc<SharedMemoryBuffer>>,
+    pub slots: Vec<i64>,
+    pub table: Vec<Option<TableEntry>>,
+    limits: Limits,
+    stack: Tape<'static, Cell<i64>>,
+    call_stack: Tape<'static, Cell<i64>>,
+    frame_stack: Tape<'static, Cell<i64>>,
+    natives: NativeRegistry<StdEnvironment>,
+    standard_syscalls: bool,
+    clock: Box<dyn Clock>,
+    random: Box<dyn RandomSource>,
+    fs: Option<FsSandbox>,
+    wasi_args: Vec<String>,
+    wasi_env: Vec<String>,
+    mmio_regions: Vec<MmioRegion>,
+    mmio_devices: Vec<Box<dyn MmioDevice>>,
+    tracers: Tracers
+}
+
+impl StdEnvironment {
+    pub fn builder() -> StdEnvironmentBuilder {
+        StdEnvironmentBuilder::new()
+    }
+
+    pub fn natives(&self) -> &NativeRegistry<StdEnvironment> {
+        &self.natives
+    }
+
+    // For adding or replacing native functions after the environment has been built.
+    pub fn natives_mut(&mut self) -> &mut NativeRegistry<StdEnvironment> {
+        &mut self.natives
+    }
+}
+
+impl Environment for StdEnvironment {
+    const TRACE_STACK: bool = false;
+
+    fn get_memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    fn get_memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    fn grow_memory(&mut self, len_inc: usize) -> ExecuteResult<()> {
+        #[cfg(feature = "shared-memory")]
+        {
+            if let Some(ref mem) = self.shared_memory {
+                return mem.memory().grow(len_inc);
+            }
+        }
+
+        let new_len = match self.memory.len().checked_add(len_inc) {
+            Some(v) => v,
+            None => return Err(ExecuteError::MemoryLimit)
+        };
+        self.memory.resize(new_len, 0);
+        Ok(())
+    }
+
+    #[cfg(feature = "shared-memory")]
+    fn get_shared_memory(&self) -> Option<SharedMemory<'_>> {
+        self.shared_memory.as_ref().map(|mem| mem.memory())
+    }
+
+    #[cfg(feature = "shared-memory")]
+    fn atomic_wait(&mut self, addr: usize, expected: u64, width: usize, timeout_ns: i64) -> ExecuteResult<u32> {
+        match self.shared_memory {
+            Some(ref mem) => mem.wait(addr, expected, width, timeout_ns),
+            None => Err(ExecuteError::NotSupported)
+        }
+    }
+
+    #[cfg(feature = "shared-memory")]
+    fn atomic_notify(&mut self, addr: usize, count: u32) -> ExecuteResult<u32> {
+        match self.shared_memory {
+            Some(ref mem) => mem.notify(addr, count),
+            None => Ok(0)
+        }
+    }
+
+    fn get_mmio_regions(&self) -> &[MmioRegion] {
+        &self.mmio_regions
+    }
+
+    fn mmio_read(&mut self, region: MmioRegion, offset: usize, width: usize) -> ExecuteResult<u64> {
+        self.mmio_devices[region.id].read(offset, width)
+    }
+
+    fn mmio_write(&mut self, region: MmioRegion, offset: usize, width: usize, val: u64) -> ExecuteResult<()> {
+        self.mmio_devices[region.id].write(offset, width, val)
+    }
+
+    fn get_limits(&self) -> Limits {
+        self.limits
+    }
+
+    fn get_slots(&self) -> &[i64] {
+        &self.slots
+    }
+
+    fn get_slots_mut(&mut self) -> &mut [i64] {
+        &mut self.slots
+    }
+
+    fn reset_slots(&mut self, len: usize) -> ExecuteResult<()> {
+        self.slots = vec![0; len];
+        Ok(())
+    }
+
+    fn get_table(&self) -> &[Option<TableEntry>] {
+        &self.table
+    }
+
+    fn get_table_mut(&mut self) -> &mut [Option<TableEntry>] {
+        &mut self.table
+    }
+
+    fn get_stack(&self) -> &Tape<Cell<i64>> {
+        &self.stack
+    }
+
+    fn get_call_stack(&self) -> &Tape<Cell<i64>> {
+        &self.call_stack
+    }
+
+    fn get_frame_stack(&self) -> Option<&Tape<Cell<i64>>> {
+        Some(&self.frame_stack)
+    }
+
+    fn do_native_invoke(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        if self.standard_syscalls {
+            if wasi::is_wasi_syscall(id) {
+                wasi::dispatch(self, id)
+            } else {
+                syscall::dispatch(self, id)
+            }
+        } else {
+            self.user_syscall(id)
+        }
+    }
+
+    fn trace_mem_init(&self, start: usize, data: &[u8]) {
+        if let Some(ref f) = self.tracers.mem_init {
+            f(start, data);
+        }
+    }
+
+    fn trace_opcode(&self, op: &Opcode) -> ExecuteResult<()> {
+        match self.tracers.opcode {
+            Some(ref f) => f(op),
+            None => Ok(())
+        }
+    }
+
+    fn trace_call(&self, target: usize, n_locals: usize) {
+        if let Some(ref f) = self.tracers.call {
+            f(target, n_locals);
+        }
+    }
+
+    fn trace_load(&self, offset: usize, addr: usize, val: u64) {
+        if let Some(ref f) = self.tracers.load {
+            f(offset, addr, val);
+        }
+    }
+
+    fn trace_branch(&self, target: usize) -> ExecuteResult<()> {
+        match self.tracers.branch {
+            Some(ref f) => f(target),
+            None => Ok(())
+        }
+    }
+}
+
+impl SyscallEnvironment for StdEnvironment {
+    // Writes to stderr.
+    fn log(&self, level: LogLevel, text: &str) {
+        let _ = writeln!(io::stderr(), "[{:?}] {}", level, text);
+    }
+
+    fn clock(&mut self) -> Option<&mut dyn Clock> {
+        Some(&mut *self.clock)
+    }
+
+    fn random(&mut self) -> Option<&mut dyn RandomSource> {
+        Some(&mut *self.random)
+    }
+
+    fn fs(&mut self) -> Option<&mut FsSandbox> {
+        self.fs.as_mut()
+    }
+
+    fn user_syscall(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        let f = self.natives.get(id)?;
+        f(self)
+    }
+}
+
+// Uses the process's stdio.
+impl WasiEnvironment for StdEnvironment {
+    fn wasi_arg(&self, index: usize) -> Option<&[u8]> {
+        self.wasi_args.get(index).map(|v| v.as_bytes())
+    }
+
+    fn wasi_env_var(&self, index: usize) -> Option<&[u8]> {
+        self.wasi_env.get(index).map(|v| v.as_bytes())
+    }
+
+    fn wasi_read(&mut self, fd: u32, buf: &mut [u8]) -> Result<usize, Errno> {
+        match fd {
+            FD_STDIN => io::stdin().read(buf).map_err(|_| ERRNO_IO),
+            _ => Err(ERRNO_BADF)
+        }
+    }
+
+    fn wasi_write(&mut self, fd: u32, buf: &[u8]) -> Result<usize, Errno> {
+        match fd {
+            FD_STDOUT => io::stdout().write(buf).map_err(|_| ERRNO_IO),
+            FD_STDERR => io::stderr().write(buf).map_err(|_| ERRNO_IO),
+            _ => Err(ERRNO_BADF)
+        }
+    }
+}
@@ -0,0 +1,321 @@
+// Dispatcher for the standard syscalls specified in `syscalls.md`, invoked through
+// `NativeInvoke` with the syscall number as the id. Environments implement
+// `SyscallEnvironment` and forward `do_native_invoke` to `dispatch`.
+//
+// Arguments are popped from the operand stack with the last one on top. Pointers and
+// lengths are taken as u32, like memory addresses. For what the specification leaves
+// implementation-defined:
+// - Text that is not valid UTF-8 fails with `ExecuteError::InvalidInput`.
+// - Text or buffers not entirely within linear memory fail with `ExecuteError::Bounds`.
+// - Log levels other than the specified ones fail with `ExecuteError::InvalidInput`.
+// - Reserved numbers without a standard syscall fail with `ExecuteError::InvalidNativeInvoke`.
+// - Clocks and randomness not provided by the environment fail with `ExecuteError::NotSupported`.
+// With the `std` feature, the file system syscalls are passed to `fs_syscall::dispatch`.
+// A standard syscall that fails leaves the operand stack and linear memory as they were:
+// arguments are only popped once they have been checked. The exception is a
+// `RandomSource` that fails partway through `random`, which leaves the buffer partly
+// filled.
+//
+// `exit` and `abort` stop the VM with `ExecuteError::Exit` / `ExecuteError::Abort`.
+
+use core::str;
+use environment::Environment;
+use verifier::NativeSignature;
+#[cfg(feature = "std")]
+use fs_syscall::{self, FsSandbox};
+use error::*;
+
+pub const SYSCALL_LOG: usize = 0;
+pub const SYSCALL_CLOCK_MONOTONIC: usize = 1;
+pub const SYSCALL_CLOCK_WALL: usize = 2;
+pub const SYSCALL_RANDOM: usize = 3;
+pub const SYSCALL_EXIT: usize = 4;
+pub const SYSCALL_ABORT: usize = 5;
+
+// Syscall numbers from here on are passed to `SyscallEnvironment::user_syscall`.
+pub const USER_SYSCALL_BASE: usize = 65536;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info
+}
+
+impl LogLevel {
+    pub fn from_raw(v: i32) -> Option<LogLevel> {
+        match v {
+            1 => Some(LogLevel::Error),
+            3 => Some(LogLevel::Warning),
+            6 => Some(LogLevel::Info),
+            _ => None
+        }
+    }
+}
+
+// Times are in nanoseconds; wall clock time is since the Unix epoch.
+pub trait Clock {
+    fn monotonic_ns(&mut self) -> ExecuteResult<i64>;
+    fn wall_ns(&mut self) -> ExecuteResult<i64>;
+}
+
+pub trait RandomSource {
+    fn fill(&mut self, buf: &mut [u8]) -> ExecuteResult<()>;
+}
+
+// A clock for reproducible runs. Both clocks advance by `step_ns` on every read.
+#[derive(Copy, Clone, Debug)]
+pub struct VirtualClock {
+    elapsed_ns: i64,
+    wall_start_ns: i64,
+    step_ns: i64
+}
+
+impl VirtualClock {
+    pub fn new(wall_start_ns: i64, step_ns: i64) -> VirtualClock {
+        VirtualClock {
+            elapsed_ns: 0,
+            wall_start_ns: wall_start_ns,
+            step_ns: step_ns
+        }
+    }
+
+    fn tick(&mut self) -> i64 {
+        let now = self.elapsed_ns;
+        self.elapsed_ns = self.elapsed_ns.wrapping_add(self.step_ns);
+        now
+    }
+}
+
+impl Clock for VirtualClock {
+    fn monotonic_ns(&mut self) -> ExecuteResult<i64> {
+        Ok(self.tick())
+    }
+
+    fn wall_ns(&mut self) -> ExecuteResult<i64> {
+        Ok(self.wall_start_ns.wrapping_add(self.tick()))
+    }
+}
+
+// SplitMix64. Deterministic for a given seed and not suitable for cryptography.
+#[derive(Copy, Clone, Debug)]
+pub struct SeededRandom {
+    state: u64
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        SeededRandom {
+            state: seed
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn fill(&mut self, buf: &mut [u8]) -> ExecuteResult<()> {
+        for chunk in buf.chunks_mut(8) {
+            let v = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&v[..chunk.len()]);
+        }
+        Ok(())
+    }
+}
+
+// The host's clocks. Monotonic time counts from construction.
+#[cfg(feature = "std")]
+pub struct SystemClock {
+    start: ::std::time::Instant
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock {
+            start: ::std::time::Instant::now()
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock::default()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn monotonic_ns(&mut self) -> ExecuteResult<i64> {
+        Ok(self.start.elapsed().as_nanos() as i64)
+    }
+
+    fn wall_ns(&mut self) -> ExecuteResult<i64> {
+        match ::std::time::SystemTime::now().duration_since(::std::time::UNIX_EPOCH) {
+            Ok(v) => Ok(v.as_nanos() as i64),
+            Err(e) => Ok(-(e.duration().as_nanos() as i64))
+        }
+    }
+}
+
+// Reads `/dev/urandom`. Fails with `ExecuteError::NotSupported` where it does not exist.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct SystemRandom {
+    file: Option<::std::fs::File>
+}
+
+#[cfg(feature = "std")]
+impl SystemRandom {
+    pub fn new() -> SystemRandom {
+        SystemRandom::default()
+    }
+}
+
+#[cfg(feature = "std")]
+impl RandomSource for SystemRandom {
+    fn fill(&mut self, buf: &mut [u8]) -> ExecuteResult<()> {
+        use std::io::Read;
+
+        if self.file.is_none() {
+            self.file = Some(::std::fs::File::open("/dev/urandom").map_err(|_| ExecuteError::NotSupported)?);
+        }
+        self.file.as_mut().unwrap().read_exact(buf).map_err(|_| ExecuteError::NotSupported)
+    }
+}
+
+pub trait SyscallEnvironment: Environment {
+    fn log(&self, _level: LogLevel, _text: &str) {}
+
+    fn clock(&mut self) -> Option<&mut dyn Clock> {
+        None
+    }
+
+    fn random(&mut self) -> Option<&mut dyn RandomSource> {
+        None
+    }
+
+    // Used by the file system syscalls in `fs_syscall`.
+    #[cfg(feature = "std")]
+    fn fs(&mut self) -> Option<&mut FsSandbox> {
+        None
+    }
+
+    fn user_syscall(&mut self, _id: usize) -> ExecuteResult<Option<i64>> {
+        Err(ExecuteError::InvalidNativeInvoke)
+    }
+}
+
+// For use as the `native_sig` argument of `verifier::verify`. User syscalls are unknown.
+pub fn standard_signature(id: usize) -> Option<NativeSignature> {
+    match id {
+        SYSCALL_LOG => Some(NativeSignature { n_args: 3, n_results: 0 }),
+        SYSCALL_CLOCK_MONOTONIC | SYSCALL_CLOCK_WALL => Some(NativeSignature { n_args: 0, n_results: 1 }),
+        SYSCALL_RANDOM | SYSCALL_ABORT => Some(NativeSignature { n_args: 2, n_results: 0 }),
+        SYSCALL_EXIT => Some(NativeSignature { n_args: 1, n_results: 0 }),
+        #[cfg(feature = "std")]
+        _ if fs_syscall::is_fs_syscall(id) => fs_syscall::signature(id),
+        _ => None
+    }
+}
+
+pub fn dispatch<E: SyscallEnvironment>(env: &mut E, id: usize) -> ExecuteResult<Option<i64>> {
+    if id >= USER_SYSCALL_BASE {
+        return env.user_syscall(id);
+    }
+
+    match id {
+        SYSCALL_LOG => {
+            let (level, text_base, text_len) = {
+                let args = env.get_stack().tail_many(3)?;
+                (args[0].get() as i32, args[1].get() as u32 as usize, args[2].get() as u32 as usize)
+            };
+
+            let level = LogLevel::from_raw(level).ok_or(ExecuteError::InvalidInput)?;
+            let text = str::from_utf8(read_memory(env, text_base, text_len)?)
+                .map_err(|_| ExecuteError::InvalidInput)?;
+            env.get_stack().prev_many(3)?;
+            env.log(level, text);
+            Ok(None)
+        },
+        SYSCALL_CLOCK_MONOTONIC => {
+            let clock = env.clock().ok_or(ExecuteError::NotSupported)?;
+            Ok(Some(clock.monotonic_ns()?))
+        },
+        SYSCALL_CLOCK_WALL => {
+            let clock = env.clock().ok_or(ExecuteError::NotSupported)?;
+            Ok(Some(clock.wall_ns()?))
+        },
+        SYSCALL_RANDOM => {
+            let (buf_base, buf_len) = {
+                let args = env.get_stack().tail_many(2)?;
+                (args[0].get() as u32 as usize, args[1].get() as u32 as usize)
+            };
+
+            read_memory(env, buf_base, buf_len)?;
+            if env.random().is_none() {
+                return Err(ExecuteError::NotSupported);
+            }
+            env.get_stack().prev_many(2)?;
+
+            let mut chunk = [0u8; 64];
+            let mut pos = buf_base;
+            while pos < buf_base + buf_len {
+                let n = ::core::cmp::min(chunk.len(), buf_base + buf_len - pos);
+                env.random().ok_or(ExecuteError::NotSupported)?.fill(&mut chunk[..n])?;
+                write_memory(env, pos, n)?.copy_from_slice(&chunk[..n]);
+                pos += n;
+            }
+            Ok(None)
+        },
+        SYSCALL_EXIT => {
+            let status = env.get_stack().prev()?.get() as i32;
+            Err(ExecuteError::Exit(status))
+        },
+        SYSCALL_ABORT => {
+            let (message_base, message_len) = {
+                let args = env.get_stack().tail_many(2)?;
+                (args[0].get() as u32, args[1].get() as u32)
+            };
+
+            str::from_utf8(read_memory(env, message_base as usize, message_len as usize)?)
+                .map_err(|_| ExecuteError::InvalidInput)?;
+            env.get_stack().prev_many(2)?;
+            Err(ExecuteError::Abort {
+                message_base: message_base,
+                message_len: message_len
+            })
+        },
+        #[cfg(feature = "std")]
+        _ if fs_syscall::is_fs_syscall(id) => fs_syscall::dispatch(env, id),
+        _ => Err(ExecuteError::InvalidNativeInvoke)
+    }
+}
+
+pub(crate) fn read_memory<E: Environment>(env: &E, base: usize, len: usize) -> ExecuteResult<&[u8]> {
+    // `get_memory` does not reflect shared linear memory.
+    #[cfg(feature = "shared-memory")]
+    {
+        if env.get_shared_memory().is_some() {
+            return Err(ExecuteError::NotSupported);
+        }
+    }
+
+    let mem = env.get_memory();
+    match base.checked_add(len) {
+        Some(end) if end <= mem.len() => Ok(&mem[base..end]),
+        _ => Err(ExecuteError::Bounds)
+    }
+}
+
+pub(crate) fn write_memory<E: Environment>(env: &mut E, base: usize, len: usize) -> ExecuteResult<&mut [u8]> {
+    read_memory(env, base, len)?;
+    Ok(&mut env.get_memory_mut()[base..base + len])
+}
@@ -1,27 +1,55 @@
 use error::*;
 use core::cell::Cell;
+#[cfg(feature = "std")]
+use std::boxed::Box;
 
 pub struct Tape<'a, T: 'static> {
-    data: &'a [T],
+    data: Storage<'a, T>,
     pos: Cell<usize>
 }
 
+enum Storage<'a, T: 'static> {
+    Borrowed(&'a [T]),
+    // For environments that keep their stacks alongside the rest of their state.
+    #[cfg(feature = "std")]
+    Owned(Box<[T]>)
+}
+
 impl<'a, T: 'static> From<&'a [T]> for Tape<'a, T> {
     fn from(other: &'a [T]) -> Tape<'a, T> {
         Tape {
-            data: other,
+            data: Storage::Borrowed(other),
+            pos: Cell::new(0)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: 'static> From<Box<[T]>> for Tape<'static, T> {
+    fn from(other: Box<[T]>) -> Tape<'static, T> {
+        Tape {
+            data: Storage::Owned(other),
             pos: Cell::new(0)
         }
     }
 }
 
 impl<'a, T: 'static> Tape<'a, T> {
+    #[inline]
+    fn data(&self) -> &[T] {
+        match self.data {
+            Storage::Borrowed(data) => data,
+            #[cfg(feature = "std")]
+            Storage::Owned(ref data) => data
+        }
+    }
+
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.data().len()
     }
 
     pub fn remaining(&self) -> usize {
-        self.data.len() - self.pos.get()
+        self.data().len() - self.pos.get()
     }
 
     pub fn next(&self) -> ExecuteResult<&T> {
@@ -29,7 +57,7 @@ impl<'a, T: 'static> Tape<'a, T> {
             Err(ExecuteError::Bounds)
         } else {
             let pos = self.pos.get();
-            let v = &self.data[pos];
+            let v = &self.data()[pos];
             self.pos.set(pos + 1);
 
             Ok(v)
@@ -41,7 +69,7 @@ impl<'a, T: 'static> Tape<'a, T> {
             Err(ExecuteError::Bounds)
         } else {
             let pos = self.pos.get();
-            let data = &self.data[pos..pos + n];
+            let data = &self.data()[pos..pos + n];
             self.pos.set(pos + n);
 
             Ok(data)
@@ -53,13 +81,28 @@ impl<'a, T: 'static> Tape<'a, T> {
             Err(ExecuteError::Bounds)
         } else {
             let pos = self.pos.get() - 1;
-            let v = &self.data[pos];
+            let v = &self.data()[pos];
             self.pos.set(pos);
 
             Ok(v)
         }
     }
 
+    // The `_unchecked` variants skip the `Result` plumbing for callers that have
+    // already proven the access to be in range (e.g. verified code). A violated
+    // assumption fails the debug assertions, or panics on the slice index.
+    #[inline]
+    pub(crate) fn at_unchecked(&self, at: usize) -> &T {
+        debug_assert!(at < self.data().len());
+        &self.data()[at]
+    }
+
+    #[inline]
+    pub(crate) fn set_pos_unchecked(&self, pos: usize) {
+        debug_assert!(pos <= self.data().len());
+        self.pos.set(pos);
+    }
+
     pub fn prev_many(&self, n: usize) -> ExecuteResult<&[T]> {
         match self.tail_many(n) {
             Ok(v) => {
@@ -77,13 +120,13 @@ impl<'a, T: 'static> Tape<'a, T> {
         if n > pos {
             Err(ExecuteError::Bounds)
         } else {
-            Ok(&self.data[pos - n .. pos])
+            Ok(&self.data()[pos - n .. pos])
         }
     }
 
     pub fn at(&self, at: usize) -> ExecuteResult<&T> {
-        if at < self.data.len() {
-            Ok(&self.data[at])
+        if at < self.data().len() {
+            Ok(&self.data()[at])
         } else {
             Err(ExecuteError::Bounds)
         }
@@ -94,7 +137,7 @@ impl<'a, T: 'static> Tape<'a, T> {
     }
 
     pub fn set_pos(&self, pos: usize) -> ExecuteResult<()> {
-        if pos <= self.data.len() {
+        if pos <= self.data().len() {
             self.pos.set(pos);
             Ok(())
         } else {
@@ -117,7 +160,7 @@ impl<'a> TapeU8 for Tape<'a, u8> {
             use byteorder::{LittleEndian, ByteOrder};
 
             let pos = self.pos.get();
-            let v = LittleEndian::read_u32(&self.data[pos..pos + 4]);
+            let v = LittleEndian::read_u32(&self.data()[pos..pos + 4]);
             self.pos.set(pos + 4);
 
             Ok(v)
@@ -132,7 +175,7 @@ impl<'a> TapeU8 for Tape<'a, u8> {
             use byteorder::{LittleEndian, ByteOrder};
 
             let pos = self.pos.get();
-            let v = LittleEndian::read_u64(&self.data[pos..pos + 8]);
+            let v = LittleEndian::read_u64(&self.data()[pos..pos + 8]);
             self.pos.set(pos + 8);
 
             Ok(v)
@@ -0,0 +1,631 @@
+use module::{Module, Opcode};
+use tape::{Tape, TapeU8};
+use byteorder::{LittleEndian, ByteOrder};
+use error::*;
+#[cfg(feature = "simd")]
+use simd::SimdOpcode;
+
+const REACHED: u8 = 1;
+const DIRTY: u8 = 2;
+const ENTRY: u8 = 4;
+const RESULTS_KNOWN: u8 = 8;
+
+// Per-byte analysis state. Only entries at reached instruction boundaries are meaningful;
+// `max_depth` and `n_results` are only meaningful at function entries.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CodeInfo {
+    flags: u8,
+    known_consts: u8, // bit 1: consts[1] (top) known, bit 0: consts[0] known
+    function: u32,
+    height: u32, // operand stack height relative to function entry
+    consts: [u32; 2],
+    max_depth: u32,
+    n_results: u32
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct NativeSignature {
+    pub n_args: usize,
+    pub n_results: usize // 0 or 1
+}
+
+// A module whose functions have a known maximum operand stack depth and whose
+// stack heights agree at every jump target.
+//
+// Functions are discovered from offset 0, from the targets of `Call`s, `CallDirect`s and
+// `ReturnCall`s, and from the initial table entries matching the signature of a
+// `CallIndirect` or `ReturnCallIndirect`. Handlers covering a `Throw` or a call belong
+// to the function containing it.
+// The target and n_locals operands of every `Call` must be produced by `I32Const`s so that
+// the callee is known statically.
+#[derive(Copy, Clone, Debug)]
+pub struct VerifiedModule<'a> {
+    module: Module<'a>,
+    info: &'a [CodeInfo]
+}
+
+impl<'a> VerifiedModule<'a> {
+    pub(crate) fn empty() -> VerifiedModule<'static> {
+        VerifiedModule {
+            module: Module {
+                memory_initializers: &[],
+                data_segments: &[],
+                table: &[],
+                handlers: &[],
+                code: &[]
+            },
+            info: &[]
+        }
+    }
+
+    pub fn module(&self) -> Module<'a> {
+        self.module
+    }
+
+    pub fn max_depth(&self, entry: usize) -> ExecuteResult<usize> {
+        match self.info.get(entry) {
+            Some(v) if v.flags & ENTRY != 0 => Ok(v.max_depth as usize),
+            _ => Err(ExecuteError::Bounds)
+        }
+    }
+
+    // Returns `None` if `entry` is not a verified function that returns.
+    pub fn n_results(&self, entry: usize) -> Option<usize> {
+        match self.info.get(entry) {
+            Some(v) if v.flags & ENTRY != 0 && v.flags & RESULTS_KNOWN != 0 => Some(v.n_results as usize),
+            _ => None
+        }
+    }
+
+    // Entry of the function containing the reached instruction at `ip`.
+    pub fn function_at(&self, ip: usize) -> Option<usize> {
+        match self.info.get(ip) {
+            Some(v) if v.flags & REACHED != 0 => Some(v.function as usize),
+            _ => None
+        }
+    }
+
+    // Stack height delta between two reached instructions of the same function.
+    pub fn height_delta(&self, from: usize, to: usize) -> ExecuteResult<isize> {
+        match (self.info.get(from), self.info.get(to)) {
+            (Some(a), Some(b)) if a.flags & b.flags & REACHED != 0 => {
+                Ok(b.height as isize - a.height as isize)
+            },
+            _ => Err(ExecuteError::Bounds)
+        }
+    }
+}
+
+// `info` is caller-provided scratch space and must be at least as long as the code.
+pub fn verify<'a, F>(
+    module: &Module<'a>,
+    info: &'a mut [CodeInfo],
+    native_sig: F
+) -> ExecuteResult<VerifiedModule<'a>> where F: Fn(usize) -> Option<NativeSignature> {
+    let code = module.code;
+    if info.len() < code.len() {
+        return Err(ExecuteError::Bounds);
+    }
+
+    let info = &mut info[0..code.len()];
+    for v in info.iter_mut() {
+        *v = CodeInfo::default();
+    }
+
+    if !code.is_empty() {
+        reach(info, 0, 0, 0, 0, [0; 2])?;
+        info[0].flags |= ENTRY;
+
+        // Forward edges are handled within a single pass; loops and calls to
+        // functions whose result count is not yet known need more passes. A call that is
+        // still pending may have entered a callee before it, so passes continue while
+        // anything dirty has not been stepped.
+        loop {
+            let mut changed = false;
+            let mut n_pending = 0;
+            for ip in 0..code.len() {
+                if info[ip].flags & DIRTY != 0 {
+                    if step(module, info, ip, &native_sig)? {
+                        changed = true;
+                    } else {
+                        n_pending += 1;
+                    }
+                }
+            }
+
+            // Only calls to functions that never return, e.g. because they always throw,
+            // can stay pending. Nothing after them is reached.
+            let n_dirty = info.iter().filter(|v| v.flags & DIRTY != 0).count();
+            if n_dirty == 0 || (!changed && n_dirty == n_pending) {
+                break;
+            }
+        }
+    }
+
+    Ok(VerifiedModule {
+        module: *module,
+        info: info
+    })
+}
+
+fn reach(
+    info: &mut [CodeInfo],
+    target: usize,
+    function: u32,
+    height: u32,
+    known_consts: u8,
+    consts: [u32; 2]
+) -> ExecuteResult<()> {
+    if target >= info.len() {
+        return Err(ExecuteError::InvalidInput);
+    }
+
+    let entry = &mut info[target];
+
+    if entry.flags & REACHED == 0 {
+        entry.flags |= REACHED | DIRTY;
+        entry.function = function;
+        entry.height = height;
+        entry.known_consts = known_consts;
+        entry.consts = consts;
+        return Ok(());
+    }
+
+    if entry.function != function || entry.height != height {
+        return Err(ExecuteError::InvalidInput);
+    }
+
+    let mut known = entry.known_consts & known_consts;
+    for (i, (a, b)) in entry.consts.iter().zip(consts.iter()).enumerate() {
+        if a != b {
+            known &= !(1 << i);
+        }
+    }
+    if known != entry.known_consts {
+        entry.known_consts = known;
+        entry.flags |= DIRTY;
+    }
+
+    Ok(())
+}
+
+fn enter_function(info: &mut [CodeInfo], target: usize) -> ExecuteResult<()> {
+    reach(info, target, target as u32, 0, 0, [0; 2])?;
+    info[target].flags |= ENTRY;
+    Ok(())
+}
+
+// Returns `None` if the callee's result count is not known yet.
+fn callee_results(info: &mut [CodeInfo], target: usize, height: u32, n_pop: u32) -> ExecuteResult<Option<u32>> {
+    if height < n_pop {
+        return Err(ExecuteError::InvalidInput);
+    }
+
+    enter_function(info, target)?;
+    let callee = info[target];
+    if callee.flags & RESULTS_KNOWN == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(callee.n_results))
+    }
+}
+
+// Every initial table entry with the given signature must return the same number of results.
+// Returns `None` if some of them are not known yet.
+fn table_results(
+    module: &Module,
+    info: &mut [CodeInfo],
+    height: u32,
+    n_pop: u32,
+    n_args: u32,
+    n_locals: u32
+) -> ExecuteResult<Option<u32>> {
+    let mut results = None;
+    let mut pending = false;
+    for i in 0..module.table_len() {
+        let entry = match module.table_entry(i)? {
+            Some(v) if v.n_args == n_args && v.n_locals == n_locals => v,
+            _ => continue
+        };
+        match callee_results(info, entry.target as usize, height, n_pop)? {
+            Some(v) => {
+                if results.is_some() && results != Some(v) {
+                    return Err(ExecuteError::InvalidInput);
+                }
+                results = Some(v);
+            },
+            None => pending = true
+        }
+    }
+    if pending {
+        return Ok(None);
+    }
+    results.map(Some).ok_or(ExecuteError::InvalidInput)
+}
+
+// Handlers start with the tag and the payload on the operand stack. `ip` is the last
+// byte of an instruction that may throw.
+fn reach_handlers(module: &Module, info: &mut [CodeInfo], ip: usize, function: u32) -> ExecuteResult<()> {
+    for i in 0..module.handlers_len() {
+        let h = module.handler(i)?;
+        if ip >= h.start as usize && ip < h.end as usize {
+            reach(info, h.target as usize, function, 2, 0, [0; 2])?;
+            update_max_depth(info, function, 2);
+        }
+    }
+    Ok(())
+}
+
+// Records that `function` returns `n_results` values.
+fn set_results(info: &mut [CodeInfo], function: u32, n_results: u32) -> ExecuteResult<()> {
+    let entry = &mut info[function as usize];
+    if entry.flags & RESULTS_KNOWN != 0 {
+        if entry.n_results != n_results {
+            return Err(ExecuteError::InvalidInput);
+        }
+    } else {
+        entry.flags |= RESULTS_KNOWN;
+        entry.n_results = n_results;
+    }
+    Ok(())
+}
+
+fn update_max_depth(info: &mut [CodeInfo], function: u32, height: u32) {
+    let entry = &mut info[function as usize];
+    if height > entry.max_depth {
+        entry.max_depth = height;
+    }
+}
+
+// Returns `Ok(false)` if the instruction cannot be processed yet.
+fn step<F>(
+    module: &Module,
+    info: &mut [CodeInfo],
+    ip: usize,
+    native_sig: &F
+) -> ExecuteResult<bool> where F: Fn(usize) -> Option<NativeSignature> {
+    let cur = info[ip];
+    let function = cur.function;
+    let height = cur.height;
+
+    let tape = Tape::from(module.code);
+    tape.set_pos(ip)?;
+
+    let op = Opcode::from_raw(*tape.next()?)?;
+
+    let mut n_pop: u32 = 0;
+    let mut n_push: u32 = 0;
+    let mut known_consts: u8 = 0;
+    let mut consts: [u32; 2] = [0; 2];
+    let mut fallthrough = true;
+
+    match op {
+        Opcode::Drop => n_pop = 1,
+        Opcode::Dup => {
+            n_pop = 1;
+            n_push = 2;
+        },
+        Opcode::Swap2 => {
+            n_pop = 2;
+            n_push = 2;
+        },
+        Opcode::Select => {
+            n_pop = 3;
+            n_push = 1;
+        },
+        Opcode::Call => {
+            let n_args = tape.next_u32()?;
+            reach_handlers(module, info, tape.get_pos() - 1, function)?;
+
+            if cur.known_consts != 3 {
+                return Err(ExecuteError::InvalidInput);
+            }
+            let target = cur.consts[0] as usize;
+
+            n_pop = n_args.checked_add(2).ok_or(ExecuteError::InvalidInput)?;
+            n_push = match callee_results(info, target, height, n_pop)? {
+                Some(v) => v,
+                None => return Ok(false)
+            };
+        },
+        Opcode::CallDirect => {
+            let target = tape.next_u32()? as usize;
+            n_pop = tape.next_u32()?;
+            tape.next_u32()?;
+            reach_handlers(module, info, tape.get_pos() - 1, function)?;
+
+            n_push = match callee_results(info, target, height, n_pop)? {
+                Some(v) => v,
+                None => return Ok(false)
+            };
+        },
+        Opcode::CallIndirect => {
+            let n_args = tape.next_u32()?;
+            let n_locals = tape.next_u32()?;
+            reach_handlers(module, info, tape.get_pos() - 1, function)?;
+
+            n_pop = n_args.checked_add(1).ok_or(ExecuteError::InvalidInput)?;
+            n_push = match table_results(module, info, height, n_pop, n_args, n_locals)? {
+                Some(v) => v,
+                None => return Ok(false)
+            };
+        },
+        Opcode::ReturnCall => {
+            let target = tape.next_u32()? as usize;
+            n_pop = tape.next_u32()?;
+            tape.next_u32()?;
+
+            if height != n_pop {
+                return Err(ExecuteError::InvalidInput);
+            }
+            match callee_results(info, target, height, n_pop)? {
+                Some(v) => set_results(info, function, v)?,
+                None => return Ok(false)
+            }
+            fallthrough = false;
+        },
+        Opcode::ReturnCallIndirect => {
+            let n_args = tape.next_u32()?;
+            let n_locals = tape.next_u32()?;
+
+            n_pop = n_args.checked_add(1).ok_or(ExecuteError::InvalidInput)?;
+            if height != n_pop {
+                return Err(ExecuteError::InvalidInput);
+            }
+            match table_results(module, info, height, n_pop, n_args, n_locals)? {
+                Some(v) => set_results(info, function, v)?,
+                None => return Ok(false)
+            }
+            fallthrough = false;
+        },
+        Opcode::Return => {
+            set_results(info, function, height)?;
+            fallthrough = false;
+        },
+        Opcode::Throw => {
+            reach_handlers(module, info, ip, function)?;
+            n_pop = 2;
+            fallthrough = false;
+        },
+        Opcode::Halt | Opcode::Unreachable | Opcode::NotSupported => {
+            fallthrough = false;
+        },
+        Opcode::GetLocal => {
+            tape.next_u32()?;
+            n_push = 1;
+        },
+        Opcode::SetLocal => {
+            tape.next_u32()?;
+            n_pop = 1;
+        },
+        Opcode::TeeLocal => {
+            tape.next_u32()?;
+            n_pop = 1;
+            n_push = 1;
+        },
+        Opcode::GetSlotIndirect => {
+            n_pop = 1;
+            n_push = 1;
+        },
+        Opcode::GetSlot => {
+            tape.next_u32()?;
+            n_push = 1;
+        },
+        Opcode::SetSlot => {
+            tape.next_u32()?;
+            n_pop = 1;
+        },
+        Opcode::ResetSlots => {
+            tape.next_u32()?;
+        },
+        Opcode::NativeInvoke => {
+            let id = tape.next_u32()? as usize;
+            let sig = native_sig(id).ok_or(ExecuteError::InvalidNativeInvoke)?;
+            if sig.n_results > 1 {
+                return Err(ExecuteError::InvalidNativeInvoke);
+            }
+            n_pop = sig.n_args as u32;
+            n_push = sig.n_results as u32;
+        },
+        Opcode::CurrentMemory => n_push = 1,
+        Opcode::GrowMemory => {
+            n_pop = 1;
+            n_push = 1;
+        },
+        Opcode::Nop => {},
+        Opcode::Jmp => {
+            let target = tape.next_u32()? as usize;
+            reach(info, target, function, height, cur.known_consts, cur.consts)?;
+            fallthrough = false;
+        },
+        Opcode::JmpIf => {
+            let target = tape.next_u32()? as usize;
+            n_pop = 1;
+            if height < n_pop {
+                return Err(ExecuteError::InvalidInput);
+            }
+            reach(info, target, function, height - n_pop, 0, [0; 2])?;
+        },
+        Opcode::JmpEither => {
+            let target_a = tape.next_u32()? as usize;
+            let target_b = tape.next_u32()? as usize;
+            n_pop = 1;
+            if height < n_pop {
+                return Err(ExecuteError::InvalidInput);
+            }
+            reach(info, target_a, function, height - n_pop, 0, [0; 2])?;
+            reach(info, target_b, function, height - n_pop, 0, [0; 2])?;
+            fallthrough = false;
+        },
+        Opcode::JmpTable => {
+            let default_target = tape.next_u32()? as usize;
+            let table_len = tape.next_u32()? as usize;
+            let table = tape.next_many(table_len.checked_mul(4).ok_or(ExecuteError::Bounds)?)?;
+
+            n_pop = 1;
+            if height < n_pop {
+                return Err(ExecuteError::InvalidInput);
+            }
+
+            reach(info, default_target, function, height - n_pop, 0, [0; 2])?;
+            for i in 0..table_len {
+                let target = LittleEndian::read_u32(&table[i * 4 .. i * 4 + 4]) as usize;
+                reach(info, target, function, height - n_pop, 0, [0; 2])?;
+            }
+            fallthrough = false;
+        },
+        Opcode::I32Const => {
+            let v = tape.next_u32()?;
+            n_push = 1;
+            known_consts = ((cur.known_consts >> 1) & 1) | 2;
+            consts = [cur.consts[1], v];
+        },
+        Opcode::I64Const | Opcode::F64Const => {
+            tape.next_u64()?;
+            n_push = 1;
+        },
+        Opcode::F32Const => {
+            tape.next_u32()?;
+            n_push = 1;
+        },
+        Opcode::I32Load | Opcode::I32Load8U | Opcode::I32Load8S
+            | Opcode::I32Load16U | Opcode::I32Load16S
+            | Opcode::I64Load | Opcode::I64Load8U | Opcode::I64Load8S
+            | Opcode::I64Load16U | Opcode::I64Load16S
+            | Opcode::I64Load32U | Opcode::I64Load32S
+            | Opcode::F32Load | Opcode::F64Load => {
+            tape.next_u32()?;
+            n_pop = 1;
+            n_push = 1;
+        },
+        Opcode::I32Store | Opcode::I32Store8 | Opcode::I32Store16
+            | Opcode::I64Store | Opcode::I64Store8
+            | Opcode::I64Store16 | Opcode::I64Store32
+            | Opcode::F32Store | Opcode::F64Store => {
+            tape.next_u32()?;
+            n_pop = 2;
+        },
+        Opcode::I32Ctz | Opcode::I32Clz | Opcode::I32Popcnt | Opcode::I32WrapI64
+            | Opcode::I64Ctz | Opcode::I64Clz | Opcode::I64Popcnt
+            | Opcode::I64ExtendI32U | Opcode::I64ExtendI32S
+            | Opcode::F32Abs | Opcode::F32Neg | Opcode::F32Ceil | Opcode::F32Floor
+            | Opcode::F32Trunc | Opcode::F32Nearest | Opcode::F32Sqrt
+            | Opcode::F64Abs | Opcode::F64Neg | Opcode::F64Ceil | Opcode::F64Floor
+            | Opcode::F64Trunc | Opcode::F64Nearest | Opcode::F64Sqrt
+            | Opcode::I32TruncF32S | Opcode::I32TruncF32U | Opcode::I32TruncF64S | Opcode::I32TruncF64U
+            | Opcode::I64TruncF32S | Opcode::I64TruncF32U | Opcode::I64TruncF64S | Opcode::I64TruncF64U
+            | Opcode::F32ConvertI32S | Opcode::F32ConvertI32U
+            | Opcode::F32ConvertI64S | Opcode::F32ConvertI64U | Opcode::F32DemoteF64
+            | Opcode::F64ConvertI32S | Opcode::F64ConvertI32U
+            | Opcode::F64ConvertI64S | Opcode::F64ConvertI64U | Opcode::F64PromoteF32
+            | Opcode::I32ReinterpretF32 | Opcode::I64ReinterpretF64
+            | Opcode::F32ReinterpretI32 | Opcode::F64ReinterpretI64
+            | Opcode::I32Extend8S | Opcode::I32Extend16S
+            | Opcode::I64Extend8S | Opcode::I64Extend16S | Opcode::I64Extend32S => {
+            n_pop = 1;
+            n_push = 1;
+        },
+        Opcode::I32Add | Opcode::I32Sub | Opcode::I32Mul
+            | Opcode::I32DivU | Opcode::I32DivS | Opcode::I32RemU | Opcode::I32RemS
+            | Opcode::I32And | Opcode::I32Or | Opcode::I32Xor
+            | Opcode::I32Shl | Opcode::I32ShrU | Opcode::I32ShrS
+            | Opcode::I32Rotl | Opcode::I32Rotr
+            | Opcode::I32Eq | Opcode::I32Ne
+            | Opcode::I32LtU | Opcode::I32LtS | Opcode::I32LeU | Opcode::I32LeS
+            | Opcode::I32GtU | Opcode::I32GtS | Opcode::I32GeU | Opcode::I32GeS
+            | Opcode::I64Add | Opcode::I64Sub | Opcode::I64Mul
+            | Opcode::I64DivU | Opcode::I64DivS | Opcode::I64RemU | Opcode::I64RemS
+            | Opcode::I64And | Opcode::I64Or | Opcode::I64Xor
+            | Opcode::I64Shl | Opcode::I64ShrU | Opcode::I64ShrS
+            | Opcode::I64Rotl | Opcode::I64Rotr
+            | Opcode::I64Eq | Opcode::I64Ne
+            | Opcode::I64LtU | Opcode::I64LtS | Opcode::I64LeU | Opcode::I64LeS
+            | Opcode::I64GtU | Opcode::I64GtS | Opcode::I64GeU | Opcode::I64GeS
+            | Opcode::F32Add | Opcode::F32Sub | Opcode::F32Mul | Opcode::F32Div
+            | Opcode::F32Min | Opcode::F32Max | Opcode::F32Copysign
+            | Opcode::F32Eq | Opcode::F32Ne | Opcode::F32Lt | Opcode::F32Gt | Opcode::F32Le | Opcode::F32Ge
+            | Opcode::F64Add | Opcode::F64Sub | Opcode::F64Mul | Opcode::F64Div
+            | Opcode::F64Min | Opcode::F64Max | Opcode::F64Copysign
+            | Opcode::F64Eq | Opcode::F64Ne | Opcode::F64Lt | Opcode::F64Gt | Opcode::F64Le | Opcode::F64Ge
+            | Opcode::I32AddCheckedS | Opcode::I32AddCheckedU | Opcode::I32SubCheckedS | Opcode::I32SubCheckedU
+            | Opcode::I32MulCheckedS | Opcode::I32MulCheckedU
+            | Opcode::I64AddCheckedS | Opcode::I64AddCheckedU | Opcode::I64SubCheckedS | Opcode::I64SubCheckedU
+            | Opcode::I64MulCheckedS | Opcode::I64MulCheckedU => {
+            n_pop = 2;
+            n_push = 1;
+        },
+        Opcode::I32AddOverflowingS | Opcode::I32AddOverflowingU
+            | Opcode::I32SubOverflowingS | Opcode::I32SubOverflowingU
+            | Opcode::I32MulOverflowingS | Opcode::I32MulOverflowingU
+            | Opcode::I64AddOverflowingS | Opcode::I64AddOverflowingU
+            | Opcode::I64SubOverflowingS | Opcode::I64SubOverflowingU
+            | Opcode::I64MulOverflowingS | Opcode::I64MulOverflowingU
+            | Opcode::I64MulWideS | Opcode::I64MulWideU => {
+            n_pop = 2;
+            n_push = 2;
+        },
+        Opcode::I64AddWithCarry | Opcode::I64SubWithBorrow => {
+            n_pop = 3;
+            n_push = 2;
+        },
+        Opcode::MemCopy | Opcode::MemFill => n_pop = 3,
+        Opcode::I32AtomicLoad | Opcode::I64AtomicLoad => {
+            tape.next_u32()?;
+            n_pop = 1;
+            n_push = 1;
+        },
+        Opcode::I32AtomicStore | Opcode::I64AtomicStore => {
+            tape.next_u32()?;
+            n_pop = 2;
+        },
+        Opcode::I32AtomicRmwAdd | Opcode::I32AtomicRmwSub | Opcode::I32AtomicRmwAnd
+            | Opcode::I32AtomicRmwOr | Opcode::I32AtomicRmwXor | Opcode::I32AtomicRmwXchg
+            | Opcode::I64AtomicRmwAdd | Opcode::I64AtomicRmwSub | Opcode::I64AtomicRmwAnd
+            | Opcode::I64AtomicRmwOr | Opcode::I64AtomicRmwXor | Opcode::I64AtomicRmwXchg
+            | Opcode::MemoryAtomicNotify => {
+            tape.next_u32()?;
+            n_pop = 2;
+            n_push = 1;
+        },
+        Opcode::I32AtomicRmwCmpxchg | Opcode::I64AtomicRmwCmpxchg
+            | Opcode::MemoryAtomicWait32 | Opcode::MemoryAtomicWait64 => {
+            tape.next_u32()?;
+            n_pop = 3;
+            n_push = 1;
+        },
+        Opcode::AtomicFence => {},
+        #[cfg(feature = "simd")]
+        Opcode::Simd => {
+            let op = SimdOpcode::from_raw(*tape.next()?)?;
+            tape.next_many(op.immediate_len())?;
+            let (a, b) = op.stack_effect();
+            n_pop = a;
+            n_push = b;
+        },
+        #[cfg(not(feature = "simd"))]
+        Opcode::Simd => {
+            return Err(ExecuteError::NotSupported);
+        },
+        Opcode::MemoryInit => {
+            tape.next_u32()?;
+            n_pop = 3;
+        },
+        Opcode::Never => {
+            return Err(ExecuteError::IllegalOpcode);
+        }
+    }
+
+    if height < n_pop {
+        return Err(ExecuteError::InvalidInput);
+    }
+    let new_height = height - n_pop + n_push;
+    update_max_depth(info, function, new_height);
+
+    info[ip].flags &= !DIRTY;
+
+    if fallthrough {
+        reach(info, tape.get_pos(), function, new_height, known_consts, consts)?;
+    }
+
+    Ok(true)
+}
@@ -1,6 +1,13 @@
+use core::cell::Cell;
+use core::sync::atomic::{fence, Ordering};
 use environment::Environment;
 use module::{Module, Opcode};
 use tape::{Tape, TapeU8};
+use verifier::VerifiedModule;
+use mmio::{find_region, overlaps_any};
+use shared_memory::AtomicRmwOp;
+#[cfg(feature = "simd")]
+use simd::{self, SimdOpcode, Lane};
 use byteorder::{LittleEndian, ByteOrder};
 use error::*;
 
@@ -8,177 +15,994 @@ pub struct VirtualMachine<'a, E: Environment> {
     pub module: Module<'a>,
     pub env: E,
 
+    pub limits: Limits,
+
+    verified: Option<VerifiedModule<'a>>,
     reset_slots_fuse: bool
 }
 
+// Enforced by the VM regardless of the environment, which only supplies the initial
+// ones through `Environment::get_limits`. Exceeding `max_memory` (in bytes)
+// or `max_slots` fails with `ExecuteError::MemoryLimit` / `SlotLimit`; exceeding
+// `max_call_depth` (in frames pushed by a `run`, not counting those already on the call
+// stack) or `max_locals` (n_args + n_locals of a frame) fails with
+// `ExecuteError::ExecutionLimit`.
+#[derive(Copy, Clone, Debug)]
+pub struct Limits {
+    pub max_memory: usize,
+    pub max_slots: usize,
+    pub max_call_depth: usize,
+    pub max_locals: usize
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_memory: usize::MAX,
+            max_slots: usize::MAX,
+            max_call_depth: usize::MAX,
+            max_locals: usize::MAX
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct ExecutionState {
     pub sp: usize,
     pub ip: usize
 }
 
+trait StackAccess {
+    const VERIFIED: bool;
+}
+
+struct Checked;
+
+impl StackAccess for Checked {
+    const VERIFIED: bool = false;
+}
+
+// Stack headroom is checked once on function entry against the verified maximum depth,
+// and the verifier guarantees that a function never pops below its entry height.
+struct Verified;
+
+impl StackAccess for Verified {
+    const VERIFIED: bool = true;
+}
+
+// Locals of the current call frame, kept in sync with the call stack on `Call`/`Return`
+// so that local access does not need to walk the frame layout.
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    base: usize,
+    n_all_locals: usize
+}
+
+impl Frame {
+    fn current(cs: &Tape<Cell<i64>>) -> ExecuteResult<Frame> {
+        if cs.get_pos() < 2 {
+            return Ok(Frame {
+                base: 0,
+                n_all_locals: 0
+            });
+        }
+
+        let n_all_locals = cs.tail_many(2)?[0].get() as usize;
+        let base = n_all_locals.checked_add(2)
+            .and_then(|v| cs.get_pos().checked_sub(v))
+            .ok_or(ExecuteError::Bounds)?;
+
+        Ok(Frame {
+            base: base,
+            n_all_locals: n_all_locals
+        })
+    }
+}
+
+// Call stack position where the frame ending at `pos` starts.
+fn frame_start(cs: &Tape<Cell<i64>>, pos: usize) -> ExecuteResult<usize> {
+    let n_all_locals = cs.at(pos.checked_sub(2).ok_or(ExecuteError::Bounds)?)?.get() as usize;
+    n_all_locals.checked_add(2)
+        .and_then(|v| pos.checked_sub(v))
+        .ok_or(ExecuteError::Bounds)
+}
+
+// Interpreter registers. While the operand stack is not empty, `tos` holds its top value
+// and the top cell of the stack tape is stale; the tape position is always accurate.
+// The cell is written back before anything that can observe the tape, and read again
+// after anything that can change it.
+struct Registers {
+    frame: Frame,
+    depth: usize, // number of frames pushed since `run` was entered
+    tos: i64,
+    entry_height: usize, // operand stack height when `run` was entered
+    track_frames: bool // whether the frame stack is kept, i.e. the module has handlers and the environment a frame stack
+}
+
+impl Registers {
+    #[inline(always)]
+    fn pop<S: StackAccess>(&mut self, stack: &Tape<Cell<i64>>) -> ExecuteResult<i64> {
+        let pos = stack.get_pos();
+        if !S::VERIFIED && pos == 0 {
+            return Err(ExecuteError::Bounds);
+        }
+
+        let v = self.tos;
+        if pos > 1 {
+            self.tos = stack.at_unchecked(pos - 2).get();
+        }
+        stack.set_pos_unchecked(pos - 1);
+
+        Ok(v)
+    }
+
+    #[inline(always)]
+    fn push<S: StackAccess>(&mut self, stack: &Tape<Cell<i64>>, v: i64) -> ExecuteResult<()> {
+        let pos = stack.get_pos();
+        if !S::VERIFIED && pos >= stack.len() {
+            return Err(ExecuteError::Bounds);
+        }
+
+        if pos > 0 {
+            stack.at_unchecked(pos - 1).set(self.tos);
+        }
+        stack.set_pos_unchecked(pos + 1);
+        self.tos = v;
+
+        Ok(())
+    }
+
+    // `take1` and `take2` pop operands whose result is stored with `put` right after,
+    // without anything in between that can fail or observe the stack.
+    #[inline(always)]
+    fn take1<S: StackAccess>(&mut self, stack: &Tape<Cell<i64>>) -> ExecuteResult<i64> {
+        if !S::VERIFIED && stack.get_pos() == 0 {
+            return Err(ExecuteError::Bounds);
+        }
+        Ok(self.tos)
+    }
+
+    #[inline(always)]
+    fn take2<S: StackAccess>(&mut self, stack: &Tape<Cell<i64>>) -> ExecuteResult<(i64, i64)> {
+        let pos = stack.get_pos();
+        if !S::VERIFIED && pos < 2 {
+            return Err(ExecuteError::Bounds);
+        }
+
+        let a = stack.at_unchecked(pos - 2).get();
+        stack.set_pos_unchecked(pos - 1);
+
+        Ok((a, self.tos))
+    }
+
+    #[inline(always)]
+    fn put(&mut self, v: i64) {
+        self.tos = v;
+    }
+
+    #[inline(always)]
+    fn flush(&mut self, stack: &Tape<Cell<i64>>) {
+        let pos = stack.get_pos();
+        if pos > 0 {
+            stack.at_unchecked(pos - 1).set(self.tos);
+        }
+    }
+
+    #[inline(always)]
+    fn reload(&mut self, stack: &Tape<Cell<i64>>) {
+        let pos = stack.get_pos();
+        if pos > 0 {
+            self.tos = stack.at_unchecked(pos - 1).get();
+        }
+    }
+
+    #[inline(always)]
+    fn flush_for_trace<E: Environment>(&mut self, env: &E) {
+        if E::TRACE_STACK {
+            self.flush(env.get_stack());
+        }
+    }
+
+    #[inline(always)]
+    fn reload_after_trace<E: Environment>(&mut self, env: &E) {
+        if E::TRACE_STACK {
+            self.reload(env.get_stack());
+        }
+    }
+}
+
+fn check_headroom(stack: &Tape<Cell<i64>>, verified: &VerifiedModule, entry: usize) -> ExecuteResult<()> {
+    if stack.remaining() < verified.max_depth(entry)? {
+        Err(ExecuteError::Bounds)
+    } else {
+        Ok(())
+    }
+}
+
+// Conversion between stack cells and typed values. Integers are truncated or extended;
+// floats are stored as their bit patterns, zero-extended to 64 bits.
+trait StackValue {
+    fn from_cell(v: i64) -> Self;
+    fn to_cell(self) -> i64;
+}
+
+macro_rules! impl_int_stack_value {
+    ($t:ty) => {
+        impl StackValue for $t {
+            #[inline(always)]
+            fn from_cell(v: i64) -> $t {
+                v as $t
+            }
+
+            #[inline(always)]
+            fn to_cell(self) -> i64 {
+                self as i64
+            }
+        }
+    }
+}
+
+impl_int_stack_value!(i32);
+impl_int_stack_value!(u32);
+impl_int_stack_value!(i64);
+impl_int_stack_value!(u64);
+
+impl StackValue for f32 {
+    #[inline(always)]
+    fn from_cell(v: i64) -> f32 {
+        f32::from_bits(v as u32)
+    }
+
+    #[inline(always)]
+    fn to_cell(self) -> i64 {
+        self.to_bits() as i64
+    }
+}
+
+impl StackValue for f64 {
+    #[inline(always)]
+    fn from_cell(v: i64) -> f64 {
+        f64::from_bits(v as u64)
+    }
+
+    #[inline(always)]
+    fn to_cell(self) -> i64 {
+        self.to_bits() as i64
+    }
+}
+
+// Unlike `f32::min`/`f32::max`, NaN operands propagate and -0.0 is less than +0.0.
+macro_rules! float_min {
+    ($a:expr, $b:expr) => {
+        {
+            let (a, b) = ($a, $b);
+            if a.is_nan() || b.is_nan() {
+                a + b
+            } else if a == b {
+                if a.is_sign_negative() { a } else { b }
+            } else if a < b {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+macro_rules! float_max {
+    ($a:expr, $b:expr) => {
+        {
+            let (a, b) = ($a, $b);
+            if a.is_nan() || b.is_nan() {
+                a + b
+            } else if a == b {
+                if a.is_sign_positive() { a } else { b }
+            } else if a > b {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+// Truncates `v` towards zero if the result lies in `(lower, upper)`. Both bounds are
+// exactly representable as f64, and so is every f32.
+macro_rules! float_trunc {
+    ($v:expr, $t:ty, $lower:expr, $upper:expr) => {
+        {
+            let v: f64 = $v;
+            if v > $lower && v < $upper {
+                Ok(::core::intrinsics::truncf64(v) as $t)
+            } else {
+                Err(ExecuteError::InvalidConversion)
+            }
+        }
+    }
+}
+
 macro_rules! pop1 {
-    ($env:expr) => {
-        $env.get_stack().prev()?.get()
+    ($s:ty, $env:expr, $regs:expr) => {
+        $regs.pop::<$s>($env.get_stack())?
     }
 }
 
 macro_rules! pop2 {
-    ($env:expr) => {
+    ($s:ty, $env:expr, $regs:expr) => {
         {
             let stack = $env.get_stack();
-            let b = stack.prev()?;
-            let a = stack.prev()?;
-            (a.get(), b.get())
+            let b = $regs.pop::<$s>(stack)?;
+            let a = $regs.pop::<$s>(stack)?;
+            (a, b)
         }
     }
 }
 
 macro_rules! pop3 {
-    ($env:expr) => {
+    ($s:ty, $env:expr, $regs:expr) => {
         {
             let stack = $env.get_stack();
-            let c = stack.prev()?;
-            let b = stack.prev()?;
-            let a = stack.prev()?;
-            (a.get(), b.get(), c.get())
+            let c = $regs.pop::<$s>(stack)?;
+            let b = $regs.pop::<$s>(stack)?;
+            let a = $regs.pop::<$s>(stack)?;
+            (a, b, c)
         }
     }
 }
 
 macro_rules! push1 {
-    ($env:expr, $v:expr) => {
+    ($s:ty, $env:expr, $regs:expr, $v:expr) => {
         {
             let v = $v;
+            $regs.push::<$s>($env.get_stack(), v)?;
+        }
+    }
+}
 
-            let stack = $env.get_stack();
-            let location = stack.next()?;
-            location.set(v);
+macro_rules! take1 {
+    ($s:ty, $env:expr, $regs:expr) => {
+        $regs.take1::<$s>($env.get_stack())?
+    }
+}
+
+macro_rules! take2 {
+    ($s:ty, $env:expr, $regs:expr) => {
+        $regs.take2::<$s>($env.get_stack())?
+    }
+}
+
+macro_rules! put {
+    ($regs:expr, $v:expr) => {
+        {
+            let v = $v;
+            $regs.put(v);
+        }
+    }
+}
+
+macro_rules! flush {
+    ($env:expr, $regs:expr) => {
+        $regs.flush($env.get_stack())
+    }
+}
+
+macro_rules! reload {
+    ($env:expr, $regs:expr) => {
+        $regs.reload($env.get_stack())
+    }
+}
+
+
+// Host callbacks can move the operand stack through its cells. Verified code relies on
+// the heights computed by the verifier, so there a callback that changes the height
+// fails with `ExecuteError::Bounds`.
+macro_rules! host_call {
+    ($s:ty, $env:expr, $call:expr) => {
+        {
+            let sp = $env.get_stack().get_pos();
+            let ret = $call;
+            if <$s as StackAccess>::VERIFIED && $env.get_stack().get_pos() != sp {
+                return Err(ExecuteError::Bounds);
+            }
+            ret
         }
     }
 }
 
-macro_rules! extract_locals {
-    ($cs:expr) => {
+// Calls a `trace_*` hook, which can see and change the operand stack if the environment
+// traces it.
+macro_rules! trace {
+    ($s:ty, $env:expr, $regs:expr, $call:expr) => {
         {
-            let n_all_locals = $cs.tail_many(2)?[0].get() as usize;
-            &$cs.tail_many(n_all_locals + 2)?[0..n_all_locals]
+            $regs.flush_for_trace(&$env);
+            let ret = host_call!($s, $env, $call);
+            $regs.reload_after_trace(&$env);
+            ret
         }
     }
 }
 
-macro_rules! get_local {
-    ($env:expr, $id:expr) => {
+macro_rules! local_at {
+    ($env:expr, $frame:expr, $id:expr) => {
         {
             let id = $id;
-            let cs = $env.get_call_stack();
-            let locals = extract_locals!(cs);
-
-            if id >= locals.len() {
+            if id >= $frame.n_all_locals {
                 return Err(ExecuteError::Bounds);
             }
+            $env.get_call_stack().at($frame.base + id)?
+        }
+    }
+}
 
-            push1!($env, locals[id].get());
+macro_rules! get_local {
+    ($s:ty, $env:expr, $regs:expr, $id:expr) => {
+        {
+            let val = local_at!($env, $regs.frame, $id).get();
+            push1!($s, $env, $regs, val);
         }
     }
 }
 
 macro_rules! set_local {
-    ($env:expr, $id:expr) => {
+    ($s:ty, $env:expr, $regs:expr, $id:expr) => {
         {
-            let id = $id;
+            let val = pop1!($s, $env, $regs);
+            local_at!($env, $regs.frame, $id).set(val);
+        }
+    }
+}
+
+macro_rules! tee_local {
+    ($s:ty, $env:expr, $regs:expr, $id:expr) => {
+        {
+            let val = take1!($s, $env, $regs);
+            local_at!($env, $regs.frame, $id).set(val);
+        }
+    }
+}
+
+#[cfg(feature = "guard-memory")]
+macro_rules! read_memory {
+    ($env:expr, $read:ident, $addr:expr) => {
+        match $env.get_guarded_memory() {
+            Some(mem) => mem.$read($addr)?,
+            None => unguarded_memory_op!($env, get_memory, $read($addr))
+        }
+    }
+}
+
+#[cfg(not(feature = "guard-memory"))]
+macro_rules! read_memory {
+    ($env:expr, $read:ident, $addr:expr) => {
+        unguarded_memory_op!($env, get_memory, $read($addr))
+    }
+}
+
+#[cfg(feature = "guard-memory")]
+macro_rules! write_memory {
+    ($env:expr, $write:ident, $addr:expr, $v:expr) => {
+        match $env.get_guarded_memory_mut() {
+            Some(mem) => mem.$write($addr, $v)?,
+            None => unguarded_memory_op!($env, get_memory_mut, $write($addr, $v))
+        }
+    }
+}
+
+#[cfg(not(feature = "guard-memory"))]
+macro_rules! write_memory {
+    ($env:expr, $write:ident, $addr:expr, $v:expr) => {
+        unguarded_memory_op!($env, get_memory_mut, $write($addr, $v))
+    }
+}
+
+// Runs a fallible `Memory` / `AtomicMemory` operation on the shared memory if there is one,
+// or on the slice returned by `$get` otherwise.
+#[cfg(feature = "shared-memory")]
+macro_rules! unguarded_memory_op {
+    ($env:expr, $get:ident, $op:ident($($arg:expr),*)) => {
+        match $env.get_shared_memory() {
+            Some(mem) => mem.$op($($arg),*)?,
+            None => $env.$get().$op($($arg),*)?
+        }
+    }
+}
+
+#[cfg(not(feature = "shared-memory"))]
+macro_rules! unguarded_memory_op {
+    ($env:expr, $get:ident, $op:ident($($arg:expr),*)) => {
+        $env.$get().$op($($arg),*)?
+    }
+}
+
+#[cfg(feature = "shared-memory")]
+macro_rules! memory_len {
+    ($env:expr) => {
+        match $env.get_shared_memory() {
+            Some(mem) => mem.len(),
+            None => $env.get_memory().len()
+        }
+    }
+}
+
+#[cfg(not(feature = "shared-memory"))]
+macro_rules! memory_len {
+    ($env:expr) => {
+        $env.get_memory().len()
+    }
+}
+
+macro_rules! check_locals_limit {
+    ($limits:expr, $n_args:expr, $n_locals:expr) => {
+        match $n_args.checked_add($n_locals) {
+            Some(v) if v <= $limits.max_locals => {},
+            _ => return Err(ExecuteError::ExecutionLimit)
+        }
+    }
+}
+
+macro_rules! call {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $verified:expr, $limits:expr, $target:expr, $n_args:expr, $n_locals:expr) => {
+        {
+            let target = $target;
+            let n_args = $n_args;
+            let n_locals = $n_locals;
+
+            if $regs.depth >= $limits.max_call_depth {
+                return Err(ExecuteError::ExecutionLimit);
+            }
+            check_locals_limit!($limits, n_args, n_locals);
+
+            flush!($env, $regs);
+
+            host_call!($s, $env, $env.trace_call(target, n_locals));
+            host_call!($s, $env, $env.trace_branch(target))?;
+
+            let vs = $env.get_stack();
             let cs = $env.get_call_stack();
-            let locals = extract_locals!(cs);
 
-            if id >= locals.len() {
-                return Err(ExecuteError::Bounds);
+            let args = vs.prev_many(n_args)?;
+            reload!($env, $regs);
+
+            let base = cs.get_pos();
+
+            // [all_locals]
+            for arg in args {
+                cs.next()?.set(arg.get());
             }
+            for _ in 0..n_locals {
+                cs.next()?.set(0);
+            }
+
+            // n_all_locals
+            cs.next()?.set((n_args + n_locals) as _);
 
-            locals[id].set(pop1!($env));
+            // return_ip
+            cs.next()?.set($code.get_pos() as _);
+
+            if let (true, Some(fs)) = ($regs.track_frames, $env.get_frame_stack()) {
+                fs.next()?.set(vs.get_pos() as _);
+            }
+
+            $regs.frame = Frame {
+                base: base,
+                n_all_locals: n_args + n_locals
+            };
+            $regs.depth += 1;
+
+            if <$s as StackAccess>::VERIFIED {
+                check_headroom(vs, $verified, target)?;
+            }
+
+            // Jump!
+            $code.set_pos(target)?;
         }
     }
 }
 
-macro_rules! tee_local {
-    ($env:expr, $id:expr) => {
+// Like `call!`, but replaces the current frame and keeps its return_ip.
+macro_rules! tail_call {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $verified:expr, $limits:expr, $target:expr, $n_args:expr, $n_locals:expr) => {
         {
-            let id = $id;
+            let target = $target;
+            let n_args = $n_args;
+            let n_locals = $n_locals;
+
+            check_locals_limit!($limits, n_args, n_locals);
+
+            flush!($env, $regs);
+
+            host_call!($s, $env, $env.trace_call(target, n_locals));
+            host_call!($s, $env, $env.trace_branch(target))?;
+
+            let vs = $env.get_stack();
             let cs = $env.get_call_stack();
-            let locals = extract_locals!(cs);
 
-            if id >= locals.len() {
-                return Err(ExecuteError::Bounds);
+            let return_ip = cs.prev()?.get();
+            let n_all_locals = cs.prev()?.get();
+            cs.prev_many(n_all_locals as _)?;
+
+            let args = vs.prev_many(n_args)?;
+            reload!($env, $regs);
+
+            // Frames that were on the call stack before `run` have no entry.
+            if let (true, true, Some(fs)) = ($regs.track_frames, $regs.depth > 0, $env.get_frame_stack()) {
+                fs.prev()?;
+                fs.next()?.set(vs.get_pos() as _);
+            }
+
+            let base = cs.get_pos();
+
+            // [all_locals]
+            for arg in args {
+                cs.next()?.set(arg.get());
+            }
+            for _ in 0..n_locals {
+                cs.next()?.set(0);
+            }
+
+            // n_all_locals
+            cs.next()?.set((n_args + n_locals) as _);
+
+            // return_ip
+            cs.next()?.set(return_ip);
+
+            $regs.frame = Frame {
+                base: base,
+                n_all_locals: n_args + n_locals
+            };
+
+            if <$s as StackAccess>::VERIFIED {
+                check_headroom(vs, $verified, target)?;
             }
 
-            locals[id].set($env.get_stack().tail_many(1)?[0].get());
+            $code.set_pos(target)?;
+        }
+    }
+}
+
+// The MMIO region that an access falls into. Environments without regions only pay for
+// the emptiness check.
+macro_rules! mmio_region {
+    ($env:expr, $addr:expr, $width:expr) => {
+        {
+            let regions = $env.get_mmio_regions();
+            if regions.is_empty() {
+                None
+            } else {
+                find_region(regions, $addr, $width)?
+            }
+        }
+    }
+}
+
+// Fails before anything is accessed if `$len` bytes at `$addr` touch an MMIO region,
+// for operations that cannot be split into device accesses.
+macro_rules! check_no_mmio {
+    ($env:expr, $addr:expr, $len:expr) => {
+        if overlaps_any($env.get_mmio_regions(), $addr, $len) {
+            return Err(ExecuteError::MmioAccess);
+        }
+    }
+}
+
+// Reads `$width` bytes at `$addr` from the MMIO region there, or from memory with `$read`.
+macro_rules! read_or_mmio {
+    ($s:ty, $env:expr, $regs:expr, $addr:expr, $width:expr, $read:ident) => {
+        {
+            let addr = $addr;
+            let width = $width;
+            match mmio_region!($env, addr, width) {
+                Some(region) => {
+                    flush!($env, $regs);
+                    let ret = host_call!($s, $env, $env.mmio_read(region, addr - region.start, width));
+                    reload!($env, $regs);
+                    ret?
+                },
+                None => read_memory!($env, $read, addr) as u64
+            }
+        }
+    }
+}
+
+macro_rules! write_or_mmio {
+    ($s:ty, $env:expr, $regs:expr, $addr:expr, $width:expr, $write:ident, $v:expr) => {
+        {
+            let addr = $addr;
+            let width = $width;
+            let v = $v;
+            match mmio_region!($env, addr, width) {
+                Some(region) => {
+                    flush!($env, $regs);
+                    let ret = host_call!($s, $env, $env.mmio_write(region, addr - region.start, width, v as u64));
+                    reload!($env, $regs);
+                    ret?;
+                },
+                None => {
+                    write_memory!($env, $write, addr, v);
+                }
+            }
         }
     }
 }
 
 macro_rules! load_val {
-    ($env:expr, $code:expr, $t1: ty, $t2: ty, $read:ident) => {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $t1: ty, $t2: ty, $read:ident) => {
         let offset = $code.next_u32()? as usize;
-        let addr = pop1!($env) as u32 as usize;
+        let addr = pop1!($s, $env, $regs) as u32 as usize;
 
-        let real_addr = offset + addr;
-        let val = $env.get_memory().$read(real_addr)? as $t1 as $t2;
-        $env.trace_load(offset, addr, val as u64);
-        push1!($env, val as u64 as _);
+        let width = ::core::mem::size_of::<$t1>();
+        let val = read_or_mmio!($s, $env, $regs, offset + addr, width, $read) as $t1 as $t2;
+        trace!($s, $env, $regs, $env.trace_load(offset, addr, val as u64));
+        push1!($s, $env, $regs, val as u64 as _);
     }
 }
 
 macro_rules! store_val {
-    ($env:expr, $code:expr, $write:ident) => {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $t:ty, $write:ident) => {
         let offset = $code.next_u32()? as usize;
-        let val = pop1!($env) as u64 as _;
-        let addr = pop1!($env) as u32 as usize;
+        let val = pop1!($s, $env, $regs) as u64 as $t;
+        let addr = pop1!($s, $env, $regs) as u32 as usize;
 
-        let real_addr = offset + addr;
-        $env.get_memory_mut().$write(real_addr, val)?;
+        let width = ::core::mem::size_of::<$t>();
+        write_or_mmio!($s, $env, $regs, offset + addr, width, $write, val);
     }
 }
 
 macro_rules! run_unop {
-    ($env:expr, $t:ty, $body:expr) => {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
         {
-            let v = pop1!($env);
+            let v = take1!($s, $env, $regs);
             let result = ($body)(v as $t) as $t;
-            push1!($env, result as u64 as i64);
+            put!($regs, result as u64 as i64);
         }
     }
 }
 
 macro_rules! run_binop_checking_div_by_zero {
-    ($env:expr, $t:ty, $body:expr) => {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
         {
-            let (left, right) = pop2!($env);
+            let (left, right) = pop2!($s, $env, $regs);
 
             if (right as $t) == 0 {
                 return Err(ExecuteError::DivideByZero);
             }
 
             let result = ($body)(left as $t, right as $t) as $t;
-            push1!($env, result as u64 as i64);
+            push1!($s, $env, $regs, result as u64 as i64);
+        }
+    }
+}
+
+macro_rules! run_binop_checking_overflow {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
+        {
+            let (left, right) = pop2!($s, $env, $regs);
+
+            let result: $t = match ($body)(left as $t, right as $t) {
+                Some(v) => v,
+                None => return Err(ExecuteError::IntegerOverflow)
+            };
+            push1!($s, $env, $regs, result as u64 as i64);
+        }
+    }
+}
+
+macro_rules! run_binop_with_overflow_flag {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
+        {
+            let (left, right) = pop2!($s, $env, $regs);
+
+            let (result, overflow): ($t, bool) = ($body)(left as $t, right as $t);
+            push1!($s, $env, $regs, result as u64 as i64);
+            push1!($s, $env, $regs, if overflow { 1 } else { 0 });
         }
     }
 }
 
 macro_rules! run_binop {
-    ($env:expr, $t:ty, $body:expr) => {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
         {
-            let (left, right) = pop2!($env);
+            let (left, right) = take2!($s, $env, $regs);
             let result = ($body)(left as $t, right as $t) as $t;
-            push1!($env, result as u64 as i64);
+            put!($regs, result as u64 as i64);
         }
     }
 }
 
 macro_rules! run_relop {
-    ($env:expr, $t:ty, $body:expr) => {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
         {
-            let (left, right) = pop2!($env);
+            let (left, right) = take2!($s, $env, $regs);
             let result = ($body)(left as $t, right as $t);
-            push1!($env, if result == true { 1 } else { 0 });
+            put!($regs, if result == true { 1 } else { 0 });
+        }
+    }
+}
+
+macro_rules! run_float_unop {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
+        run_convert!($s, $env, $regs, $t, $t, $body)
+    }
+}
+
+macro_rules! run_float_binop {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
+        {
+            let (left, right) = take2!($s, $env, $regs);
+            let result: $t = ($body)(<$t>::from_cell(left), <$t>::from_cell(right));
+            put!($regs, result.to_cell());
+        }
+    }
+}
+
+macro_rules! run_float_relop {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
+        {
+            let (left, right) = take2!($s, $env, $regs);
+            let result = ($body)(<$t>::from_cell(left), <$t>::from_cell(right));
+            put!($regs, if result == true { 1 } else { 0 });
+        }
+    }
+}
+
+macro_rules! run_convert {
+    ($s:ty, $env:expr, $regs:expr, $from:ty, $to:ty, $body:expr) => {
+        {
+            let v = take1!($s, $env, $regs);
+            let result: $to = ($body)(<$from>::from_cell(v));
+            put!($regs, result.to_cell());
+        }
+    }
+}
+
+// Like `run_convert`, but `$body` returns an `ExecuteResult`.
+macro_rules! run_trapping_convert {
+    ($s:ty, $env:expr, $regs:expr, $from:ty, $to:ty, $body:expr) => {
+        {
+            let v = take1!($s, $env, $regs);
+            let result: $to = ($body)(<$from>::from_cell(v))?;
+            put!($regs, result.to_cell());
+        }
+    }
+}
+
+macro_rules! atomic_load {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $load:ident, $width:expr) => {
+        {
+            let offset = $code.next_u32()? as usize;
+            let addr = pop1!($s, $env, $regs) as u32 as usize;
+
+            check_no_mmio!($env, offset + addr, $width);
+            let val = unguarded_memory_op!($env, get_memory_mut, $load(offset + addr));
+            push1!($s, $env, $regs, val as i64);
+        }
+    }
+}
+
+macro_rules! atomic_store {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $store:ident, $width:expr) => {
+        {
+            let offset = $code.next_u32()? as usize;
+            let (addr, val) = pop2!($s, $env, $regs);
+            let addr = addr as u32 as usize;
+
+            check_no_mmio!($env, offset + addr, $width);
+            unguarded_memory_op!($env, get_memory_mut, $store(offset + addr, val as u64 as _));
+        }
+    }
+}
+
+macro_rules! atomic_rmw {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $rmw:ident, $width:expr, $op:expr) => {
+        {
+            let offset = $code.next_u32()? as usize;
+            let (addr, val) = pop2!($s, $env, $regs);
+            let addr = addr as u32 as usize;
+
+            check_no_mmio!($env, offset + addr, $width);
+            let old = unguarded_memory_op!($env, get_memory_mut, $rmw(offset + addr, $op, val as u64 as _));
+            push1!($s, $env, $regs, old as i64);
+        }
+    }
+}
+
+macro_rules! atomic_cmpxchg {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $cmpxchg:ident, $width:expr) => {
+        {
+            let offset = $code.next_u32()? as usize;
+            let (addr, expected, replacement) = pop3!($s, $env, $regs);
+            let addr = addr as u32 as usize;
+
+            check_no_mmio!($env, offset + addr, $width);
+            let old = unguarded_memory_op!(
+                $env, get_memory_mut,
+                $cmpxchg(offset + addr, expected as u64 as _, replacement as u64 as _)
+            );
+            push1!($s, $env, $regs, old as i64);
+        }
+    }
+}
+
+macro_rules! atomic_wait {
+    ($s:ty, $env:expr, $regs:expr, $code:expr, $t:ty) => {
+        {
+            let offset = $code.next_u32()? as usize;
+            let (addr, expected, timeout) = pop3!($s, $env, $regs);
+            let ra = offset + addr as u32 as usize;
+            let width = ::core::mem::size_of::<$t>();
+
+            check_no_mmio!($env, ra, width);
+            unguarded_memory_op!($env, get_memory_mut, check_atomic_address(ra, width));
+
+            flush!($env, $regs);
+            let ret = host_call!($s, $env, $env.atomic_wait(ra, expected as $t as u64, width, timeout));
+            reload!($env, $regs);
+
+            push1!($s, $env, $regs, ret? as i64);
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+macro_rules! pop_v128 {
+    ($s:ty, $env:expr, $regs:expr) => {
+        {
+            let high = pop1!($s, $env, $regs) as u64;
+            let low = pop1!($s, $env, $regs) as u64;
+            ((high as u128) << 64) | low as u128
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+macro_rules! push_v128 {
+    ($s:ty, $env:expr, $regs:expr, $v:expr) => {
+        {
+            let v: u128 = $v;
+            push1!($s, $env, $regs, v as u64 as i64);
+            push1!($s, $env, $regs, (v >> 64) as u64 as i64);
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+macro_rules! run_simd_unop {
+    ($s:ty, $env:expr, $regs:expr, $body:expr) => {
+        {
+            let v = pop_v128!($s, $env, $regs);
+            push_v128!($s, $env, $regs, ($body)(v));
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+macro_rules! run_simd_binop {
+    ($s:ty, $env:expr, $regs:expr, $body:expr) => {
+        {
+            let right = pop_v128!($s, $env, $regs);
+            let left = pop_v128!($s, $env, $regs);
+            push_v128!($s, $env, $regs, ($body)(left, right));
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+macro_rules! run_simd_shift {
+    ($s:ty, $env:expr, $regs:expr, $t:ty, $body:expr) => {
+        {
+            let n = pop1!($s, $env, $regs) as u32;
+            let v = pop_v128!($s, $env, $regs);
+            push_v128!($s, $env, $regs, simd::map1::<$t, _>(v, |x: $t| ($body)(x, n)));
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+macro_rules! run_simd_test {
+    ($s:ty, $env:expr, $regs:expr, $body:expr) => {
+        {
+            let v = pop_v128!($s, $env, $regs);
+            push1!($s, $env, $regs, if ($body)(v) == true { 1 } else { 0 });
         }
     }
 }
@@ -190,7 +1014,22 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
     ) -> VirtualMachine<'a, E> {
         VirtualMachine {
             module: *module,
+            limits: env.get_limits(),
+            env: env,
+            verified: None,
+            reset_slots_fuse: false
+        }
+    }
+
+    pub fn new_verified(
+        module: &VerifiedModule<'a>,
+        env: E
+    ) -> VirtualMachine<'a, E> {
+        VirtualMachine {
+            module: module.module(),
+            limits: env.get_limits(),
             env: env,
+            verified: Some(*module),
             reset_slots_fuse: false
         }
     }
@@ -207,15 +1046,11 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
             let data_len = mi.next_u32()? as usize;
             let data = mi.next_many(data_len)?;
 
-            {
-                let mem = self.env.get_memory_mut();
-
-                if addr >= mem.len() || addr + data_len > mem.len() {
-                    return Err(ExecuteError::Bounds);
-                }
-
-                mem[addr..addr + data_len].copy_from_slice(data);
+            let mem_len = memory_len!(self.env);
+            if addr >= mem_len || addr + data_len > mem_len {
+                return Err(ExecuteError::Bounds);
             }
+            unguarded_memory_op!(self.env, get_memory_mut, write_bytes(addr, data));
 
             self.env.trace_mem_init(addr as usize, data);
         }
@@ -223,101 +1058,273 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
         Ok(())
     }
 
+    pub fn run_table_initializers(&mut self) -> ExecuteResult<()> {
+        let n = self.module.table_len();
+        if self.env.get_table().len() < n {
+            return Err(ExecuteError::Bounds);
+        }
+
+        for i in 0..n {
+            let entry = self.module.table_entry(i)?;
+            self.env.get_table_mut()[i] = entry;
+        }
+
+        Ok(())
+    }
+
     pub fn run(&mut self) -> ExecuteResult<()> {
+        match self.verified {
+            // Falls back to the checked path if `module` has been replaced since verification,
+            // or when resuming with frames left on the call stack: returning into them leaves
+            // the code the verifier saw.
+            Some(v) if v.module().code.as_ptr() == self.module.code.as_ptr()
+                && v.module().code.len() == self.module.code.len()
+                && self.env.get_call_stack().get_pos() == 0
+                && self.env.get_frame_stack().is_none_or(|fs| fs.get_pos() == 0) => {
+                check_headroom(self.env.get_stack(), &v, 0)?;
+                self.run_with::<Verified>(&v)
+            },
+            _ => self.run_with::<Checked>(&VerifiedModule::empty())
+        }
+    }
+
+    // Like `run`, with the status passed to the `exit` syscall as the result.
+    // Halting gives 0.
+    pub fn run_to_exit(&mut self) -> ExecuteResult<i32> {
+        match self.run() {
+            Ok(()) => Ok(0),
+            Err(ExecuteError::Exit(status)) => Ok(status),
+            Err(e) => Err(e)
+        }
+    }
+
+    fn run_with<S: StackAccess>(&mut self, verified: &VerifiedModule<'a>) -> ExecuteResult<()> {
+        let mut regs = Registers {
+            frame: Frame::current(self.env.get_call_stack())?,
+            depth: 0,
+            tos: 0,
+            entry_height: self.env.get_stack().get_pos(),
+            track_frames: self.module.handlers_len() > 0 && self.env.get_frame_stack().is_some()
+        };
+        reload!(self.env, regs);
+
+        let ret = self.run_loop::<S>(verified, &mut regs);
+        flush!(self.env, regs);
+        ret
+    }
+
+    // Walks the call frames from the `Throw` at `ip` outwards, up to `depth` frames,
+    // i.e. those pushed by the current `run`. Returns the handler, the call stack
+    // position of the frame it belongs to and the number of frames above.
+    fn find_handler(&self, ip: usize, tag: u32, depth: usize) -> ExecuteResult<(usize, usize, usize)> {
+        let cs = self.env.get_call_stack();
+        let mut ip = ip;
+        let mut pos = cs.get_pos();
+        let mut n_unwound = 0;
+
+        loop {
+            if let Some(target) = self.module.find_handler(ip, tag)? {
+                return Ok((target, pos, n_unwound));
+            }
+            if n_unwound == depth || pos < 2 {
+                return Err(ExecuteError::UncaughtException(tag));
+            }
+
+            let return_ip = cs.at(pos - 1)?.get() as usize;
+            pos = frame_start(cs, pos)?;
+            n_unwound += 1;
+
+            // The last byte of the call instruction.
+            ip = return_ip.checked_sub(1).ok_or(ExecuteError::Bounds)?;
+        }
+    }
+
+    // Looks up the target of an indirect call through the function table.
+    fn table_target(&self, id: usize, n_args: usize, n_locals: usize) -> ExecuteResult<usize> {
+        let entry = match self.env.get_table().get(id) {
+            Some(&Some(v)) => v,
+            Some(&None) => return Err(ExecuteError::NullTableEntry),
+            None => return Err(ExecuteError::Bounds)
+        };
+        if entry.n_args as usize != n_args || entry.n_locals as usize != n_locals {
+            return Err(ExecuteError::SignatureMismatch);
+        }
+        Ok(entry.target as usize)
+    }
+
+    #[inline(always)]
+    fn run_loop<S: StackAccess>(&mut self, verified: &VerifiedModule<'a>, regs: &mut Registers) -> ExecuteResult<()> {
         let code = Tape::from(self.module.code);
         loop {
             let op = Opcode::from_raw(*(code.next()?))?;
-            self.env.trace_opcode(&op)?;
+            trace!(S, self.env, regs, self.env.trace_opcode(&op))?;
 
             match op {
                 Opcode::Drop => {
-                    pop1!(self.env);
+                    pop1!(S, self.env, regs);
                 },
                 Opcode::Dup => {
-                    let stack = self.env.get_stack();
-                    let val = stack.tail_many(1)?[0].get();
-                    stack.next()?.set(val);
+                    let val = pop1!(S, self.env, regs);
+                    push1!(S, self.env, regs, val);
+                    push1!(S, self.env, regs, val);
                 },
                 Opcode::Swap2 => {
-                    let stack = self.env.get_stack();
-                    let tail = stack.tail_many(2)?;
-                    let a = tail[0].get();
-                    let b = tail[1].get();
-                    tail[0].set(b);
-                    tail[1].set(a);
+                    let (a, b) = pop2!(S, self.env, regs);
+                    push1!(S, self.env, regs, b);
+                    push1!(S, self.env, regs, a);
                 },
                 Opcode::Select => {
-                    let (val1, val2, cond) = pop3!(self.env);
+                    let (val1, val2, cond) = pop3!(S, self.env, regs);
                     if cond != 0 {
-                        push1!(self.env, val1);
+                        push1!(S, self.env, regs, val1);
                     } else {
-                        push1!(self.env, val2);
+                        push1!(S, self.env, regs, val2);
                     }
                 },
                 Opcode::Call => {
                     let n_args = code.next_u32()? as usize;
 
-                    let vs = self.env.get_stack();
-                    let cs = self.env.get_call_stack();
+                    let (target, n_locals) = pop2!(S, self.env, regs);
+                    let (target, n_locals) = (target as usize, n_locals as usize);
 
-                    let n_locals = vs.prev()?.get() as usize;
-                    let target = vs.prev()?.get() as usize;
+                    call!(S, self.env, regs, code, verified, self.limits, target, n_args, n_locals);
+                },
+                Opcode::CallDirect => {
+                    let target = code.next_u32()? as usize;
+                    let n_args = code.next_u32()? as usize;
+                    let n_locals = code.next_u32()? as usize;
+
+                    call!(S, self.env, regs, code, verified, self.limits, target, n_args, n_locals);
+                },
+                Opcode::CallIndirect => {
+                    let ip = code.get_pos() - 1;
+                    let n_args = code.next_u32()? as usize;
+                    let n_locals = code.next_u32()? as usize;
 
-                    self.env.trace_call(target, n_locals);
-                    self.env.trace_branch(target)?;
+                    let id = pop1!(S, self.env, regs) as u32 as usize;
+                    let target = self.table_target(id, n_args, n_locals)?;
 
-                    // [all_locals]
-                    for arg in vs.prev_many(n_args)? {
-                        cs.next()?.set(arg.get());
-                    }
-                    for _ in 0..n_locals {
-                        cs.next()?.set(0);
+                    // The host may have changed the table since verification.
+                    if S::VERIFIED {
+                        let n_results = verified.height_delta(ip, code.get_pos())? + n_args as isize + 1;
+                        if verified.n_results(target) != Some(n_results as usize) {
+                            return Err(ExecuteError::SignatureMismatch);
+                        }
                     }
 
-                    // n_all_locals
-                    cs.next()?.set((n_args + n_locals) as _);
+                    call!(S, self.env, regs, code, verified, self.limits, target, n_args, n_locals);
+                },
+                Opcode::ReturnCall => {
+                    let target = code.next_u32()? as usize;
+                    let n_args = code.next_u32()? as usize;
+                    let n_locals = code.next_u32()? as usize;
 
-                    // return_ip
-                    cs.next()?.set(code.get_pos() as _);
+                    tail_call!(S, self.env, regs, code, verified, self.limits, target, n_args, n_locals);
+                },
+                Opcode::ReturnCallIndirect => {
+                    let ip = code.get_pos() - 1;
+                    let n_args = code.next_u32()? as usize;
+                    let n_locals = code.next_u32()? as usize;
 
-                    // Jump!
-                    code.set_pos(target)?;
+                    let id = pop1!(S, self.env, regs) as u32 as usize;
+                    let target = self.table_target(id, n_args, n_locals)?;
+
+                    // The host may have changed the table since verification.
+                    if S::VERIFIED {
+                        let n_results = verified.function_at(ip).and_then(|f| verified.n_results(f));
+                        if n_results.is_none() || verified.n_results(target) != n_results {
+                            return Err(ExecuteError::SignatureMismatch);
+                        }
+                    }
+
+                    tail_call!(S, self.env, regs, code, verified, self.limits, target, n_args, n_locals);
                 },
                 Opcode::Return => {
+                    flush!(self.env, regs);
+
                     let cs = self.env.get_call_stack();
 
                     let return_ip = cs.prev()?.get() as usize;
                     let n_all_locals = cs.prev()?.get();
 
                     cs.prev_many(n_all_locals as _)?;
+                    regs.frame = Frame::current(cs)?;
+
+                    // Returning into a frame that was on the call stack before `run` pops
+                    // nothing from the frame stack.
+                    if regs.depth > 0 {
+                        regs.depth -= 1;
+                        if let (true, Some(fs)) = (regs.track_frames, self.env.get_frame_stack()) {
+                            fs.prev()?;
+                        }
+                    }
 
-                    self.env.trace_branch(return_ip)?;
+                    trace!(S, self.env, regs, self.env.trace_branch(return_ip))?;
 
                     code.set_pos(return_ip)?;
                 },
+                Opcode::Throw => {
+                    let ip = code.get_pos() - 1;
+                    flush!(self.env, regs);
+
+                    let (tag, payload) = {
+                        let v = self.env.get_stack().tail_many(2)?;
+                        (v[0].get() as u32, v[1].get())
+                    };
+                    let (target, cs_pos, n_unwound) = self.find_handler(ip, tag, regs.depth)?;
+
+                    let cs = self.env.get_call_stack();
+                    cs.set_pos(cs_pos)?;
+                    regs.frame = Frame::current(cs)?;
+                    regs.depth -= n_unwound;
+
+                    // Handlers exist, so if there is a frame stack, the frames pushed by this run
+                    // are on it.
+                    let fs = match (regs.track_frames, self.env.get_frame_stack()) {
+                        (true, Some(fs)) => {
+                            fs.prev_many(n_unwound)?;
+                            Some(fs)
+                        },
+                        _ => None
+                    };
+                    let stack_base = match (regs.depth, fs) {
+                        (0, _) => regs.entry_height,
+                        (_, Some(fs)) => fs.tail_many(1)?[0].get() as usize,
+                        (_, None) => return Err(ExecuteError::NotSupported)
+                    };
+                    let vs = self.env.get_stack();
+                    vs.set_pos(stack_base)?;
+                    vs.next()?.set(tag as i64);
+                    vs.next()?.set(payload);
+                    reload!(self.env, regs);
+
+                    trace!(S, self.env, regs, self.env.trace_branch(target))?;
+
+                    code.set_pos(target)?;
+                },
                 Opcode::Halt => {
                     return Ok(());
                 },
                 Opcode::GetLocal => {
                     let id = code.next_u32()? as usize;
-                    get_local!(self.env, id);
+                    get_local!(S, self.env, regs, id);
                 },
                 Opcode::SetLocal => {
                     let id = code.next_u32()? as usize;
-                    set_local!(self.env, id);
+                    set_local!(S, self.env, regs, id);
                 },
                 Opcode::TeeLocal => {
                     let id = code.next_u32()? as usize;
-                    tee_local!(self.env, id);
+                    tee_local!(S, self.env, regs, id);
                 },
                 Opcode::GetSlotIndirect => {
-                    let id = pop1!(self.env) as usize;
+                    let id = pop1!(S, self.env, regs) as usize;
 
                     let slots = self.env.get_slots();
                     bounds_check(slots, id, 1)?;
 
                     let val = slots[id];
-                    push1!(self.env, val);
+                    push1!(S, self.env, regs, val);
                 },
                 Opcode::GetSlot => {
                     let id = code.next_u32()? as usize;
@@ -326,11 +1333,11 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
                     bounds_check(slots, id, 1)?;
 
                     let val = slots[id];
-                    push1!(self.env, val);
+                    push1!(S, self.env, regs, val);
                 },
                 Opcode::SetSlot => {
                     let id = code.next_u32()? as usize;
-                    let val = pop1!(self.env);
+                    let val = pop1!(S, self.env, regs);
 
                     let slots = self.env.get_slots_mut();
                     bounds_check(slots, id, 1)?;
@@ -340,31 +1347,59 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
                 Opcode::ResetSlots => {
                     let n = code.next_u32()? as usize;
 
+                    if n > self.limits.max_slots {
+                        return Err(ExecuteError::SlotLimit);
+                    }
                     if self.reset_slots_fuse {
                         return Err(ExecuteError::Fuse);
                     }
                     self.reset_slots_fuse = true;
 
-                    self.env.reset_slots(n)?;
+                    flush!(self.env, regs);
+                    let ret = host_call!(S, self.env, self.env.reset_slots(n));
+                    reload!(self.env, regs);
+                    ret?;
                 },
                 Opcode::NativeInvoke => {
+                    flush!(self.env, regs);
+
+                    let ip = code.get_pos() - 1;
                     let id = code.next_u32()? as usize;
-                    let ret = self.env.do_native_invoke(id)?;
-                    if let Some(v) = ret {
-                        push1!(self.env, v);
+                    let sp = self.env.get_stack().get_pos();
+
+                    let ret = self.env.do_native_invoke(id);
+                    reload!(self.env, regs);
+
+                    if let Some(v) = ret? {
+                        push1!(Checked, self.env, regs, v);
+                    }
+
+                    // The host must have honored the signature given to the verifier.
+                    if S::VERIFIED {
+                        let delta = verified.height_delta(ip, code.get_pos())?;
+                        if self.env.get_stack().get_pos() as isize != sp as isize + delta {
+                            return Err(ExecuteError::InvalidNativeInvoke);
+                        }
                     }
                 },
                 Opcode::CurrentMemory => {
-                    let len = self.env.get_memory().len();
-                    push1!(self.env, len as _);
+                    let len = memory_len!(self.env);
+                    push1!(S, self.env, regs, len as _);
                 },
                 Opcode::GrowMemory => {
-                    let len_inc = pop1!(self.env);
+                    let len_inc = pop1!(S, self.env, regs);
 
-                    let len = self.env.get_memory().len();
-                    push1!(self.env, len as _);
+                    let len = memory_len!(self.env);
+                    match len.checked_add(len_inc as usize) {
+                        Some(v) if v <= self.limits.max_memory => {},
+                        _ => return Err(ExecuteError::MemoryLimit)
+                    }
+                    push1!(S, self.env, regs, len as _);
 
-                    self.env.grow_memory(len_inc as usize)?;
+                    flush!(self.env, regs);
+                    let ret = host_call!(S, self.env, self.env.grow_memory(len_inc as usize));
+                    reload!(self.env, regs);
+                    ret?;
                 },
                 Opcode::Nop => {},
                 Opcode::Unreachable => {
@@ -375,38 +1410,38 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
                 },
                 Opcode::Jmp => {
                     let target = code.next_u32()? as usize;
-                    self.env.trace_branch(target)?;
+                    trace!(S, self.env, regs, self.env.trace_branch(target))?;
                     code.set_pos(target)?;
                 },
                 Opcode::JmpIf => {
                     let target = code.next_u32()? as usize;
-                    let cond = pop1!(self.env);
+                    let cond = pop1!(S, self.env, regs);
                     if cond != 0 {
-                        self.env.trace_branch(target)?;
+                        trace!(S, self.env, regs, self.env.trace_branch(target))?;
                         code.set_pos(target)?;
                     }
                 },
                 Opcode::JmpEither => {
                     let target_a = code.next_u32()? as usize;
                     let target_b = code.next_u32()? as usize;
-                    let cond = pop1!(self.env);
+                    let cond = pop1!(S, self.env, regs);
                     if cond != 0 {
-                        self.env.trace_branch(target_a)?;
+                        trace!(S, self.env, regs, self.env.trace_branch(target_a))?;
                         code.set_pos(target_a)?;
                     } else {
-                        self.env.trace_branch(target_b)?;
+                        trace!(S, self.env, regs, self.env.trace_branch(target_b))?;
                         code.set_pos(target_b)?;
                     }
                 },
                 Opcode::JmpTable => {
-                    let cond = pop1!(self.env) as usize;
+                    let cond = pop1!(S, self.env, regs) as usize;
                     let default_target = code.next_u32()? as usize;
 
                     let table_len = code.next_u32()? as usize;
                     let table = code.next_many(table_len * 4)?; // 32-bit
 
                     if cond >= table_len {
-                        self.env.trace_branch(default_target)?;
+                        trace!(S, self.env, regs, self.env.trace_branch(default_target))?;
                         code.set_pos(default_target)?;
                     } else {
                         // cond < table_len
@@ -414,145 +1449,546 @@ impl<'a, E: Environment> VirtualMachine<'a, E> {
                         // => cond * 4 + 4 <= table_len * 4
                         // table.len() == table_len * 4
                         let target = LittleEndian::read_u32(&table[cond * 4 .. cond * 4 + 4]) as usize;
-                        self.env.trace_branch(target)?;
+                        trace!(S, self.env, regs, self.env.trace_branch(target))?;
                         code.set_pos(target)?;
                     }
                 },
                 Opcode::I32Load => {
-                    load_val!(self.env, code, u32, u32, read_u32);
+                    load_val!(S, self.env, regs, code, u32, u32, read_u32);
                 },
                 Opcode::I32Load8U => {
-                    load_val!(self.env, code, u8, u32, read_u8);
+                    load_val!(S, self.env, regs, code, u8, u32, read_u8);
                 },
                 Opcode::I32Load8S => {
-                    load_val!(self.env, code, i8, i32, read_u8);
+                    load_val!(S, self.env, regs, code, i8, i32, read_u8);
                 },
                 Opcode::I32Load16U => {
-                    load_val!(self.env, code, u16, u32, read_u16);
+                    load_val!(S, self.env, regs, code, u16, u32, read_u16);
                 },
                 Opcode::I32Load16S => {
-                    load_val!(self.env, code, i16, i32, read_u16);
+                    load_val!(S, self.env, regs, code, i16, i32, read_u16);
                 },
                 Opcode::I32Store => {
-                    store_val!(self.env, code, write_u32);
+                    store_val!(S, self.env, regs, code, u32, write_u32);
                 },
                 Opcode::I32Store8 => {
-                    store_val!(self.env, code, write_u8);
+                    store_val!(S, self.env, regs, code, u8, write_u8);
                 },
                 Opcode::I32Store16 => {
-                    store_val!(self.env, code, write_u16);
+                    store_val!(S, self.env, regs, code, u16, write_u16);
                 },
                 Opcode::I32Const => {
                     let v = code.next_u32()?;
-                    push1!(self.env, v as i64);
-                },
-                Opcode::I32Clz => run_unop!(self.env, i32, |v| unsafe { ::core::intrinsics::ctlz(v) }),
-                Opcode::I32Ctz => run_unop!(self.env, i32, |v| unsafe { ::core::intrinsics::cttz(v) }),
-                Opcode::I32Popcnt => run_unop!(self.env, i32, |v| unsafe { ::core::intrinsics::ctpop(v) }),
-                Opcode::I32Add => run_binop!(self.env, i32, |a: i32, b: i32| a.wrapping_add(b)),
-                Opcode::I32Sub => run_binop!(self.env, i32, |a: i32, b: i32| a.wrapping_sub(b)),
-                Opcode::I32Mul => run_binop!(self.env, i32, |a: i32, b: i32| a.wrapping_mul(b)),
-                Opcode::I32DivU => run_binop_checking_div_by_zero!(self.env, u32, |a: u32, b: u32| a.wrapping_div(b)),
-                Opcode::I32DivS => run_binop_checking_div_by_zero!(self.env, i32, |a: i32, b: i32| a.wrapping_div(b)),
-                Opcode::I32RemU => run_binop_checking_div_by_zero!(self.env, u32, |a: u32, b: u32| a.wrapping_rem(b)),
-                Opcode::I32RemS => run_binop_checking_div_by_zero!(self.env, i32, |a: i32, b: i32| a.wrapping_rem(b)),
-                Opcode::I32And => run_binop!(self.env, u32, |a: u32, b: u32| a & b),
-                Opcode::I32Or => run_binop!(self.env, u32, |a: u32, b: u32| a | b),
-                Opcode::I32Xor => run_binop!(self.env, u32, |a: u32, b: u32| a ^ b),
-                Opcode::I32Shl => run_binop!(self.env, u32, |a: u32, b: u32| a.wrapping_shl(b)),
-                Opcode::I32ShrU => run_binop!(self.env, u32, |a: u32, b: u32| a.wrapping_shr(b)),
-                Opcode::I32ShrS => run_binop!(self.env, i32, |a: i32, b: i32| a.wrapping_shr(b as u32)),
-                Opcode::I32Rotl => run_binop!(self.env, u32, |a: u32, b: u32| a.rotate_left(b)),
-                Opcode::I32Rotr => run_binop!(self.env, u32, |a: u32, b: u32| a.rotate_right(b)),
-                Opcode::I32Eq => run_relop!(self.env, u32, |a: u32, b: u32| a == b),
-                Opcode::I32Ne => run_relop!(self.env, u32, |a: u32, b: u32| a != b),
-                Opcode::I32LtU => run_relop!(self.env, u32, |a: u32, b: u32| a < b),
-                Opcode::I32LtS => run_relop!(self.env, i32, |a: i32, b: i32| a < b),
-                Opcode::I32LeU => run_relop!(self.env, u32, |a: u32, b: u32| a <= b),
-                Opcode::I32LeS => run_relop!(self.env, i32, |a: i32, b: i32| a <= b),
-                Opcode::I32GtU => run_relop!(self.env, u32, |a: u32, b: u32| a > b),
-                Opcode::I32GtS => run_relop!(self.env, i32, |a: i32, b: i32| a > b),
-                Opcode::I32GeU => run_relop!(self.env, u32, |a: u32, b: u32| a >= b),
-                Opcode::I32GeS => run_relop!(self.env, i32, |a: i32, b: i32| a >= b),
-
-                Opcode::I32WrapI64 => run_unop!(self.env, u32, |v: u32| v),
+                    push1!(S, self.env, regs, v as i64);
+                },
+                Opcode::I32Clz => run_unop!(S, self.env, regs, i32, |v| unsafe { ::core::intrinsics::ctlz(v) }),
+                Opcode::I32Ctz => run_unop!(S, self.env, regs, i32, |v| unsafe { ::core::intrinsics::cttz(v) }),
+                Opcode::I32Popcnt => run_unop!(S, self.env, regs, i32, |v| unsafe { ::core::intrinsics::ctpop(v) }),
+                Opcode::I32Add => run_binop!(S, self.env, regs, i32, |a: i32, b: i32| a.wrapping_add(b)),
+                Opcode::I32Sub => run_binop!(S, self.env, regs, i32, |a: i32, b: i32| a.wrapping_sub(b)),
+                Opcode::I32Mul => run_binop!(S, self.env, regs, i32, |a: i32, b: i32| a.wrapping_mul(b)),
+                Opcode::I32DivU => run_binop_checking_div_by_zero!(S, self.env, regs, u32, |a: u32, b: u32| a.wrapping_div(b)),
+                Opcode::I32DivS => run_binop_checking_div_by_zero!(S, self.env, regs, i32, |a: i32, b: i32| a.wrapping_div(b)),
+                Opcode::I32RemU => run_binop_checking_div_by_zero!(S, self.env, regs, u32, |a: u32, b: u32| a.wrapping_rem(b)),
+                Opcode::I32RemS => run_binop_checking_div_by_zero!(S, self.env, regs, i32, |a: i32, b: i32| a.wrapping_rem(b)),
+                Opcode::I32And => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a & b),
+                Opcode::I32Or => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a | b),
+                Opcode::I32Xor => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a ^ b),
+                Opcode::I32Shl => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a.wrapping_shl(b)),
+                Opcode::I32ShrU => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a.wrapping_shr(b)),
+                Opcode::I32ShrS => run_binop!(S, self.env, regs, i32, |a: i32, b: i32| a.wrapping_shr(b as u32)),
+                Opcode::I32Rotl => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a.rotate_left(b)),
+                Opcode::I32Rotr => run_binop!(S, self.env, regs, u32, |a: u32, b: u32| a.rotate_right(b)),
+                Opcode::I32Eq => run_relop!(S, self.env, regs, u32, |a: u32, b: u32| a == b),
+                Opcode::I32Ne => run_relop!(S, self.env, regs, u32, |a: u32, b: u32| a != b),
+                Opcode::I32LtU => run_relop!(S, self.env, regs, u32, |a: u32, b: u32| a < b),
+                Opcode::I32LtS => run_relop!(S, self.env, regs, i32, |a: i32, b: i32| a < b),
+                Opcode::I32LeU => run_relop!(S, self.env, regs, u32, |a: u32, b: u32| a <= b),
+                Opcode::I32LeS => run_relop!(S, self.env, regs, i32, |a: i32, b: i32| a <= b),
+                Opcode::I32GtU => run_relop!(S, self.env, regs, u32, |a: u32, b: u32| a > b),
+                Opcode::I32GtS => run_relop!(S, self.env, regs, i32, |a: i32, b: i32| a > b),
+                Opcode::I32GeU => run_relop!(S, self.env, regs, u32, |a: u32, b: u32| a >= b),
+                Opcode::I32GeS => run_relop!(S, self.env, regs, i32, |a: i32, b: i32| a >= b),
+
+                Opcode::I32WrapI64 => run_unop!(S, self.env, regs, u32, |v: u32| v),
 
                 Opcode::I64Load => {
-                    load_val!(self.env, code, u64, u64, read_u64);
+                    load_val!(S, self.env, regs, code, u64, u64, read_u64);
                 },
                 Opcode::I64Load8U => {
-                    load_val!(self.env, code, u8, u64, read_u8);
+                    load_val!(S, self.env, regs, code, u8, u64, read_u8);
                 },
                 Opcode::I64Load8S => {
-                    load_val!(self.env, code, i8, i64, read_u8);
+                    load_val!(S, self.env, regs, code, i8, i64, read_u8);
                 },
                 Opcode::I64Load16U => {
-                    load_val!(self.env, code, u16, u64, read_u16);
+                    load_val!(S, self.env, regs, code, u16, u64, read_u16);
                 },
                 Opcode::I64Load16S => {
-                    load_val!(self.env, code, i16, i64, read_u16);
+                    load_val!(S, self.env, regs, code, i16, i64, read_u16);
                 },
                 Opcode::I64Load32U => {
-                    load_val!(self.env, code, u32, u64, read_u32);
+                    load_val!(S, self.env, regs, code, u32, u64, read_u32);
                 },
                 Opcode::I64Load32S => {
-                    load_val!(self.env, code, i32, i64, read_u32);
+                    load_val!(S, self.env, regs, code, i32, i64, read_u32);
                 },
                 Opcode::I64Store => {
-                    store_val!(self.env, code, write_u64);
+                    store_val!(S, self.env, regs, code, u64, write_u64);
                 },
                 Opcode::I64Store8 => {
-                    store_val!(self.env, code, write_u8);
+                    store_val!(S, self.env, regs, code, u8, write_u8);
                 },
                 Opcode::I64Store16 => {
-                    store_val!(self.env, code, write_u16);
+                    store_val!(S, self.env, regs, code, u16, write_u16);
                 },
                 Opcode::I64Store32 => {
-                    store_val!(self.env, code, write_u32);
+                    store_val!(S, self.env, regs, code, u32, write_u32);
                 },
                 Opcode::I64Const => {
                     let v = code.next_u64()?;
-                    push1!(self.env, v as i64);
-                },
-                Opcode::I64Clz => run_unop!(self.env, i64, |v| unsafe { ::core::intrinsics::ctlz(v) }),
-                Opcode::I64Ctz => run_unop!(self.env, i64, |v| unsafe { ::core::intrinsics::cttz(v) }),
-                Opcode::I64Popcnt => run_unop!(self.env, i64, |v| unsafe { ::core::intrinsics::ctpop(v) }),
-                Opcode::I64Add => run_binop!(self.env, i64, |a: i64, b: i64| a.wrapping_add(b)),
-                Opcode::I64Sub => run_binop!(self.env, i64, |a: i64, b: i64| a.wrapping_sub(b)),
-                Opcode::I64Mul => run_binop!(self.env, i64, |a: i64, b: i64| a.wrapping_mul(b)),
-                Opcode::I64DivU => run_binop_checking_div_by_zero!(self.env, u64, |a: u64, b: u64| a.wrapping_div(b)),
-                Opcode::I64DivS => run_binop_checking_div_by_zero!(self.env, i64, |a: i64, b: i64| a.wrapping_div(b)),
-                Opcode::I64RemU => run_binop_checking_div_by_zero!(self.env, u64, |a: u64, b: u64| a.wrapping_rem(b)),
-                Opcode::I64RemS => run_binop_checking_div_by_zero!(self.env, i64, |a: i64, b: i64| a.wrapping_rem(b)),
-                Opcode::I64And => run_binop!(self.env, u64, |a: u64, b: u64| a & b),
-                Opcode::I64Or => run_binop!(self.env, u64, |a: u64, b: u64| a | b),
-                Opcode::I64Xor => run_binop!(self.env, u64, |a: u64, b: u64| a ^ b),
-                Opcode::I64Shl => run_binop!(self.env, u64, |a: u64, b: u64| a.wrapping_shl(b as u32)),
-                Opcode::I64ShrU => run_binop!(self.env, u64, |a: u64, b: u64| a.wrapping_shr(b as u32)),
-                Opcode::I64ShrS => run_binop!(self.env, i64, |a: i64, b: i64| a.wrapping_shr(b as u32)),
-                Opcode::I64Rotl => run_binop!(self.env, u64, |a: u64, b: u64| a.rotate_left(b as u32)),
-                Opcode::I64Rotr => run_binop!(self.env, u64, |a: u64, b: u64| a.rotate_right(b as u32)),
-                Opcode::I64Eq => run_relop!(self.env, u64, |a: u64, b: u64| a == b),
-                Opcode::I64Ne => run_relop!(self.env, u64, |a: u64, b: u64| a != b),
-                Opcode::I64LtU => run_relop!(self.env, u64, |a: u64, b: u64| a < b),
-                Opcode::I64LtS => run_relop!(self.env, i64, |a: i64, b: i64| a < b),
-                Opcode::I64LeU => run_relop!(self.env, u64, |a: u64, b: u64| a <= b),
-                Opcode::I64LeS => run_relop!(self.env, i64, |a: i64, b: i64| a <= b),
-                Opcode::I64GtU => run_relop!(self.env, u64, |a: u64, b: u64| a > b),
-                Opcode::I64GtS => run_relop!(self.env, i64, |a: i64, b: i64| a > b),
-                Opcode::I64GeU => run_relop!(self.env, u64, |a: u64, b: u64| a >= b),
-                Opcode::I64GeS => run_relop!(self.env, i64, |a: i64, b: i64| a >= b),
-                Opcode::I64ExtendI32U => run_unop!(self.env, u64, |v: u64| v as u32 as u64),
-                Opcode::I64ExtendI32S => run_unop!(self.env, u64, |v: u64| v as u32 as i32 as i64 as u64),
+                    push1!(S, self.env, regs, v as i64);
+                },
+                Opcode::I64Clz => run_unop!(S, self.env, regs, i64, |v| unsafe { ::core::intrinsics::ctlz(v) }),
+                Opcode::I64Ctz => run_unop!(S, self.env, regs, i64, |v| unsafe { ::core::intrinsics::cttz(v) }),
+                Opcode::I64Popcnt => run_unop!(S, self.env, regs, i64, |v| unsafe { ::core::intrinsics::ctpop(v) }),
+                Opcode::I64Add => run_binop!(S, self.env, regs, i64, |a: i64, b: i64| a.wrapping_add(b)),
+                Opcode::I64Sub => run_binop!(S, self.env, regs, i64, |a: i64, b: i64| a.wrapping_sub(b)),
+                Opcode::I64Mul => run_binop!(S, self.env, regs, i64, |a: i64, b: i64| a.wrapping_mul(b)),
+                Opcode::I64DivU => run_binop_checking_div_by_zero!(S, self.env, regs, u64, |a: u64, b: u64| a.wrapping_div(b)),
+                Opcode::I64DivS => run_binop_checking_div_by_zero!(S, self.env, regs, i64, |a: i64, b: i64| a.wrapping_div(b)),
+                Opcode::I64RemU => run_binop_checking_div_by_zero!(S, self.env, regs, u64, |a: u64, b: u64| a.wrapping_rem(b)),
+                Opcode::I64RemS => run_binop_checking_div_by_zero!(S, self.env, regs, i64, |a: i64, b: i64| a.wrapping_rem(b)),
+                Opcode::I64And => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a & b),
+                Opcode::I64Or => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a | b),
+                Opcode::I64Xor => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a ^ b),
+                Opcode::I64Shl => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a.wrapping_shl(b as u32)),
+                Opcode::I64ShrU => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a.wrapping_shr(b as u32)),
+                Opcode::I64ShrS => run_binop!(S, self.env, regs, i64, |a: i64, b: i64| a.wrapping_shr(b as u32)),
+                Opcode::I64Rotl => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a.rotate_left(b as u32)),
+                Opcode::I64Rotr => run_binop!(S, self.env, regs, u64, |a: u64, b: u64| a.rotate_right(b as u32)),
+                Opcode::I64Eq => run_relop!(S, self.env, regs, u64, |a: u64, b: u64| a == b),
+                Opcode::I64Ne => run_relop!(S, self.env, regs, u64, |a: u64, b: u64| a != b),
+                Opcode::I64LtU => run_relop!(S, self.env, regs, u64, |a: u64, b: u64| a < b),
+                Opcode::I64LtS => run_relop!(S, self.env, regs, i64, |a: i64, b: i64| a < b),
+                Opcode::I64LeU => run_relop!(S, self.env, regs, u64, |a: u64, b: u64| a <= b),
+                Opcode::I64LeS => run_relop!(S, self.env, regs, i64, |a: i64, b: i64| a <= b),
+                Opcode::I64GtU => run_relop!(S, self.env, regs, u64, |a: u64, b: u64| a > b),
+                Opcode::I64GtS => run_relop!(S, self.env, regs, i64, |a: i64, b: i64| a > b),
+                Opcode::I64GeU => run_relop!(S, self.env, regs, u64, |a: u64, b: u64| a >= b),
+                Opcode::I64GeS => run_relop!(S, self.env, regs, i64, |a: i64, b: i64| a >= b),
+                Opcode::I64ExtendI32U => run_unop!(S, self.env, regs, u64, |v: u64| v as u32 as u64),
+                Opcode::I64ExtendI32S => run_unop!(S, self.env, regs, u64, |v: u64| v as u32 as i32 as i64 as u64),
+                Opcode::F32Load => {
+                    load_val!(S, self.env, regs, code, u32, u32, read_u32);
+                },
+                Opcode::F32Store => {
+                    store_val!(S, self.env, regs, code, u32, write_u32);
+                },
+                Opcode::F32Const => {
+                    let v = code.next_u32()?;
+                    push1!(S, self.env, regs, v as i64);
+                },
+                Opcode::F32Abs => run_float_unop!(S, self.env, regs, f32, |v: f32| v.abs()),
+                Opcode::F32Neg => run_float_unop!(S, self.env, regs, f32, |v: f32| -v),
+                Opcode::F32Ceil => run_float_unop!(S, self.env, regs, f32, ::core::intrinsics::ceilf32),
+                Opcode::F32Floor => run_float_unop!(S, self.env, regs, f32, ::core::intrinsics::floorf32),
+                Opcode::F32Trunc => run_float_unop!(S, self.env, regs, f32, ::core::intrinsics::truncf32),
+                Opcode::F32Nearest => run_float_unop!(S, self.env, regs, f32, ::core::intrinsics::round_ties_even_f32),
+                Opcode::F32Sqrt => run_float_unop!(S, self.env, regs, f32, ::core::intrinsics::sqrtf32),
+                Opcode::F32Add => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| a + b),
+                Opcode::F32Sub => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| a - b),
+                Opcode::F32Mul => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| a * b),
+                Opcode::F32Div => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| a / b),
+                Opcode::F32Min => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| float_min!(a, b)),
+                Opcode::F32Max => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| float_max!(a, b)),
+                Opcode::F32Copysign => run_float_binop!(S, self.env, regs, f32, |a: f32, b: f32| a.copysign(b)),
+                Opcode::F32Eq => run_float_relop!(S, self.env, regs, f32, |a: f32, b: f32| a == b),
+                Opcode::F32Ne => run_float_relop!(S, self.env, regs, f32, |a: f32, b: f32| a != b),
+                Opcode::F32Lt => run_float_relop!(S, self.env, regs, f32, |a: f32, b: f32| a < b),
+                Opcode::F32Gt => run_float_relop!(S, self.env, regs, f32, |a: f32, b: f32| a > b),
+                Opcode::F32Le => run_float_relop!(S, self.env, regs, f32, |a: f32, b: f32| a <= b),
+                Opcode::F32Ge => run_float_relop!(S, self.env, regs, f32, |a: f32, b: f32| a >= b),
+                Opcode::F64Load => {
+                    load_val!(S, self.env, regs, code, u64, u64, read_u64);
+                },
+                Opcode::F64Store => {
+                    store_val!(S, self.env, regs, code, u64, write_u64);
+                },
+                Opcode::F64Const => {
+                    let v = code.next_u64()?;
+                    push1!(S, self.env, regs, v as i64);
+                },
+                Opcode::F64Abs => run_float_unop!(S, self.env, regs, f64, |v: f64| v.abs()),
+                Opcode::F64Neg => run_float_unop!(S, self.env, regs, f64, |v: f64| -v),
+                Opcode::F64Ceil => run_float_unop!(S, self.env, regs, f64, ::core::intrinsics::ceilf64),
+                Opcode::F64Floor => run_float_unop!(S, self.env, regs, f64, ::core::intrinsics::floorf64),
+                Opcode::F64Trunc => run_float_unop!(S, self.env, regs, f64, ::core::intrinsics::truncf64),
+                Opcode::F64Nearest => run_float_unop!(S, self.env, regs, f64, ::core::intrinsics::round_ties_even_f64),
+                Opcode::F64Sqrt => run_float_unop!(S, self.env, regs, f64, ::core::intrinsics::sqrtf64),
+                Opcode::F64Add => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| a + b),
+                Opcode::F64Sub => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| a - b),
+                Opcode::F64Mul => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| a * b),
+                Opcode::F64Div => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| a / b),
+                Opcode::F64Min => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| float_min!(a, b)),
+                Opcode::F64Max => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| float_max!(a, b)),
+                Opcode::F64Copysign => run_float_binop!(S, self.env, regs, f64, |a: f64, b: f64| a.copysign(b)),
+                Opcode::F64Eq => run_float_relop!(S, self.env, regs, f64, |a: f64, b: f64| a == b),
+                Opcode::F64Ne => run_float_relop!(S, self.env, regs, f64, |a: f64, b: f64| a != b),
+                Opcode::F64Lt => run_float_relop!(S, self.env, regs, f64, |a: f64, b: f64| a < b),
+                Opcode::F64Gt => run_float_relop!(S, self.env, regs, f64, |a: f64, b: f64| a > b),
+                Opcode::F64Le => run_float_relop!(S, self.env, regs, f64, |a: f64, b: f64| a <= b),
+                Opcode::F64Ge => run_float_relop!(S, self.env, regs, f64, |a: f64, b: f64| a >= b),
+                Opcode::I32TruncF32S => run_trapping_convert!(S, self.env, regs, f32, i32, |v: f32| float_trunc!(v as f64, i32, -2147483649.0, 2147483648.0)),
+                Opcode::I32TruncF32U => run_trapping_convert!(S, self.env, regs, f32, u32, |v: f32| float_trunc!(v as f64, u32, -1.0, 4294967296.0)),
+                Opcode::I32TruncF64S => run_trapping_convert!(S, self.env, regs, f64, i32, |v: f64| float_trunc!(v, i32, -2147483649.0, 2147483648.0)),
+                Opcode::I32TruncF64U => run_trapping_convert!(S, self.env, regs, f64, u32, |v: f64| float_trunc!(v, u32, -1.0, 4294967296.0)),
+                Opcode::I64TruncF32S => run_trapping_convert!(S, self.env, regs, f32, i64, |v: f32| float_trunc!(v as f64, i64, -9223372036854777856.0, 9223372036854775808.0)),
+                Opcode::I64TruncF32U => run_trapping_convert!(S, self.env, regs, f32, u64, |v: f32| float_trunc!(v as f64, u64, -1.0, 18446744073709551616.0)),
+                Opcode::I64TruncF64S => run_trapping_convert!(S, self.env, regs, f64, i64, |v: f64| float_trunc!(v, i64, -9223372036854777856.0, 9223372036854775808.0)),
+                Opcode::I64TruncF64U => run_trapping_convert!(S, self.env, regs, f64, u64, |v: f64| float_trunc!(v, u64, -1.0, 18446744073709551616.0)),
+                Opcode::F32ConvertI32S => run_convert!(S, self.env, regs, i32, f32, |v: i32| v as f32),
+                Opcode::F32ConvertI32U => run_convert!(S, self.env, regs, u32, f32, |v: u32| v as f32),
+                Opcode::F32ConvertI64S => run_convert!(S, self.env, regs, i64, f32, |v: i64| v as f32),
+                Opcode::F32ConvertI64U => run_convert!(S, self.env, regs, u64, f32, |v: u64| v as f32),
+                Opcode::F32DemoteF64 => run_convert!(S, self.env, regs, f64, f32, |v: f64| v as f32),
+                Opcode::F64ConvertI32S => run_convert!(S, self.env, regs, i32, f64, |v: i32| v as f64),
+                Opcode::F64ConvertI32U => run_convert!(S, self.env, regs, u32, f64, |v: u32| v as f64),
+                Opcode::F64ConvertI64S => run_convert!(S, self.env, regs, i64, f64, |v: i64| v as f64),
+                Opcode::F64ConvertI64U => run_convert!(S, self.env, regs, u64, f64, |v: u64| v as f64),
+                Opcode::F64PromoteF32 => run_convert!(S, self.env, regs, f32, f64, |v: f32| v as f64),
+                Opcode::I32ReinterpretF32 => run_convert!(S, self.env, regs, f32, u32, |v: f32| v.to_bits()),
+                Opcode::I64ReinterpretF64 => run_convert!(S, self.env, regs, f64, u64, |v: f64| v.to_bits()),
+                Opcode::F32ReinterpretI32 => run_convert!(S, self.env, regs, u32, f32, f32::from_bits),
+                Opcode::F64ReinterpretI64 => run_convert!(S, self.env, regs, u64, f64, f64::from_bits),
+                Opcode::MemCopy => {
+                    let (dest, src, len) = pop3!(S, self.env, regs);
+                    let (dest, src, len) = (dest as u32 as usize, src as u32 as usize, len as u32 as usize);
+
+                    check_no_mmio!(self.env, src, len);
+                    check_no_mmio!(self.env, dest, len);
+                    unguarded_memory_op!(self.env, get_memory_mut, copy_range(src, dest, len));
+                },
+                Opcode::MemFill => {
+                    let (dest, val, len) = pop3!(S, self.env, regs);
+                    let (dest, len) = (dest as u32 as usize, len as u32 as usize);
+
+                    check_no_mmio!(self.env, dest, len);
+                    unguarded_memory_op!(self.env, get_memory_mut, fill_range(dest, val as u8, len));
+                },
+                Opcode::MemoryInit => {
+                    let id = code.next_u32()? as usize;
+                    let (dest, offset, len) = pop3!(S, self.env, regs);
+                    let (dest, offset, len) = (dest as u32 as usize, offset as u32 as usize, len as u32 as usize);
+
+                    let data = self.module.data_segment(id)?;
+                    range_check(data, offset, len)?;
+                    let data = &data[offset..offset + len];
+
+                    check_no_mmio!(self.env, dest, len);
+                    unguarded_memory_op!(self.env, get_memory_mut, write_bytes(dest, data));
+
+                    trace!(S, self.env, regs, self.env.trace_mem_init(dest, data));
+                },
+                Opcode::I32Extend8S => run_unop!(S, self.env, regs, i32, |v: i32| v as i8 as i32),
+                Opcode::I32Extend16S => run_unop!(S, self.env, regs, i32, |v: i32| v as i16 as i32),
+                Opcode::I64Extend8S => run_unop!(S, self.env, regs, i64, |v: i64| v as i8 as i64),
+                Opcode::I64Extend16S => run_unop!(S, self.env, regs, i64, |v: i64| v as i16 as i64),
+                Opcode::I64Extend32S => run_unop!(S, self.env, regs, i64, |v: i64| v as i32 as i64),
+                Opcode::I32AtomicLoad => atomic_load!(S, self.env, regs, code, atomic_load_u32, 4),
+                Opcode::I64AtomicLoad => atomic_load!(S, self.env, regs, code, atomic_load_u64, 8),
+                Opcode::I32AtomicStore => atomic_store!(S, self.env, regs, code, atomic_store_u32, 4),
+                Opcode::I64AtomicStore => atomic_store!(S, self.env, regs, code, atomic_store_u64, 8),
+                Opcode::I32AtomicRmwAdd => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u32, 4, AtomicRmwOp::Add),
+                Opcode::I32AtomicRmwSub => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u32, 4, AtomicRmwOp::Sub),
+                Opcode::I32AtomicRmwAnd => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u32, 4, AtomicRmwOp::And),
+                Opcode::I32AtomicRmwOr => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u32, 4, AtomicRmwOp::Or),
+                Opcode::I32AtomicRmwXor => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u32, 4, AtomicRmwOp::Xor),
+                Opcode::I32AtomicRmwXchg => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u32, 4, AtomicRmwOp::Xchg),
+                Opcode::I32AtomicRmwCmpxchg => atomic_cmpxchg!(S, self.env, regs, code, atomic_cmpxchg_u32, 4),
+                Opcode::I64AtomicRmwAdd => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u64, 8, AtomicRmwOp::Add),
+                Opcode::I64AtomicRmwSub => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u64, 8, AtomicRmwOp::Sub),
+                Opcode::I64AtomicRmwAnd => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u64, 8, AtomicRmwOp::And),
+                Opcode::I64AtomicRmwOr => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u64, 8, AtomicRmwOp::Or),
+                Opcode::I64AtomicRmwXor => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u64, 8, AtomicRmwOp::Xor),
+                Opcode::I64AtomicRmwXchg => atomic_rmw!(S, self.env, regs, code, atomic_rmw_u64, 8, AtomicRmwOp::Xchg),
+                Opcode::I64AtomicRmwCmpxchg => atomic_cmpxchg!(S, self.env, regs, code, atomic_cmpxchg_u64, 8),
+                Opcode::MemoryAtomicWait32 => atomic_wait!(S, self.env, regs, code, u32),
+                Opcode::MemoryAtomicWait64 => atomic_wait!(S, self.env, regs, code, u64),
+                Opcode::MemoryAtomicNotify => {
+                    let offset = code.next_u32()? as usize;
+                    let (addr, count) = pop2!(S, self.env, regs);
+                    let ra = offset + addr as u32 as usize;
+
+                    check_no_mmio!(self.env, ra, 4);
+                    unguarded_memory_op!(self.env, get_memory_mut, check_atomic_address(ra, 4));
+
+                    flush!(self.env, regs);
+                    let ret = host_call!(S, self.env, self.env.atomic_notify(ra, count as u32));
+                    reload!(self.env, regs);
+
+                    push1!(S, self.env, regs, ret? as i64);
+                },
+                Opcode::AtomicFence => {
+                    fence(Ordering::SeqCst);
+                },
+                #[cfg(feature = "simd")]
+                Opcode::Simd => {
+                    self.run_simd::<S>(&code, regs)?;
+                },
+                #[cfg(not(feature = "simd"))]
+                Opcode::Simd => {
+                    return Err(ExecuteError::NotSupported);
+                },
+                Opcode::I32AddCheckedS => run_binop_checking_overflow!(S, self.env, regs, i32, |a: i32, b: i32| a.checked_add(b)),
+                Opcode::I32AddCheckedU => run_binop_checking_overflow!(S, self.env, regs, u32, |a: u32, b: u32| a.checked_add(b)),
+                Opcode::I32SubCheckedS => run_binop_checking_overflow!(S, self.env, regs, i32, |a: i32, b: i32| a.checked_sub(b)),
+                Opcode::I32SubCheckedU => run_binop_checking_overflow!(S, self.env, regs, u32, |a: u32, b: u32| a.checked_sub(b)),
+                Opcode::I32MulCheckedS => run_binop_checking_overflow!(S, self.env, regs, i32, |a: i32, b: i32| a.checked_mul(b)),
+                Opcode::I32MulCheckedU => run_binop_checking_overflow!(S, self.env, regs, u32, |a: u32, b: u32| a.checked_mul(b)),
+                Opcode::I64AddCheckedS => run_binop_checking_overflow!(S, self.env, regs, i64, |a: i64, b: i64| a.checked_add(b)),
+                Opcode::I64AddCheckedU => run_binop_checking_overflow!(S, self.env, regs, u64, |a: u64, b: u64| a.checked_add(b)),
+                Opcode::I64SubCheckedS => run_binop_checking_overflow!(S, self.env, regs, i64, |a: i64, b: i64| a.checked_sub(b)),
+                Opcode::I64SubCheckedU => run_binop_checking_overflow!(S, self.env, regs, u64, |a: u64, b: u64| a.checked_sub(b)),
+                Opcode::I64MulCheckedS => run_binop_checking_overflow!(S, self.env, regs, i64, |a: i64, b: i64| a.checked_mul(b)),
+                Opcode::I64MulCheckedU => run_binop_checking_overflow!(S, self.env, regs, u64, |a: u64, b: u64| a.checked_mul(b)),
+                Opcode::I32AddOverflowingS => run_binop_with_overflow_flag!(S, self.env, regs, i32, |a: i32, b: i32| a.overflowing_add(b)),
+                Opcode::I32AddOverflowingU => run_binop_with_overflow_flag!(S, self.env, regs, u32, |a: u32, b: u32| a.overflowing_add(b)),
+                Opcode::I32SubOverflowingS => run_binop_with_overflow_flag!(S, self.env, regs, i32, |a: i32, b: i32| a.overflowing_sub(b)),
+                Opcode::I32SubOverflowingU => run_binop_with_overflow_flag!(S, self.env, regs, u32, |a: u32, b: u32| a.overflowing_sub(b)),
+                Opcode::I32MulOverflowingS => run_binop_with_overflow_flag!(S, self.env, regs, i32, |a: i32, b: i32| a.overflowing_mul(b)),
+                Opcode::I32MulOverflowingU => run_binop_with_overflow_flag!(S, self.env, regs, u32, |a: u32, b: u32| a.overflowing_mul(b)),
+                Opcode::I64AddOverflowingS => run_binop_with_overflow_flag!(S, self.env, regs, i64, |a: i64, b: i64| a.overflowing_add(b)),
+                Opcode::I64AddOverflowingU => run_binop_with_overflow_flag!(S, self.env, regs, u64, |a: u64, b: u64| a.overflowing_add(b)),
+                Opcode::I64SubOverflowingS => run_binop_with_overflow_flag!(S, self.env, regs, i64, |a: i64, b: i64| a.overflowing_sub(b)),
+                Opcode::I64SubOverflowingU => run_binop_with_overflow_flag!(S, self.env, regs, u64, |a: u64, b: u64| a.overflowing_sub(b)),
+                Opcode::I64MulOverflowingS => run_binop_with_overflow_flag!(S, self.env, regs, i64, |a: i64, b: i64| a.overflowing_mul(b)),
+                Opcode::I64MulOverflowingU => run_binop_with_overflow_flag!(S, self.env, regs, u64, |a: u64, b: u64| a.overflowing_mul(b)),
+                Opcode::I64MulWideS => {
+                    let (left, right) = pop2!(S, self.env, regs);
+                    let result = (left as i128).wrapping_mul(right as i128) as u128;
+                    push1!(S, self.env, regs, result as u64 as i64);
+                    push1!(S, self.env, regs, (result >> 64) as u64 as i64);
+                },
+                Opcode::I64MulWideU => {
+                    let (left, right) = pop2!(S, self.env, regs);
+                    let result = (left as u64 as u128) * (right as u64 as u128);
+                    push1!(S, self.env, regs, result as u64 as i64);
+                    push1!(S, self.env, regs, (result >> 64) as u64 as i64);
+                },
+                Opcode::I64AddWithCarry => {
+                    let (left, right, carry) = pop3!(S, self.env, regs);
+                    let (result, c1) = (left as u64).overflowing_add(right as u64);
+                    let (result, c2) = result.overflowing_add(if carry != 0 { 1 } else { 0 });
+                    push1!(S, self.env, regs, result as i64);
+                    push1!(S, self.env, regs, if c1 || c2 { 1 } else { 0 });
+                },
+                Opcode::I64SubWithBorrow => {
+                    let (left, right, borrow) = pop3!(S, self.env, regs);
+                    let (result, b1) = (left as u64).overflowing_sub(right as u64);
+                    let (result, b2) = result.overflowing_sub(if borrow != 0 { 1 } else { 0 });
+                    push1!(S, self.env, regs, result as i64);
+                    push1!(S, self.env, regs, if b1 || b2 { 1 } else { 0 });
+                },
                 Opcode::Never => {
                     return Err(ExecuteError::IllegalOpcode)
                 }
             }
         }
     }
+
+    #[cfg(feature = "simd")]
+    fn run_simd<S: StackAccess>(&mut self, code: &Tape<u8>, regs: &mut Registers) -> ExecuteResult<()> {
+        let op = SimdOpcode::from_raw(*code.next()?)?;
+
+        match op {
+            SimdOpcode::V128Load => {
+                let offset = code.next_u32()? as usize;
+                let addr = pop1!(S, self.env, regs) as u32 as usize;
+
+                let real_addr = offset + addr;
+                let high = read_or_mmio!(S, self.env, regs, real_addr + 8, 8, read_u64);
+                let low = read_or_mmio!(S, self.env, regs, real_addr, 8, read_u64);
+                trace!(S, self.env, regs, self.env.trace_load(offset, addr, low));
+                trace!(S, self.env, regs, self.env.trace_load(offset + 8, addr, high));
+                push_v128!(S, self.env, regs, ((high as u128) << 64) | low as u128);
+            },
+            SimdOpcode::V128Store => {
+                let offset = code.next_u32()? as usize;
+                let v = pop_v128!(S, self.env, regs);
+                let addr = pop1!(S, self.env, regs) as u32 as usize;
+
+                // The high half goes first so that nothing is written if the range is out of bounds.
+                let real_addr = offset + addr;
+                write_or_mmio!(S, self.env, regs, real_addr + 8, 8, write_u64, (v >> 64) as u64);
+                write_or_mmio!(S, self.env, regs, real_addr, 8, write_u64, v as u64);
+            },
+            SimdOpcode::V128Const => {
+                let low = code.next_u64()?;
+                let high = code.next_u64()?;
+                push_v128!(S, self.env, regs, ((high as u128) << 64) | low as u128);
+            },
+            SimdOpcode::I8x16Splat => {
+                let x = pop1!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::splat(x as u8));
+            },
+            SimdOpcode::I16x8Splat => {
+                let x = pop1!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::splat(x as u16));
+            },
+            SimdOpcode::I32x4Splat => {
+                let x = pop1!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::splat(x as u32));
+            },
+            SimdOpcode::I64x2Splat => {
+                let x = pop1!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::splat(x as u64));
+            },
+            SimdOpcode::I8x16ExtractLaneS => {
+                let lane = simd::check_lane::<i8>(*code.next()?)?;
+                let v = pop_v128!(S, self.env, regs);
+                push1!(S, self.env, regs, i8::get(v, lane) as i64);
+            },
+            SimdOpcode::I8x16ExtractLaneU => {
+                let lane = simd::check_lane::<u8>(*code.next()?)?;
+                let v = pop_v128!(S, self.env, regs);
+                push1!(S, self.env, regs, u8::get(v, lane) as i64);
+            },
+            SimdOpcode::I16x8ExtractLaneS => {
+                let lane = simd::check_lane::<i16>(*code.next()?)?;
+                let v = pop_v128!(S, self.env, regs);
+                push1!(S, self.env, regs, i16::get(v, lane) as i64);
+            },
+            SimdOpcode::I16x8ExtractLaneU => {
+                let lane = simd::check_lane::<u16>(*code.next()?)?;
+                let v = pop_v128!(S, self.env, regs);
+                push1!(S, self.env, regs, u16::get(v, lane) as i64);
+            },
+            SimdOpcode::I32x4ExtractLane => {
+                let lane = simd::check_lane::<u32>(*code.next()?)?;
+                let v = pop_v128!(S, self.env, regs);
+                push1!(S, self.env, regs, u32::get(v, lane) as i64);
+            },
+            SimdOpcode::I64x2ExtractLane => {
+                let lane = simd::check_lane::<u64>(*code.next()?)?;
+                let v = pop_v128!(S, self.env, regs);
+                push1!(S, self.env, regs, u64::get(v, lane) as i64);
+            },
+            SimdOpcode::I8x16ReplaceLane => {
+                let lane = simd::check_lane::<u8>(*code.next()?)?;
+                let x = pop1!(S, self.env, regs);
+                let v = pop_v128!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::replace_lane(v, lane, x as u8));
+            },
+            SimdOpcode::I16x8ReplaceLane => {
+                let lane = simd::check_lane::<u16>(*code.next()?)?;
+                let x = pop1!(S, self.env, regs);
+                let v = pop_v128!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::replace_lane(v, lane, x as u16));
+            },
+            SimdOpcode::I32x4ReplaceLane => {
+                let lane = simd::check_lane::<u32>(*code.next()?)?;
+                let x = pop1!(S, self.env, regs);
+                let v = pop_v128!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::replace_lane(v, lane, x as u32));
+            },
+            SimdOpcode::I64x2ReplaceLane => {
+                let lane = simd::check_lane::<u64>(*code.next()?)?;
+                let x = pop1!(S, self.env, regs);
+                let v = pop_v128!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::replace_lane(v, lane, x as u64));
+            },
+            SimdOpcode::I8x16Shuffle => {
+                let selectors = code.next_many(16)?;
+                let right = pop_v128!(S, self.env, regs);
+                let left = pop_v128!(S, self.env, regs);
+                push_v128!(S, self.env, regs, simd::shuffle(left, right, selectors)?);
+            },
+            SimdOpcode::I8x16Swizzle => run_simd_binop!(S, self.env, regs, simd::swizzle),
+            SimdOpcode::V128Not => run_simd_unop!(S, self.env, regs, |a: u128| !a),
+            SimdOpcode::V128And => run_simd_binop!(S, self.env, regs, |a: u128, b: u128| a & b),
+            SimdOpcode::V128Or => run_simd_binop!(S, self.env, regs, |a: u128, b: u128| a | b),
+            SimdOpcode::V128Xor => run_simd_binop!(S, self.env, regs, |a: u128, b: u128| a ^ b),
+            SimdOpcode::V128AndNot => run_simd_binop!(S, self.env, regs, |a: u128, b: u128| a & !b),
+            SimdOpcode::V128AnyTrue => run_simd_test!(S, self.env, regs, |a: u128| a != 0),
+
+            SimdOpcode::I8x16Add => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u8, y: u8| x.wrapping_add(y))),
+            SimdOpcode::I8x16Sub => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u8, y: u8| x.wrapping_sub(y))),
+            SimdOpcode::I8x16Neg => run_simd_unop!(S, self.env, regs, |a| simd::map1(a, |x: i8| x.wrapping_neg())),
+            SimdOpcode::I8x16Eq => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u8, y: u8| x == y)),
+            SimdOpcode::I8x16Ne => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u8, y: u8| x != y)),
+            SimdOpcode::I8x16LtS => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: i8, y: i8| x < y)),
+            SimdOpcode::I8x16LtU => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u8, y: u8| x < y)),
+            SimdOpcode::I8x16GtS => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: i8, y: i8| x > y)),
+            SimdOpcode::I8x16GtU => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u8, y: u8| x > y)),
+            SimdOpcode::I8x16MinS => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: i8, y: i8| x.min(y))),
+            SimdOpcode::I8x16MinU => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u8, y: u8| x.min(y))),
+            SimdOpcode::I8x16MaxS => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: i8, y: i8| x.max(y))),
+            SimdOpcode::I8x16MaxU => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u8, y: u8| x.max(y))),
+            SimdOpcode::I8x16Shl => run_simd_shift!(S, self.env, regs, u8, |x: u8, n: u32| x.wrapping_shl(n)),
+            SimdOpcode::I8x16ShrS => run_simd_shift!(S, self.env, regs, i8, |x: i8, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I8x16ShrU => run_simd_shift!(S, self.env, regs, u8, |x: u8, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I8x16AllTrue => run_simd_test!(S, self.env, regs, simd::all_true::<u8>),
+
+            SimdOpcode::I16x8Add => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u16, y: u16| x.wrapping_add(y))),
+            SimdOpcode::I16x8Sub => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u16, y: u16| x.wrapping_sub(y))),
+            SimdOpcode::I16x8Mul => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u16, y: u16| x.wrapping_mul(y))),
+            SimdOpcode::I16x8Neg => run_simd_unop!(S, self.env, regs, |a| simd::map1(a, |x: i16| x.wrapping_neg())),
+            SimdOpcode::I16x8Eq => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u16, y: u16| x == y)),
+            SimdOpcode::I16x8Ne => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u16, y: u16| x != y)),
+            SimdOpcode::I16x8LtS => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: i16, y: i16| x < y)),
+            SimdOpcode::I16x8LtU => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u16, y: u16| x < y)),
+            SimdOpcode::I16x8GtS => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: i16, y: i16| x > y)),
+            SimdOpcode::I16x8GtU => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u16, y: u16| x > y)),
+            SimdOpcode::I16x8MinS => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: i16, y: i16| x.min(y))),
+            SimdOpcode::I16x8MinU => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u16, y: u16| x.min(y))),
+            SimdOpcode::I16x8MaxS => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: i16, y: i16| x.max(y))),
+            SimdOpcode::I16x8MaxU => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u16, y: u16| x.max(y))),
+            SimdOpcode::I16x8Shl => run_simd_shift!(S, self.env, regs, u16, |x: u16, n: u32| x.wrapping_shl(n)),
+            SimdOpcode::I16x8ShrS => run_simd_shift!(S, self.env, regs, i16, |x: i16, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I16x8ShrU => run_simd_shift!(S, self.env, regs, u16, |x: u16, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I16x8AllTrue => run_simd_test!(S, self.env, regs, simd::all_true::<u16>),
+
+            SimdOpcode::I32x4Add => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u32, y: u32| x.wrapping_add(y))),
+            SimdOpcode::I32x4Sub => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u32, y: u32| x.wrapping_sub(y))),
+            SimdOpcode::I32x4Mul => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u32, y: u32| x.wrapping_mul(y))),
+            SimdOpcode::I32x4Neg => run_simd_unop!(S, self.env, regs, |a| simd::map1(a, |x: i32| x.wrapping_neg())),
+            SimdOpcode::I32x4Eq => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u32, y: u32| x == y)),
+            SimdOpcode::I32x4Ne => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u32, y: u32| x != y)),
+            SimdOpcode::I32x4LtS => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: i32, y: i32| x < y)),
+            SimdOpcode::I32x4LtU => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u32, y: u32| x < y)),
+            SimdOpcode::I32x4GtS => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: i32, y: i32| x > y)),
+            SimdOpcode::I32x4GtU => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u32, y: u32| x > y)),
+            SimdOpcode::I32x4MinS => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: i32, y: i32| x.min(y))),
+            SimdOpcode::I32x4MinU => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u32, y: u32| x.min(y))),
+            SimdOpcode::I32x4MaxS => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: i32, y: i32| x.max(y))),
+            SimdOpcode::I32x4MaxU => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u32, y: u32| x.max(y))),
+            SimdOpcode::I32x4Shl => run_simd_shift!(S, self.env, regs, u32, |x: u32, n: u32| x.wrapping_shl(n)),
+            SimdOpcode::I32x4ShrS => run_simd_shift!(S, self.env, regs, i32, |x: i32, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I32x4ShrU => run_simd_shift!(S, self.env, regs, u32, |x: u32, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I32x4AllTrue => run_simd_test!(S, self.env, regs, simd::all_true::<u32>),
+
+            SimdOpcode::I64x2Add => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u64, y: u64| x.wrapping_add(y))),
+            SimdOpcode::I64x2Sub => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u64, y: u64| x.wrapping_sub(y))),
+            SimdOpcode::I64x2Mul => run_simd_binop!(S, self.env, regs, |a, b| simd::map2(a, b, |x: u64, y: u64| x.wrapping_mul(y))),
+            SimdOpcode::I64x2Neg => run_simd_unop!(S, self.env, regs, |a| simd::map1(a, |x: i64| x.wrapping_neg())),
+            SimdOpcode::I64x2Eq => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u64, y: u64| x == y)),
+            SimdOpcode::I64x2Ne => run_simd_binop!(S, self.env, regs, |a, b| simd::compare(a, b, |x: u64, y: u64| x != y)),
+            SimdOpcode::I64x2Shl => run_simd_shift!(S, self.env, regs, u64, |x: u64, n: u32| x.wrapping_shl(n)),
+            SimdOpcode::I64x2ShrS => run_simd_shift!(S, self.env, regs, i64, |x: i64, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I64x2ShrU => run_simd_shift!(S, self.env, regs, u64, |x: u64, n: u32| x.wrapping_shr(n)),
+            SimdOpcode::I64x2AllTrue => run_simd_test!(S, self.env, regs, simd::all_true::<u64>),
+
+            SimdOpcode::Never => {
+                return Err(ExecuteError::IllegalOpcode);
+            }
+        }
+
+        Ok(())
+    }
 }
 
-trait Memory {
+pub(crate) trait Memory {
     fn read_u8(&self, ra: usize) -> ExecuteResult<u8>;
     fn read_u16(&self, ra: usize) -> ExecuteResult<u16>;
     fn read_u32(&self, ra: usize) -> ExecuteResult<u32>;
@@ -562,6 +1998,28 @@ trait Memory {
     fn write_u16(&mut self, ra: usize, v: u16) -> ExecuteResult<()>;
     fn write_u32(&mut self, ra: usize, v: u32) -> ExecuteResult<()>;
     fn write_u64(&mut self, ra: usize, v: u64) -> ExecuteResult<()>;
+
+    fn copy_range(&mut self, src: usize, dest: usize, len: usize) -> ExecuteResult<()>;
+    fn fill_range(&mut self, dest: usize, val: u8, len: usize) -> ExecuteResult<()>;
+    fn write_bytes(&mut self, dest: usize, data: &[u8]) -> ExecuteResult<()>;
+}
+
+// Memory that no other instance can access, so atomic operations only need
+// bounds and alignment checks.
+trait AtomicMemory {
+    fn check_atomic_address(&self, ra: usize, width: usize) -> ExecuteResult<()>;
+
+    fn atomic_load_u32(&self, ra: usize) -> ExecuteResult<u32>;
+    fn atomic_load_u64(&self, ra: usize) -> ExecuteResult<u64>;
+
+    fn atomic_store_u32(&mut self, ra: usize, v: u32) -> ExecuteResult<()>;
+    fn atomic_store_u64(&mut self, ra: usize, v: u64) -> ExecuteResult<()>;
+
+    fn atomic_rmw_u32(&mut self, ra: usize, op: AtomicRmwOp, v: u32) -> ExecuteResult<u32>;
+    fn atomic_rmw_u64(&mut self, ra: usize, op: AtomicRmwOp, v: u64) -> ExecuteResult<u64>;
+
+    fn atomic_cmpxchg_u32(&mut self, ra: usize, expected: u32, replacement: u32) -> ExecuteResult<u32>;
+    fn atomic_cmpxchg_u64(&mut self, ra: usize, expected: u64, replacement: u64) -> ExecuteResult<u64>;
 }
 
 fn bounds_check<T>(target: &[T], start: usize, len: usize) -> ExecuteResult<()> {
@@ -572,6 +2030,14 @@ fn bounds_check<T>(target: &[T], start: usize, len: usize) -> ExecuteResult<()>
     }
 }
 
+// Unlike `bounds_check`, accepts an empty range at the end of `target`.
+fn range_check<T>(target: &[T], start: usize, len: usize) -> ExecuteResult<()> {
+    match start.checked_add(len) {
+        Some(end) if end <= target.len() => Ok(()),
+        _ => Err(ExecuteError::Bounds)
+    }
+}
+
 impl Memory for [u8] {
     fn read_u8(&self, ra: usize) -> ExecuteResult<u8> {
         bounds_check(self, ra, 1)?;
@@ -616,6 +2082,87 @@ impl Memory for [u8] {
         LittleEndian::write_u64(&mut self[ra..], v);
         Ok(())
     }
+
+    fn copy_range(&mut self, src: usize, dest: usize, len: usize) -> ExecuteResult<()> {
+        range_check(self, src, len)?;
+        range_check(self, dest, len)?;
+        self.copy_within(src..src + len, dest);
+        Ok(())
+    }
+
+    fn fill_range(&mut self, dest: usize, val: u8, len: usize) -> ExecuteResult<()> {
+        range_check(self, dest, len)?;
+        self[dest..dest + len].fill(val);
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, dest: usize, data: &[u8]) -> ExecuteResult<()> {
+        range_check(self, dest, data.len())?;
+        self[dest..dest + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl AtomicMemory for [u8] {
+    fn check_atomic_address(&self, ra: usize, width: usize) -> ExecuteResult<()> {
+        range_check(self, ra, width)?;
+        if !ra.is_multiple_of(width) {
+            return Err(ExecuteError::MisalignedAtomic);
+        }
+        Ok(())
+    }
+
+    fn atomic_load_u32(&self, ra: usize) -> ExecuteResult<u32> {
+        self.check_atomic_address(ra, 4)?;
+        self.read_u32(ra)
+    }
+
+    fn atomic_load_u64(&self, ra: usize) -> ExecuteResult<u64> {
+        self.check_atomic_address(ra, 8)?;
+        self.read_u64(ra)
+    }
+
+    fn atomic_store_u32(&mut self, ra: usize, v: u32) -> ExecuteResult<()> {
+        self.check_atomic_address(ra, 4)?;
+        self.write_u32(ra, v)
+    }
+
+    fn atomic_store_u64(&mut self, ra: usize, v: u64) -> ExecuteResult<()> {
+        self.check_atomic_address(ra, 8)?;
+        self.write_u64(ra, v)
+    }
+
+    fn atomic_rmw_u32(&mut self, ra: usize, op: AtomicRmwOp, v: u32) -> ExecuteResult<u32> {
+        self.check_atomic_address(ra, 4)?;
+        let old = self.read_u32(ra)?;
+        self.write_u32(ra, op.apply_u32(old, v))?;
+        Ok(old)
+    }
+
+    fn atomic_rmw_u64(&mut self, ra: usize, op: AtomicRmwOp, v: u64) -> ExecuteResult<u64> {
+        self.check_atomic_address(ra, 8)?;
+        let old = self.read_u64(ra)?;
+        self.write_u64(ra, op.apply_u64(old, v))?;
+        Ok(old)
+    }
+
+    fn atomic_cmpxchg_u32(&mut self, ra: usize, expected: u32, replacement: u32) -> ExecuteResult<u32> {
+        self.check_atomic_address(ra, 4)?;
+        let old = self.read_u32(ra)?;
+        if old == expected {
+            self.write_u32(ra, replacement)?;
+        }
+        Ok(old)
+    }
+
+    fn atomic_cmpxchg_u64(&mut self, ra: usize, expected: u64, replacement: u64) -> ExecuteResult<u64> {
+        self.check_atomic_address(ra, 8)?;
+        let old = self.read_u64(ra)?;
+        if old == expected {
+            self.write_u64(ra, replacement)?;
+        }
+        Ok(old)
+    }
 }
 
 /*
@@ -0,0 +1,315 @@
+// A subset of WASI preview1 on `NativeInvoke`, for reusing runtimes built for WebAssembly.
+// Each function has a fixed id from `WASI_BASE` on, takes the same arguments as its
+// WASI import (the last one on top of the operand stack) and returns its errno, except
+// for `proc_exit`, which stops the VM with `ExecuteError::Exit`.
+//
+// Pointers are u32 addresses into linear memory and the structures behind them (iovecs,
+// argv arrays, timestamps and sizes) use the little-endian layout of `vm::Memory`.
+// Pointers outside linear memory give `ERRNO_FAULT`. Clocks and randomness come from
+// `SyscallEnvironment`, and only the stdio descriptors 0 to 2 exist.
+//
+// Arguments are only popped once the call has returned an errno or exits, so that other
+// failures, such as too few operands or `ExecuteError::NotSupported` for shared linear
+// memory, leave the operand stack as it was.
+
+use core::cmp::min;
+use environment::Environment;
+use syscall::{write_memory, SyscallEnvironment};
+use verifier::NativeSignature;
+use vm::Memory;
+use error::*;
+
+pub const WASI_BASE: usize = 256;
+pub const WASI_ARGS_GET: usize = 256;
+pub const WASI_ARGS_SIZES_GET: usize = 257;
+pub const WASI_ENVIRON_GET: usize = 258;
+pub const WASI_ENVIRON_SIZES_GET: usize = 259;
+pub const WASI_CLOCK_TIME_GET: usize = 260;
+pub const WASI_FD_READ: usize = 261;
+pub const WASI_FD_WRITE: usize = 262;
+pub const WASI_PROC_EXIT: usize = 263;
+pub const WASI_RANDOM_GET: usize = 264;
+
+pub type Errno = u16;
+
+pub const ERRNO_SUCCESS: Errno = 0;
+pub const ERRNO_BADF: Errno = 8;
+pub const ERRNO_FAULT: Errno = 21;
+pub const ERRNO_INVAL: Errno = 28;
+pub const ERRNO_IO: Errno = 29;
+pub const ERRNO_NOTSUP: Errno = 58;
+
+pub const CLOCK_REALTIME: u32 = 0;
+pub const CLOCK_MONOTONIC: u32 = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: u32 = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: u32 = 3;
+
+pub const FD_STDIN: u32 = 0;
+pub const FD_STDOUT: u32 = 1;
+pub const FD_STDERR: u32 = 2;
+
+// Data is copied between the environment and linear memory through a buffer of this size.
+const CHUNK_SIZE: usize = 256;
+
+pub trait WasiEnvironment: SyscallEnvironment {
+    // Command line arguments, without the terminating NUL.
+    fn wasi_arg(&self, _index: usize) -> Option<&[u8]> {
+        None
+    }
+
+    // Environment variables as `KEY=VALUE`, without the terminating NUL.
+    fn wasi_env_var(&self, _index: usize) -> Option<&[u8]> {
+        None
+    }
+
+    // Called with `FD_STDIN` only. Returns the number of bytes read, 0 at the end.
+    fn wasi_read(&mut self, _fd: u32, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(ERRNO_BADF)
+    }
+
+    // Called with `FD_STDOUT` or `FD_STDERR` only. Returns the number of bytes written.
+    fn wasi_write(&mut self, _fd: u32, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(ERRNO_BADF)
+    }
+}
+
+pub fn is_wasi_syscall(id: usize) -> bool {
+    (WASI_ARGS_GET..=WASI_RANDOM_GET).contains(&id)
+}
+
+// For use as the `native_sig` argument of `verifier::verify`.
+pub fn signature(id: usize) -> Option<NativeSignature> {
+    let (n_args, n_results) = match id {
+        WASI_ARGS_GET | WASI_ARGS_SIZES_GET | WASI_ENVIRON_GET | WASI_ENVIRON_SIZES_GET
+            | WASI_RANDOM_GET => (2, 1),
+        WASI_CLOCK_TIME_GET => (3, 1),
+        WASI_FD_READ | WASI_FD_WRITE => (4, 1),
+        WASI_PROC_EXIT => (1, 0),
+        _ => return None
+    };
+    Some(NativeSignature { n_args: n_args, n_results: n_results })
+}
+
+pub fn dispatch<E: WasiEnvironment>(env: &mut E, id: usize) -> ExecuteResult<Option<i64>> {
+    let n_args = signature(id).ok_or(ExecuteError::InvalidNativeInvoke)?.n_args;
+
+    let mut args = [0i64; 4];
+    for (v, arg) in args.iter_mut().zip(env.get_stack().tail_many(n_args)?) {
+        *v = arg.get();
+    }
+    let ptr = |i: usize| args[i] as u32 as usize;
+
+    let ret = match id {
+        WASI_ARGS_GET => strings_get(env, StringList::Args, ptr(0), ptr(1)),
+        WASI_ARGS_SIZES_GET => strings_sizes_get(env, StringList::Args, ptr(0), ptr(1)),
+        WASI_ENVIRON_GET => strings_get(env, StringList::Environ, ptr(0), ptr(1)),
+        WASI_ENVIRON_SIZES_GET => strings_sizes_get(env, StringList::Environ, ptr(0), ptr(1)),
+        WASI_CLOCK_TIME_GET => clock_time_get(env, args[0] as u32, ptr(2)),
+        WASI_FD_READ => fd_read(env, args[0] as u32, ptr(1), ptr(2), ptr(3)),
+        WASI_FD_WRITE => fd_write(env, args[0] as u32, ptr(1), ptr(2), ptr(3)),
+        WASI_PROC_EXIT => Err(ExecuteError::Exit(args[0] as i32)),
+        WASI_RANDOM_GET => random_get(env, ptr(0), ptr(1)),
+        _ => return Err(ExecuteError::InvalidNativeInvoke)
+    };
+
+    let errno = match ret {
+        Ok(v) => v,
+        Err(ExecuteError::Bounds) => ERRNO_FAULT,
+        Err(e @ ExecuteError::Exit(_)) => {
+            env.get_stack().prev_many(n_args)?;
+            return Err(e);
+        },
+        Err(e) => return Err(e)
+    };
+    env.get_stack().prev_many(n_args)?;
+    Ok(Some(errno as i64))
+}
+
+fn memory_mut<E: Environment>(env: &mut E) -> ExecuteResult<&mut [u8]> {
+    let len = env.get_memory().len();
+    write_memory(env, 0, len)
+}
+
+#[derive(Copy, Clone)]
+enum StringList {
+    Args,
+    Environ
+}
+
+fn get_string<E: WasiEnvironment>(env: &E, list: StringList, index: usize) -> Option<&[u8]> {
+    match list {
+        StringList::Args => env.wasi_arg(index),
+        StringList::Environ => env.wasi_env_var(index)
+    }
+}
+
+fn strings_sizes_get<E: WasiEnvironment>(
+    env: &mut E,
+    list: StringList,
+    count_ptr: usize,
+    buf_size_ptr: usize
+) -> ExecuteResult<Errno> {
+    let mut count: usize = 0;
+    let mut buf_size: usize = 0;
+    while let Some(v) = get_string(env, list, count) {
+        count += 1;
+        buf_size += v.len() + 1;
+    }
+
+    let mem = memory_mut(env)?;
+    mem.write_u32(count_ptr, count as u32)?;
+    mem.write_u32(buf_size_ptr, buf_size as u32)?;
+    Ok(ERRNO_SUCCESS)
+}
+
+// Writes a pointer to each NUL-terminated string, placed one after another in `buf`.
+fn strings_get<E: WasiEnvironment>(
+    env: &mut E,
+    list: StringList,
+    ptrs: usize,
+    buf: usize
+) -> ExecuteResult<Errno> {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut pos = buf;
+    let mut index: usize = 0;
+
+    // The strings borrow the environment, so they are copied a chunk at a time.
+    while let Some(len) = get_string(env, list, index).map(|v| v.len()) {
+        memory_mut(env)?.write_u32(ptrs + index * 4, pos as u32)?;
+
+        let mut offset: usize = 0;
+        while offset < len {
+            let n = min(chunk.len(), len - offset);
+            chunk[..n].copy_from_slice(&get_string(env, list, index).unwrap()[offset..offset + n]);
+            memory_mut(env)?.write_bytes(pos + offset, &chunk[..n])?;
+            offset += n;
+        }
+        memory_mut(env)?.write_u8(pos + len, 0)?;
+
+        pos += len + 1;
+        index += 1;
+    }
+
+    Ok(ERRNO_SUCCESS)
+}
+
+fn clock_time_get<E: WasiEnvironment>(env: &mut E, clock_id: u32, time_ptr: usize) -> ExecuteResult<Errno> {
+    let time = match clock_id {
+        CLOCK_REALTIME | CLOCK_MONOTONIC => {
+            let clock = match env.clock() {
+                Some(v) => v,
+                None => return Ok(ERRNO_NOTSUP)
+            };
+            if clock_id == CLOCK_REALTIME {
+                clock.wall_ns()?
+            } else {
+                clock.monotonic_ns()?
+            }
+        },
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => return Ok(ERRNO_NOTSUP),
+        _ => return Ok(ERRNO_INVAL)
+    };
+
+    memory_mut(env)?.write_u64(time_ptr, time as u64)?;
+    Ok(ERRNO_SUCCESS)
+}
+
+fn random_get<E: WasiEnvironment>(env: &mut E, buf: usize, buf_len: usize) -> ExecuteResult<Errno> {
+    write_memory(env, buf, buf_len)?;
+    if env.random().is_none() {
+        return Ok(ERRNO_NOTSUP);
+    }
+
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut pos = buf;
+    while pos < buf + buf_len {
+        let n = min(chunk.len(), buf + buf_len - pos);
+        env.random().unwrap().fill(&mut chunk[..n])?;
+        memory_mut(env)?.write_bytes(pos, &chunk[..n])?;
+        pos += n;
+    }
+    Ok(ERRNO_SUCCESS)
+}
+
+// Reads an iovec (also a ciovec): the u32 address of the buffer, then its u32 length.
+fn read_iovec<E: Environment>(env: &mut E, iovs: usize, index: usize) -> ExecuteResult<(usize, usize)> {
+    let mem = memory_mut(env)?;
+    let base = mem.read_u32(iovs + index * 8)? as usize;
+    let len = mem.read_u32(iovs + index * 8 + 4)? as usize;
+    write_memory(env, base, len)?;
+    Ok((base, len))
+}
+
+// Stops at the first short read.
+fn fd_read<E: WasiEnvironment>(
+    env: &mut E,
+    fd: u32,
+    iovs: usize,
+    iovs_len: usize,
+    nread_ptr: usize
+) -> ExecuteResult<Errno> {
+    if fd != FD_STDIN {
+        return Ok(ERRNO_BADF);
+    }
+
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut total: usize = 0;
+
+    'iovs: for i in 0..iovs_len {
+        let (base, len) = read_iovec(env, iovs, i)?;
+        let mut offset: usize = 0;
+        while offset < len {
+            let want = min(chunk.len(), len - offset);
+            let n = match env.wasi_read(fd, &mut chunk[..want]) {
+                Ok(v) => min(v, want),
+                Err(e) => return Ok(e)
+            };
+            memory_mut(env)?.write_bytes(base + offset, &chunk[..n])?;
+            offset += n;
+            total += n;
+            if n < want {
+                break 'iovs;
+            }
+        }
+    }
+
+    memory_mut(env)?.write_u32(nread_ptr, total as u32)?;
+    Ok(ERRNO_SUCCESS)
+}
+
+// Stops at the first short write.
+fn fd_write<E: WasiEnvironment>(
+    env: &mut E,
+    fd: u32,
+    iovs: usize,
+    iovs_len: usize,
+    nwritten_ptr: usize
+) -> ExecuteResult<Errno> {
+    if fd != FD_STDOUT && fd != FD_STDERR {
+        return Ok(ERRNO_BADF);
+    }
+
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut total: usize = 0;
+
+    'iovs: for i in 0..iovs_len {
+        let (base, len) = read_iovec(env, iovs, i)?;
+        let mut offset: usize = 0;
+        while offset < len {
+            let n = min(chunk.len(), len - offset);
+            chunk[..n].copy_from_slice(&memory_mut(env)?[base + offset..base + offset + n]);
+            let written = match env.wasi_write(fd, &chunk[..n]) {
+                Ok(v) => min(v, n),
+                Err(e) => return Ok(e)
+            };
+            offset += written;
+            total += written;
+            if written < n {
+                break 'iovs;
+            }
+        }
+    }
+
+    memory_mut(env)?.write_u32(nwritten_ptr, total as u32)?;
+    Ok(ERRNO_SUCCESS)
+}
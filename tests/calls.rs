@@ -0,0 +1,533 @@
+// Tests for calls, tail calls, exceptions and the VM limits.
+
+extern crate hexagon_e;
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::fixed_environment::FixedEnvironment;
+use hexagon_e::layer::{Hooks, Layer};
+use hexagon_e::module::{Opcode, TableEntry};
+use hexagon_e::tape::Tape;
+use hexagon_e::verifier::{self, CodeInfo};
+use hexagon_e::vm::{Limits, VirtualMachine};
+
+use common::*;
+
+fn call_stack_values<E: Environment>(env: &E) -> Vec<i64> {
+    let cs = env.get_call_stack();
+    (0..cs.get_pos()).map(|i| cs.at(i).unwrap().get()).collect()
+}
+
+// Records the call stack on every native invoke, and with the target and n_locals on
+// every call.
+#[derive(Default)]
+struct CallStackRecorder {
+    seen: RefCell<Vec<Vec<i64>>>,
+    calls: RefCell<Vec<(usize, usize, Vec<i64>)>>
+}
+
+impl<E: Environment> Hooks<E> for CallStackRecorder {
+    fn do_native_invoke(&mut self, env: &mut E, _id: usize) -> ExecuteResult<Option<i64>> {
+        self.seen.borrow_mut().push(call_stack_values(env));
+        Ok(None)
+    }
+
+    fn trace_call(&self, env: &E, target: usize, n_locals: usize) {
+        self.calls.borrow_mut().push((target, n_locals, call_stack_values(env)));
+    }
+}
+
+#[test]
+fn frame_layout() {
+    // f(a, b) with one more local calls g(a) with none.
+    let mut c = Code::new();
+    c.i32_const(5).i32_const(6).call(Opcode::CallDirect, 0, 2, 1);
+    let ret_f = c.pos();
+    c.op(Opcode::Halt);
+    let f = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).call(Opcode::CallDirect, 0, 1, 0);
+    let ret_g = c.pos();
+    c.op(Opcode::Return);
+    let g = c.pos();
+    c.native_invoke(0).op(Opcode::Return);
+    c.patch(11, f);
+    c.patch(f + 6, g);
+
+    let (ret, env) = run_in(Layer::new(TestEnv::default(), CallStackRecorder::default()), &module(&c.0), false);
+    assert_eq!(ret, Ok(()));
+    // [all_locals][n_all_locals][return_ip] per frame.
+    assert_eq!(env.hooks.seen.into_inner(), vec![vec![
+        5, 6, 0, 3, ret_f as i64,
+        5, 1, ret_g as i64
+    ]]);
+    // `trace_call` sees the frames of the callers.
+    assert_eq!(env.hooks.calls.into_inner(), vec![
+        (f as usize, 1, vec![]),
+        (g as usize, 0, vec![5, 6, 0, 3, ret_f as i64])
+    ]);
+    assert_eq!(env.inner.get_call_stack().get_pos(), 0);
+    assert_eq!(env.inner.get_frame_stack().unwrap().get_pos(), 0);
+}
+
+// f(a, b) with two more locals reads all four, then sets the last one.
+fn call_direct(n_args: u32, n_locals: u32) -> Code {
+    let mut c = Code::new();
+    c.i32_const(7).i32_const(8).call(Opcode::CallDirect, 24, n_args, n_locals).op(Opcode::Halt);
+    for i in 0..4 {
+        c.op_u32(Opcode::GetLocal, i);
+    }
+    c.i32_const(9).op_u32(Opcode::SetLocal, 3).op_u32(Opcode::GetLocal, 3).op(Opcode::Return);
+    c
+}
+
+#[test]
+fn call_direct_locals() {
+    assert_eq!(run_both(&call_direct(2, 2)), (Ok(()), vec![7, 8, 0, 0, 9]));
+
+    // Too few locals for f's accesses
+    assert_eq!(run_both(&call_direct(2, 1)).0, Err(ExecuteError::Bounds));
+
+    // More arguments than on the stack, which the verifier rejects.
+    let c = call_direct(3, 2);
+    assert_eq!(run(&c).0, Err(ExecuteError::Bounds));
+    assert_eq!(run_in(TestEnv::default(), &module(&c.0), true).0, Err(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn locals_after_nested_calls() {
+    // f(3) sets its local 1 to 13 and calls g(100) twice in between reading its locals
+    // again. g sets both of its own locals and pushes the sum of all three.
+    let mut c = Code::new();
+    c.i32_const(3).call(Opcode::CallDirect, 0, 1, 1).op(Opcode::Halt);
+    let f = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).i32_const(10).op(Opcode::I32Add).op_u32(Opcode::SetLocal, 1);
+    let call_g = c.pos();
+    c.i32_const(100).call(Opcode::CallDirect, 0, 1, 2);
+    c.op_u32(Opcode::GetLocal, 0).op_u32(Opcode::GetLocal, 1);
+    let call_g_again = c.pos();
+    c.i32_const(100).call(Opcode::CallDirect, 0, 1, 2);
+    c.op_u32(Opcode::GetLocal, 1).op(Opcode::Return);
+    let g = c.pos();
+    c.i32_const(50).op_u32(Opcode::SetLocal, 1).i32_const(60).op_u32(Opcode::SetLocal, 2);
+    c.op_u32(Opcode::GetLocal, 0).op_u32(Opcode::GetLocal, 1).op(Opcode::I32Add);
+    c.op_u32(Opcode::GetLocal, 2).op(Opcode::I32Add).op(Opcode::Return);
+    c.patch(6, f);
+    c.patch(call_g + 6, g);
+    c.patch(call_g_again + 6, g);
+
+    assert_eq!(run_both(&c), (Ok(()), vec![210, 3, 13, 210, 13]));
+}
+
+#[test]
+fn throw_unwinds_operand_stack() {
+    // The handler in f starts with only the tag and the payload on f's operand stack,
+    // above what its caller keeps there.
+    let mut c = Code::new();
+    c.i32_const(100).call(Opcode::CallDirect, 0, 0, 0).op(Opcode::I32Add).op(Opcode::Halt);
+    let f = c.pos();
+    c.i32_const(1).call(Opcode::CallDirect, 0, 0, 0);
+    let f_end = c.pos();
+    c.op(Opcode::Return);
+    let handler = c.pos();
+    c.op(Opcode::I32Add).op(Opcode::Return);
+    let g = c.pos();
+    c.i32_const(2).i32_const(7).i32_const(30).op(Opcode::Throw);
+    c.patch(6, f);
+    c.patch(f + 6, g);
+
+    let h = handlers(&[(f, f_end, handler, Some(7))]);
+    let mut m = module(&c.0);
+    m.handlers = &h;
+
+    let (ret, env) = run_in(TestEnv::default(), &m, false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.stack_values(), vec![137]);
+    assert_eq!(env.get_call_stack().get_pos(), 0);
+    assert_eq!(env.get_frame_stack().unwrap().get_pos(), 0);
+
+    // Handlers in called functions need a frame stack.
+    let stack = vec![Cell::new(0); 16];
+    let call_stack = vec![Cell::new(0); 16];
+    let env = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap();
+    assert_eq!(run_in(env, &m, false).0, Err(ExecuteError::NotSupported));
+
+    let frame_stack = vec![Cell::new(0); 2];
+    let env = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap()
+        .with_frame_stack(&frame_stack);
+    let (ret, env) = run_in(env, &m, false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_stack().get_pos(), 1);
+    assert_eq!(env.get_stack().at(0).unwrap().get(), 137);
+}
+
+#[test]
+fn calls_without_frame_stack() {
+    // Without handlers, the frame stack is never used.
+    let mut c = Code::new();
+    c.i32_const(1).call(Opcode::CallDirect, 19, 1, 0).op(Opcode::Halt);
+    c.op_u32(Opcode::GetLocal, 0).i32_const(2).op(Opcode::I32Add).op(Opcode::Return);
+
+    let stack = vec![Cell::new(0); 16];
+    let call_stack = vec![Cell::new(0); 16];
+    let env = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap();
+    let (ret, env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_stack().at(0).unwrap().get(), 3);
+}
+
+// An environment written from scratch, with only what the trait requires.
+struct BareEnv<'a> {
+    stack: Tape<'a, Cell<i64>>,
+    call_stack: Tape<'a, Cell<i64>>
+}
+
+impl<'a> Environment for BareEnv<'a> {
+    fn get_memory(&self) -> &[u8] {
+        &[]
+    }
+
+    fn get_memory_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    fn grow_memory(&mut self, _len_inc: usize) -> ExecuteResult<()> {
+        Err(ExecuteError::MemoryLimit)
+    }
+
+    fn get_slots(&self) -> &[i64] {
+        &[]
+    }
+
+    fn get_slots_mut(&mut self) -> &mut [i64] {
+        &mut []
+    }
+
+    fn reset_slots(&mut self, _len: usize) -> ExecuteResult<()> {
+        Err(ExecuteError::SlotLimit)
+    }
+
+    fn get_stack(&self) -> &Tape<Cell<i64>> {
+        &self.stack
+    }
+
+    fn get_call_stack(&self) -> &Tape<Cell<i64>> {
+        &self.call_stack
+    }
+}
+
+#[test]
+fn throw_in_custom_environment() {
+    // Without a frame stack, a throw can only be caught at the top level.
+    for &(tag, expected) in &[(2, Ok(())), (1, Err(ExecuteError::NotSupported))] {
+        let (c, h) = nested_throw(tag, false);
+        let mut m = module(&c.0);
+        m.handlers = &h;
+
+        let stack = vec![Cell::new(0); 16];
+        let call_stack = vec![Cell::new(0); 16];
+        let env = BareEnv {
+            stack: Tape::from(&stack[..]),
+            call_stack: Tape::from(&call_stack[..])
+        };
+        // Values pushed by the host before `run` survive a throw caught at the top level.
+        env.stack.next().unwrap().set(42);
+
+        let (ret, env) = run_in(env, &m, false);
+        assert_eq!(ret, expected);
+        if ret.is_ok() {
+            let values: Vec<i64> = (0..env.stack.get_pos()).map(|i| env.stack.at(i).unwrap().get()).collect();
+            assert_eq!(values, vec![42, 5]);
+            assert_eq!(env.call_stack.get_pos(), 0);
+        }
+    }
+}
+
+// f calls itself forever, counting the calls in slot 0.
+fn recursion() -> Code {
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Halt);
+    c.op_u32(Opcode::GetSlot, 0).i32_const(1).op(Opcode::I32Add).op_u32(Opcode::SetSlot, 0)
+        .call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Return);
+    c
+}
+
+#[test]
+fn max_call_depth() {
+    let c = recursion();
+    let limits = Limits { max_call_depth: 10, ..Limits::default() };
+    for &verified in &[false, true] {
+        let (ret, env) = run_limited(TestEnv::default(), &module(&c.0), verified, limits);
+        assert_eq!(ret, Err(ExecuteError::ExecutionLimit));
+        assert_eq!(env.slots[0], 10);
+    }
+
+    // Without the limit, the call stack runs out.
+    let (ret, _) = run_in(TestEnv::new(0, 16, 32), &module(&c.0), false);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn max_locals() {
+    let mut c = Code::new();
+    c.i32_const(1).i32_const(2).call(Opcode::CallDirect, 0, 2, 3).op(Opcode::Halt);
+    let f = c.pos();
+    c.op(Opcode::Return);
+    c.patch(11, f);
+
+    let limits = Limits { max_locals: 4, ..Limits::default() };
+    assert_eq!(run_limited(TestEnv::default(), &module(&c.0), false, limits).0, Err(ExecuteError::ExecutionLimit));
+    let limits = Limits { max_locals: 5, ..Limits::default() };
+    assert_eq!(run_limited(TestEnv::default(), &module(&c.0), false, limits).0, Ok(()));
+}
+
+#[test]
+fn max_memory() {
+    let mut c = Code::new();
+    c.i32_const(100).op(Opcode::GrowMemory).op(Opcode::Halt);
+    let limits = Limits { max_memory: 355, ..Limits::default() };
+    let (ret, env) = run_limited(TestEnv::default(), &module(&c.0), false, limits);
+    assert_eq!(ret, Err(ExecuteError::MemoryLimit));
+    assert_eq!(env.mem.len(), 256);
+
+    let limits = Limits { max_memory: 356, ..Limits::default() };
+    let (ret, env) = run_limited(TestEnv::default(), &module(&c.0), false, limits);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.stack_values(), vec![256]);
+    assert_eq!(env.mem.len(), 356);
+}
+
+#[test]
+fn max_slots() {
+    let mut c = Code::new();
+    c.op_u32(Opcode::ResetSlots, 9).op(Opcode::Halt);
+    let limits = Limits { max_slots: 8, ..Limits::default() };
+    let (ret, env) = run_limited(TestEnv::default(), &module(&c.0), false, limits);
+    assert_eq!(ret, Err(ExecuteError::SlotLimit));
+    assert_eq!(env.slots.len(), 4);
+
+    let limits = Limits { max_slots: 9, ..Limits::default() };
+    let (ret, env) = run_limited(TestEnv::default(), &module(&c.0), false, limits);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.slots.len(), 9);
+}
+
+// Calls table entry `id` with one argument. Entry 0 is f(x) = x + 1 and entry 1 is
+// null. g(x) = x, which returns one value more, is not in the table.
+fn call_indirect(id: u32, n_args: u32, n_locals: u32) -> (Code, Vec<u8>, u32) {
+    let mut c = Code::new();
+    c.i32_const(41).i32_const(id).op_u32(Opcode::CallIndirect, n_args).u32(n_locals).op(Opcode::Halt);
+    let f = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).i32_const(1).op(Opcode::I32Add).op(Opcode::Return);
+    let g = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).op(Opcode::Dup).op(Opcode::Return);
+    (c, table(&[Some((f, 1, 0)), None]), g)
+}
+
+fn run_indirect(id: u32, n_args: u32, n_locals: u32, verified: bool) -> (ExecuteResult<()>, TestEnv) {
+    let (c, t, _) = call_indirect(id, n_args, n_locals);
+    let mut m = module(&c.0);
+    m.table = &t;
+    run_in(TestEnv::default(), &m, verified)
+}
+
+#[test]
+fn call_indirect_checks() {
+    for &verified in &[false, true] {
+        let (ret, env) = run_indirect(0, 1, 0, verified);
+        assert_eq!(ret, Ok(()));
+        assert_eq!(env.stack_values(), vec![42]);
+
+        assert_eq!(run_indirect(1, 1, 0, verified).0, Err(ExecuteError::NullTableEntry));
+        assert_eq!(run_indirect(9, 1, 0, verified).0, Err(ExecuteError::Bounds));
+    }
+
+    assert_eq!(run_indirect(0, 1, 1, false).0, Err(ExecuteError::SignatureMismatch));
+    // No function in the initial table has that signature, so its results are unknown.
+    assert_eq!(run_indirect(0, 1, 1, true).0, Err(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn call_indirect_host_entries() {
+    // The host points entry 1 at g. Entries are only checked for n_args and n_locals, so
+    // only verified code notices that g returns two values where one is expected.
+    let (c, t, g) = call_indirect(1, 1, 0);
+    let mut m = module(&c.0);
+    m.table = &t;
+    let entry = Some(TableEntry { target: g, n_args: 1, n_locals: 0 });
+
+    let mut vm = VirtualMachine::new(&m, TestEnv::default());
+    vm.run_table_initializers().unwrap();
+    vm.env.table[1] = entry;
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(vm.env.stack_values(), vec![41, 41]);
+
+    let mut info = vec![CodeInfo::default(); c.0.len()];
+    let v = verifier::verify(&m, &mut info, |_| None).unwrap();
+    let mut vm = VirtualMachine::new_verified(&v, TestEnv::default());
+    vm.run_table_initializers().unwrap();
+    vm.env.table[1] = entry;
+    assert_eq!(vm.run(), Err(ExecuteError::SignatureMismatch));
+}
+
+// f(n) = if n != 0 { f(n - 1) } else { 77 }, called with 10000, recursing with `op`.
+fn countdown(op: Opcode) -> Code {
+    let mut c = Code::new();
+    c.i32_const(10000).call(Opcode::CallDirect, 19, 1, 0).op(Opcode::Halt);
+    let f = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).op_u32(Opcode::JmpIf, 0);
+    let jmp = c.pos() - 4;
+    c.i32_const(77).op(Opcode::Return);
+    let recurse = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).i32_const(1).op(Opcode::I32Sub);
+    match op {
+        Opcode::ReturnCallIndirect => c.i32_const(0).op_u32(op, 1).u32(0),
+        Opcode::ReturnCall => c.call(op, f, 1, 0),
+        _ => c.call(op, f, 1, 0).op(Opcode::Return)
+    };
+    c.patch(jmp, recurse);
+    assert_eq!(f, 19);
+    c
+}
+
+#[test]
+fn tail_calls_reuse_the_frame() {
+    let limits = Limits { max_call_depth: 1, ..Limits::default() };
+    let t = table(&[Some((19, 1, 0))]);
+    for &op in &[Opcode::ReturnCall, Opcode::ReturnCallIndirect] {
+        let c = countdown(op);
+        let mut m = module(&c.0);
+        m.table = &t;
+        for &verified in &[false, true] {
+            // Room for a single frame of three cells.
+            let (ret, env) = run_limited(TestEnv::new(0, 16, 3), &m, verified, limits);
+            assert_eq!(ret, Ok(()));
+            assert_eq!(env.stack_values(), vec![77]);
+            assert_eq!(env.get_call_stack().get_pos(), 0);
+            assert_eq!(env.get_frame_stack().unwrap().get_pos(), 0);
+        }
+    }
+
+    let c = countdown(Opcode::CallDirect);
+    for &verified in &[false, true] {
+        assert_eq!(run_limited(TestEnv::default(), &module(&c.0), verified, limits).0, Err(ExecuteError::ExecutionLimit));
+        assert_eq!(run_in(TestEnv::new(0, 16, 3), &module(&c.0), verified).0, Err(ExecuteError::Bounds));
+    }
+}
+
+#[test]
+fn tail_call_without_frame() {
+    // There is no frame to replace at the top level.
+    let mut c = Code::new();
+    c.call(Opcode::ReturnCall, 13, 0, 0).op(Opcode::Return);
+    assert_eq!(run(&c).0, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn return_into_caller_frame() {
+    // `run` starts in a frame that the host pushed, with no locals and returning to 1.
+    let mut c = Code::new();
+    c.op(Opcode::Return).i32_const(7).op(Opcode::Halt);
+    let h = handlers(&[(1, 6, 6, Some(9))]);
+    let mut m = module(&c.0);
+    m.handlers = &h;
+
+    for &host_frames in &[0, 1] {
+        let env = TestEnv::default();
+        let cs = env.get_call_stack();
+        cs.next().unwrap().set(0);
+        cs.next().unwrap().set(1);
+        // The frame stack entries of the host's frames are its own.
+        let fs = env.get_frame_stack().unwrap();
+        for _ in 0..host_frames {
+            fs.next().unwrap().set(99);
+        }
+
+        let (ret, env) = run_in(env, &m, false);
+        assert_eq!(ret, Ok(()));
+        assert_eq!(env.stack_values(), vec![7]);
+        assert_eq!(env.get_call_stack().get_pos(), 0);
+        assert_eq!(env.get_frame_stack().unwrap().get_pos(), host_frames);
+    }
+}
+
+#[test]
+fn resume_verified_with_caller_frame() {
+    // The verifier never saw the code the host's frame returns into, which pops more
+    // than the run pushed, so a verified module resumes on the checked path.
+    let mut c = Code::new();
+    c.op(Opcode::Return).op(Opcode::I32Add).op(Opcode::Halt);
+    let m = module(&c.0);
+
+    for &verified in &[false, true] {
+        let env = TestEnv::default();
+        let cs = env.get_call_stack();
+        cs.next().unwrap().set(0);
+        cs.next().unwrap().set(1);
+
+        let (ret, env) = run_in(env, &m, verified);
+        assert_eq!(ret, Err(ExecuteError::Bounds));
+        assert_eq!(env.stack_values(), Vec::<i64>::new());
+    }
+}
+
+// main calls f, which calls g, which throws `tag` with payload 5. f catches tag 1 and
+// main catches tag 2 or, if `catch_any`, anything. Handlers push 10 * their depth plus
+// the payload.
+fn nested_throw(tag: u32, catch_any: bool) -> (Code, Vec<u8>) {
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 0, 0, 0);
+    let main_end = c.pos();
+    c.op(Opcode::Halt);
+    let main_handler = c.pos();
+    c.op(Opcode::Swap2).op(Opcode::Drop).op(Opcode::Halt);
+
+    let f = c.pos();
+    c.i32_const(1000).call(Opcode::CallDirect, 0, 0, 0);
+    let f_end = c.pos();
+    c.op(Opcode::Return);
+    let f_handler = c.pos();
+    c.op(Opcode::Swap2).op(Opcode::Drop).i32_const(10).op(Opcode::I32Add).op(Opcode::Return);
+
+    let g = c.pos();
+    c.i32_const(2000).i32_const(tag).i32_const(5).op(Opcode::Throw);
+
+    c.patch(1, f);
+    c.patch(f + 6, g);
+    let main_tag = if catch_any { None } else { Some(2) };
+    (c, handlers(&[(f, f_end, f_handler, Some(1)), (0, main_end, main_handler, main_tag)]))
+}
+
+fn run_throw(tag: u32, catch_any: bool) -> (ExecuteResult<()>, Vec<i64>) {
+    let (c, h) = nested_throw(tag, catch_any);
+    let mut m = module(&c.0);
+    m.handlers = &h;
+
+    let (ret, env) = run_in(TestEnv::default(), &m, false);
+    let (ret_verified, env_verified) = run_in(TestEnv::default(), &m, true);
+    assert_eq!((ret, env.stack_values()), (ret_verified, env_verified.stack_values()));
+    // The frame stack stays in step with the call stack, whose frames take two cells.
+    assert_eq!(env.get_frame_stack().unwrap().get_pos() * 2, env.get_call_stack().get_pos());
+    (ret, env.stack_values())
+}
+
+#[test]
+fn throw_across_frames() {
+    // Caught one frame up; f returns to main normally.
+    assert_eq!(run_throw(1, false), (Ok(()), vec![15]));
+    // Caught two frames up, skipping f's handler.
+    assert_eq!(run_throw(2, false), (Ok(()), vec![5]));
+    assert_eq!(run_throw(3, true), (Ok(()), vec![5]));
+}
+
+#[test]
+fn uncaught_exception() {
+    assert_eq!(run_throw(3, false).0, Err(ExecuteError::UncaughtException(3)));
+
+    let mut c = Code::new();
+    c.i32_const(9).i32_const(0).op(Opcode::Throw);
+    assert_eq!(run_both(&c).0, Err(ExecuteError::UncaughtException(9)));
+}
@@ -0,0 +1,399 @@
+// Shared by the integration tests: a bytecode builder and an environment over heap
+// buffers that also records what syscalls and WASI calls do.
+
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+#[cfg(feature = "guard-memory")]
+use hexagon_e::guard_memory::GuardedMemory;
+use hexagon_e::module::{Module, Opcode, TableEntry};
+#[cfg(feature = "shared-memory")]
+use hexagon_e::shared_memory::SharedMemory;
+#[cfg(feature = "simd")]
+use hexagon_e::simd::SimdOpcode;
+use hexagon_e::syscall::{Clock, LogLevel, RandomSource, SeededRandom, SyscallEnvironment, VirtualClock};
+use hexagon_e::tape::Tape;
+use hexagon_e::verifier::{self, CodeInfo};
+use hexagon_e::vm::{Limits, VirtualMachine};
+use hexagon_e::wasi::{Errno, WasiEnvironment, FD_STDOUT};
+
+#[derive(Default)]
+pub struct Code(pub Vec<u8>);
+
+impl Code {
+    pub fn new() -> Code {
+        Code::default()
+    }
+
+    // Offset of the next byte, e.g. a jump target.
+    pub fn pos(&self) -> u32 {
+        self.0.len() as u32
+    }
+
+    pub fn op(&mut self, op: Opcode) -> &mut Code {
+        self.0.push(op as u8);
+        self
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Code {
+        self.0.push(v);
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Code {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Code {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn op_u32(&mut self, op: Opcode, v: u32) -> &mut Code {
+        self.op(op).u32(v)
+    }
+
+    pub fn i32_const(&mut self, v: u32) -> &mut Code {
+        self.op_u32(Opcode::I32Const, v)
+    }
+
+    pub fn i64_const(&mut self, v: u64) -> &mut Code {
+        self.op(Opcode::I64Const).u64(v)
+    }
+
+    pub fn f32_const(&mut self, v: f32) -> &mut Code {
+        self.op_u32(Opcode::F32Const, v.to_bits())
+    }
+
+    pub fn f64_const(&mut self, v: f64) -> &mut Code {
+        self.op(Opcode::F64Const).u64(v.to_bits())
+    }
+
+    #[cfg(feature = "simd")]
+    pub fn simd(&mut self, op: SimdOpcode) -> &mut Code {
+        self.op(Opcode::Simd).u8(op as u8)
+    }
+
+    #[cfg(feature = "simd")]
+    pub fn v128_const(&mut self, v: u128) -> &mut Code {
+        self.simd(SimdOpcode::V128Const).u64(v as u64).u64((v >> 64) as u64)
+    }
+
+    pub fn native_invoke(&mut self, id: u32) -> &mut Code {
+        self.op_u32(Opcode::NativeInvoke, id)
+    }
+
+    // `CallDirect`, `ReturnCall`, etc. with a target, n_args and n_locals.
+    pub fn call(&mut self, op: Opcode, target: u32, n_args: u32, n_locals: u32) -> &mut Code {
+        self.op_u32(op, target).u32(n_args).u32(n_locals)
+    }
+
+    // Overwrites the u32 at `at`, for forward references.
+    pub fn patch(&mut self, at: u32, v: u32) {
+        let at = at as usize;
+        self.0[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    }
+}
+
+pub fn module(code: &[u8]) -> Module {
+    Module {
+        memory_initializers: &[],
+        data_segments: &[],
+        table: &[],
+        handlers: &[],
+        code: code
+    }
+}
+
+// Serializes table entries of target, n_args and n_locals for `Module::table`.
+pub fn table(entries: &[Option<(u32, u32, u32)>]) -> Vec<u8> {
+    let mut out = vec![];
+    for e in entries {
+        let (target, n_args, n_locals) = e.unwrap_or((0xffffffff, 0, 0));
+        for v in &[target, n_args, n_locals] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    out
+}
+
+// Serializes handlers of start, end, target and tag for `Module::handlers`.
+pub fn handlers(entries: &[(u32, u32, u32, Option<u32>)]) -> Vec<u8> {
+    let mut out = vec![];
+    for &(start, end, target, tag) in entries {
+        for v in &[start, end, target, tag.unwrap_or(0xffffffff)] {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    out
+}
+
+// `len` bytes of zeroed shared memory with no room to grow.
+#[cfg(feature = "shared-memory")]
+pub fn shared_memory(len: usize) -> SharedMemory<'static> {
+    use std::sync::atomic::{AtomicU64, AtomicUsize};
+
+    let cells: Vec<AtomicU64> = (0..len.div_ceil(8)).map(|_| AtomicU64::new(0)).collect();
+    SharedMemory::new(Box::leak(cells.into_boxed_slice()), Box::leak(Box::new(AtomicUsize::new(len)))).unwrap()
+}
+
+fn leak_tape(n: usize) -> Tape<'static, Cell<i64>> {
+    Tape::from(&*Box::leak(vec![Cell::new(0); n].into_boxed_slice()))
+}
+
+pub type NativeHandler = fn(&mut TestEnv, usize) -> ExecuteResult<Option<i64>>;
+pub type OpcodeHook = fn(&TestEnv, &Opcode);
+
+pub struct TestEnv {
+    pub mem: Vec<u8>,
+    // Replaces `mem` if set.
+    #[cfg(feature = "guard-memory")]
+    pub guarded: Option<GuardedMemory>,
+    #[cfg(feature = "shared-memory")]
+    pub shared: Option<SharedMemory<'static>>,
+    pub slots: Vec<i64>,
+    pub table: Vec<Option<TableEntry>>,
+    // Handles `NativeInvoke`, e.g. `syscall::dispatch`.
+    pub native_handler: Option<NativeHandler>,
+    // Called by `trace_opcode`, e.g. to change the operand stack.
+    pub opcode_hook: Option<OpcodeHook>,
+    pub logs: RefCell<Vec<(LogLevel, String)>>,
+    pub user_calls: Vec<usize>,
+    pub clock: Option<VirtualClock>,
+    pub random: Option<SeededRandom>,
+    pub args: Vec<String>,
+    pub stdin: Vec<u8>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    stack: Tape<'static, Cell<i64>>,
+    call_stack: Tape<'static, Cell<i64>>,
+    frame_stack: Tape<'static, Cell<i64>>
+}
+
+impl Default for TestEnv {
+    fn default() -> TestEnv {
+        TestEnv::new(256, 64, 256)
+    }
+}
+
+impl TestEnv {
+    pub fn new(memory_size: usize, stack_size: usize, call_stack_size: usize) -> TestEnv {
+        TestEnv {
+            mem: vec![0; memory_size],
+            #[cfg(feature = "guard-memory")]
+            guarded: None,
+            #[cfg(feature = "shared-memory")]
+            shared: None,
+            slots: vec![0; 4],
+            table: vec![None; 4],
+            native_handler: None,
+            opcode_hook: None,
+            logs: RefCell::new(vec![]),
+            user_calls: vec![],
+            clock: None,
+            random: None,
+            args: vec![],
+            stdin: vec![],
+            stdout: vec![],
+            stderr: vec![],
+            stack: leak_tape(stack_size),
+            call_stack: leak_tape(call_stack_size),
+            frame_stack: leak_tape(call_stack_size)
+        }
+    }
+
+    // Pushes `args` onto the operand stack.
+    pub fn push(&self, args: &[i64]) {
+        for &arg in args {
+            self.stack.next().unwrap().set(arg);
+        }
+    }
+
+    pub fn stack_values(&self) -> Vec<i64> {
+        (0..self.stack.get_pos()).map(|i| self.stack.at(i).unwrap().get()).collect()
+    }
+}
+
+impl Environment for TestEnv {
+    fn get_memory(&self) -> &[u8] {
+        #[cfg(feature = "guard-memory")]
+        {
+            if let Some(ref mem) = self.guarded {
+                return mem.as_slice();
+            }
+        }
+        &self.mem
+    }
+
+    fn get_memory_mut(&mut self) -> &mut [u8] {
+        #[cfg(feature = "guard-memory")]
+        {
+            if let Some(ref mut mem) = self.guarded {
+                return mem.as_mut_slice();
+            }
+        }
+        &mut self.mem
+    }
+
+    fn grow_memory(&mut self, len_inc: usize) -> ExecuteResult<()> {
+        #[cfg(feature = "guard-memory")]
+        {
+            if let Some(ref mut mem) = self.guarded {
+                return mem.grow(len_inc);
+            }
+        }
+        self.mem.extend((0..len_inc).map(|_| 0));
+        Ok(())
+    }
+
+    #[cfg(feature = "guard-memory")]
+    fn get_guarded_memory(&self) -> Option<&GuardedMemory> {
+        self.guarded.as_ref()
+    }
+
+    #[cfg(feature = "guard-memory")]
+    fn get_guarded_memory_mut(&mut self) -> Option<&mut GuardedMemory> {
+        self.guarded.as_mut()
+    }
+
+    #[cfg(feature = "shared-memory")]
+    fn get_shared_memory(&self) -> Option<SharedMemory<'_>> {
+        self.shared
+    }
+
+    fn get_slots(&self) -> &[i64] {
+        &self.slots
+    }
+
+    fn get_slots_mut(&mut self) -> &mut [i64] {
+        &mut self.slots
+    }
+
+    fn reset_slots(&mut self, len: usize) -> ExecuteResult<()> {
+        self.slots = vec![0; len];
+        Ok(())
+    }
+
+    fn get_table(&self) -> &[Option<TableEntry>] {
+        &self.table
+    }
+
+    fn get_table_mut(&mut self) -> &mut [Option<TableEntry>] {
+        &mut self.table
+    }
+
+    fn get_stack(&self) -> &Tape<Cell<i64>> {
+        &self.stack
+    }
+
+    fn get_call_stack(&self) -> &Tape<Cell<i64>> {
+        &self.call_stack
+    }
+
+    fn get_frame_stack(&self) -> Option<&Tape<Cell<i64>>> {
+        Some(&self.frame_stack)
+    }
+
+    fn do_native_invoke(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        match self.native_handler {
+            Some(f) => f(self, id),
+            None => Err(ExecuteError::InvalidNativeInvoke)
+        }
+    }
+
+    fn trace_opcode(&self, op: &Opcode) -> ExecuteResult<()> {
+        if let Some(f) = self.opcode_hook {
+            f(self, op);
+        }
+        Ok(())
+    }
+}
+
+impl SyscallEnvironment for TestEnv {
+    fn log(&self, level: LogLevel, text: &str) {
+        self.logs.borrow_mut().push((level, text.to_string()));
+    }
+
+    fn clock(&mut self) -> Option<&mut dyn Clock> {
+        self.clock.as_mut().map(|v| v as &mut dyn Clock)
+    }
+
+    fn random(&mut self) -> Option<&mut dyn RandomSource> {
+        self.random.as_mut().map(|v| v as &mut dyn RandomSource)
+    }
+
+    // Returns `id + 1`.
+    fn user_syscall(&mut self, id: usize) -> ExecuteResult<Option<i64>> {
+        self.user_calls.push(id);
+        Ok(Some(id as i64 + 1))
+    }
+}
+
+// Reads `stdin` and writes stdout and everything else to `stdout` and `stderr`.
+impl WasiEnvironment for TestEnv {
+    fn wasi_arg(&self, index: usize) -> Option<&[u8]> {
+        self.args.get(index).map(|v| v.as_bytes())
+    }
+
+    fn wasi_read(&mut self, _fd: u32, buf: &mut [u8]) -> Result<usize, Errno> {
+        let n = std::cmp::min(buf.len(), self.stdin.len());
+        buf[..n].copy_from_slice(&self.stdin[..n]);
+        self.stdin.drain(..n);
+        Ok(n)
+    }
+
+    fn wasi_write(&mut self, fd: u32, buf: &[u8]) -> Result<usize, Errno> {
+        match fd {
+            FD_STDOUT => self.stdout.extend_from_slice(buf),
+            _ => self.stderr.extend_from_slice(buf)
+        }
+        Ok(buf.len())
+    }
+}
+
+// Runs `module` with its table initialized, verifying it first if `verified`.
+// Runs with the limits of `env`.
+pub fn run_in<E: Environment>(env: E, module: &Module, verified: bool) -> (ExecuteResult<()>, E) {
+    run_vm(env, module, verified, None)
+}
+
+pub fn run_limited<E: Environment>(env: E, module: &Module, verified: bool, limits: Limits) -> (ExecuteResult<()>, E) {
+    run_vm(env, module, verified, Some(limits))
+}
+
+fn run_vm<E: Environment>(env: E, module: &Module, verified: bool, limits: Option<Limits>) -> (ExecuteResult<()>, E) {
+    let mut info = vec![CodeInfo::default(); module.code.len()];
+    let mut vm = if verified {
+        match verifier::verify(module, &mut info, |_| None) {
+            Ok(v) => VirtualMachine::new_verified(&v, env),
+            Err(e) => return (Err(e), env)
+        }
+    } else {
+        VirtualMachine::new(module, env)
+    };
+
+    if let Some(limits) = limits {
+        vm.limits = limits;
+    }
+
+    let ret = vm.run_table_initializers().and_then(|_| vm.run());
+    (ret, vm.env)
+}
+
+// Runs `code` unverified and returns the operand stack.
+pub fn run(code: &Code) -> (ExecuteResult<()>, Vec<i64>) {
+    let (ret, env) = run_in(TestEnv::default(), &module(&code.0), false);
+    (ret, env.stack_values())
+}
+
+// Runs `code` both unverified and verified, which must give the same results.
+pub fn run_both(code: &Code) -> (ExecuteResult<()>, Vec<i64>) {
+    let checked = run(code);
+    let (ret, env) = run_in(TestEnv::default(), &module(&code.0), true);
+    assert_eq!((ret, env.stack_values()), checked);
+    checked
+}
@@ -0,0 +1,114 @@
+// Tests for `FixedEnvironment`'s capacity limits and native handler.
+
+extern crate hexagon_e;
+
+mod common;
+
+use std::cell::Cell;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::fixed_environment::FixedEnvironment;
+use hexagon_e::module::Opcode;
+
+use common::*;
+
+fn cells(n: usize) -> Vec<Cell<i64>> {
+    vec![Cell::new(0); n]
+}
+
+#[test]
+fn initial_memory_beyond_capacity() {
+    let mut memory = [0u8; 16];
+    let stack = cells(8);
+    let call_stack = cells(8);
+
+    assert!(FixedEnvironment::new(&mut memory, 17, &mut [], &stack, &call_stack).is_err());
+
+    let env = FixedEnvironment::new(&mut memory, 16, &mut [], &stack, &call_stack).unwrap();
+    assert_eq!(env.get_memory().len(), 16);
+    assert_eq!(env.memory_capacity(), 16);
+}
+
+#[test]
+fn memory_limit() {
+    let mut c = Code::new();
+    c.i32_const(9).op(Opcode::GrowMemory).op(Opcode::Halt);
+    let m = module(&c.0);
+
+    let mut memory = [0xffu8; 16];
+    let stack = cells(8);
+    let call_stack = cells(8);
+    let env = FixedEnvironment::new(&mut memory, 8, &mut [], &stack, &call_stack).unwrap();
+    let (ret, env) = run_in(env, &m, false);
+    assert_eq!(ret, Err(ExecuteError::MemoryLimit));
+    assert_eq!(env.get_memory().len(), 8);
+
+    let mut c = Code::new();
+    c.i32_const(8).op(Opcode::GrowMemory).op(Opcode::Halt);
+    let m = module(&c.0);
+
+    let stack = cells(8);
+    let env = FixedEnvironment::new(&mut memory, 8, &mut [], &stack, &call_stack).unwrap();
+    let (ret, env) = run_in(env, &m, false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_stack().at(0).unwrap().get(), 8);
+    // The grown part is zeroed.
+    assert_eq!(env.get_memory(), &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0][..]);
+}
+
+#[test]
+fn slot_limit() {
+    let mut slots = [7i64; 4];
+    let stack = cells(8);
+    let call_stack = cells(8);
+
+    let mut c = Code::new();
+    c.op_u32(Opcode::ResetSlots, 5).op(Opcode::Halt);
+    let env = FixedEnvironment::new(&mut [], 0, &mut slots, &stack, &call_stack).unwrap();
+    let (ret, env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Err(ExecuteError::SlotLimit));
+    assert_eq!(env.get_slots(), &[7, 7, 7, 7][..]);
+
+    let mut c = Code::new();
+    c.op_u32(Opcode::ResetSlots, 2).op(Opcode::Halt);
+    let env = FixedEnvironment::new(&mut [], 0, &mut slots, &stack, &call_stack).unwrap();
+    let (ret, env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_slots(), &[0, 0][..]);
+    assert_eq!(env.slots_capacity(), 4);
+}
+
+fn add_one(env: &mut FixedEnvironment, id: usize) -> ExecuteResult<Option<i64>> {
+    match id {
+        1 => Ok(Some(env.get_stack().prev()?.get() + 1)),
+        _ => Err(ExecuteError::InvalidNativeInvoke)
+    }
+}
+
+#[test]
+fn native_handler() {
+    let stack = cells(8);
+    let call_stack = cells(8);
+
+    let mut c = Code::new();
+    c.i32_const(41).native_invoke(1).op(Opcode::Halt);
+    let env = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap()
+        .with_native_handler(add_one);
+    let (ret, env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_stack().get_pos(), 1);
+    assert_eq!(env.get_stack().at(0).unwrap().get(), 42);
+
+    let mut c = Code::new();
+    c.native_invoke(2).op(Opcode::Halt);
+    let env = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap()
+        .with_native_handler(add_one);
+    let (ret, _) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Err(ExecuteError::InvalidNativeInvoke));
+
+    // No handler at all.
+    let env = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap();
+    let (ret, _) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Err(ExecuteError::InvalidNativeInvoke));
+}
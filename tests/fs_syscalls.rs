@@ -0,0 +1,179 @@
+// Tests for the sandboxed file system syscalls.
+
+#![cfg(feature = "std")]
+
+extern crate hexagon_e;
+
+use std::fs;
+use std::path::PathBuf;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::fs_syscall::*;
+use hexagon_e::std_environment::StdEnvironment;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("hexagon-e-fs-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(path.join("root/sub")).unwrap();
+        fs::write(path.join("root/input.txt"), b"hello world").unwrap();
+        fs::write(path.join("secret.txt"), b"secret").unwrap();
+        TempDir(path)
+    }
+
+    fn root(&self) -> PathBuf {
+        self.0.join("root")
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn new_env(dir: &TempDir) -> StdEnvironment {
+    StdEnvironment::builder()
+        .memory_size(256)
+        .standard_syscalls()
+        .fs(FsSandbox::new(dir.root()).unwrap())
+        .build()
+        .unwrap()
+}
+
+fn call(env: &mut StdEnvironment, id: usize, args: &[i64]) -> ExecuteResult<Option<i64>> {
+    for &arg in args {
+        env.get_stack().next().unwrap().set(arg);
+    }
+    env.do_native_invoke(id)
+}
+
+// Places `path` at address 0 and opens it.
+fn open(env: &mut StdEnvironment, path: &str, flags: u32) -> i64 {
+    env.memory[..path.len()].copy_from_slice(path.as_bytes());
+    call(env, SYSCALL_FS_OPEN, &[0, path.len() as i64, flags as i64]).unwrap().unwrap()
+}
+
+#[test]
+fn read_write_seek() {
+    let dir = TempDir::new("rws");
+    let mut env = new_env(&dir);
+
+    let h = open(&mut env, "/input.txt", FS_READ);
+    assert!(h > 0);
+    assert_eq!(call(&mut env, SYSCALL_FS_SEEK, &[h, 6, FS_SEEK_START as i64]), Ok(Some(6)));
+    assert_eq!(call(&mut env, SYSCALL_FS_READ, &[h, 100, 64]), Ok(Some(5)));
+    assert_eq!(&env.memory[100..105], b"world");
+    assert_eq!(call(&mut env, SYSCALL_FS_READ, &[h, 100, 64]), Ok(Some(0)));
+    assert_eq!(call(&mut env, SYSCALL_FS_CLOSE, &[h]), Ok(Some(0)));
+    assert_eq!(call(&mut env, SYSCALL_FS_CLOSE, &[h]), Ok(Some(FS_ERR_BAD_HANDLE)));
+
+    let h = open(&mut env, "sub/output.txt", FS_WRITE | FS_CREATE | FS_TRUNCATE);
+    assert!(h > 0);
+    assert_eq!(call(&mut env, SYSCALL_FS_WRITE, &[h, 100, 5]), Ok(Some(5)));
+    assert_eq!(call(&mut env, SYSCALL_FS_CLOSE, &[h]), Ok(Some(0)));
+    assert_eq!(fs::read(dir.root().join("sub/output.txt")).unwrap(), b"world");
+}
+
+#[test]
+fn errors() {
+    let dir = TempDir::new("errors");
+    let mut env = new_env(&dir);
+
+    assert_eq!(open(&mut env, "missing.txt", FS_READ), FS_ERR_NOT_FOUND);
+    assert_eq!(open(&mut env, "input.txt", 1 << 10), FS_ERR_INVALID);
+    assert_eq!(open(&mut env, "sub", FS_WRITE), FS_ERR_INVALID);
+    assert_eq!(call(&mut env, SYSCALL_FS_READ, &[1234, 0, 1]), Ok(Some(FS_ERR_BAD_HANDLE)));
+
+    // Guest memory is checked before anything else.
+    let h = open(&mut env, "input.txt", FS_READ);
+    assert_eq!(call(&mut env, SYSCALL_FS_READ, &[h, 250, 10]), Err(ExecuteError::Bounds));
+    env.memory[0] = 0xff;
+    assert_eq!(call(&mut env, SYSCALL_FS_OPEN, &[0, 1, FS_READ as i64]), Err(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn failures_keep_arguments() {
+    let dir = TempDir::new("keep");
+    let mut env = new_env(&dir);
+    let h = open(&mut env, "input.txt", FS_READ);
+    assert_eq!(env.get_stack().get_pos(), 0);
+    env.memory[0] = 0xff;
+
+    let cases: &[(usize, &[i64], ExecuteError)] = &[
+        (SYSCALL_FS_OPEN, &[0, 1, FS_READ as i64], ExecuteError::InvalidInput),
+        (SYSCALL_FS_READ, &[h, 250, 10], ExecuteError::Bounds),
+        (SYSCALL_FS_WRITE, &[h, 250, 10], ExecuteError::Bounds),
+        (SYSCALL_FS_STAT, &[1, 1, 250], ExecuteError::Bounds),
+        (SYSCALL_FS_READDIR, &[h, 250, 10], ExecuteError::Bounds),
+        (SYSCALL_FS_READ, &[100, 64], ExecuteError::Bounds)
+    ];
+    for &(id, args, err) in cases {
+        let memory = env.memory.clone();
+        assert_eq!(call(&mut env, id, args), Err(err));
+        assert_eq!(env.get_stack().get_pos(), args.len());
+        assert_eq!(env.memory, memory);
+        env.get_stack().set_pos(0).unwrap();
+    }
+
+    // Also without a sandbox.
+    let mut env = StdEnvironment::builder().memory_size(256).standard_syscalls().build().unwrap();
+    assert_eq!(call(&mut env, SYSCALL_FS_CLOSE, &[1]), Err(ExecuteError::NotSupported));
+    assert_eq!(env.get_stack().get_pos(), 1);
+}
+
+#[test]
+fn escapes_are_refused() {
+    let dir = TempDir::new("escape");
+    let mut env = new_env(&dir);
+
+    assert_eq!(open(&mut env, "../secret.txt", FS_READ), FS_ERR_ACCESS);
+    assert_eq!(open(&mut env, "sub/../../secret.txt", FS_READ), FS_ERR_ACCESS);
+    assert!(open(&mut env, "sub/../input.txt", FS_READ) > 0);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::symlink;
+
+        symlink(dir.0.join("secret.txt"), dir.root().join("link.txt")).unwrap();
+        symlink(&dir.0, dir.root().join("outside")).unwrap();
+        symlink(dir.0.join("new.txt"), dir.root().join("dangling.txt")).unwrap();
+        symlink("input.txt", dir.root().join("inside.txt")).unwrap();
+
+        assert_eq!(open(&mut env, "link.txt", FS_READ), FS_ERR_ACCESS);
+        assert_eq!(open(&mut env, "outside/secret.txt", FS_READ), FS_ERR_ACCESS);
+        assert_eq!(open(&mut env, "outside/new.txt", FS_WRITE | FS_CREATE), FS_ERR_ACCESS);
+        assert_eq!(open(&mut env, "dangling.txt", FS_WRITE | FS_CREATE), FS_ERR_ACCESS);
+        assert!(!dir.0.join("new.txt").exists());
+        assert!(open(&mut env, "inside.txt", FS_READ) > 0);
+    }
+}
+
+#[test]
+fn stat_and_readdir() {
+    let dir = TempDir::new("stat");
+    let mut env = new_env(&dir);
+
+    env.memory[..9].copy_from_slice(b"input.txt");
+    assert_eq!(call(&mut env, SYSCALL_FS_STAT, &[0, 9, 16]), Ok(Some(0)));
+    assert_eq!(&env.memory[16..32], &[11, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(call(&mut env, SYSCALL_FS_STAT, &[0, 3, 16]), Ok(Some(FS_ERR_NOT_FOUND)));
+
+    let h = open(&mut env, "/", FS_READ);
+    // Too small a buffer returns the length without advancing.
+    assert_eq!(call(&mut env, SYSCALL_FS_READDIR, &[h, 100, 4]), Ok(Some(9)));
+    assert_eq!(call(&mut env, SYSCALL_FS_READDIR, &[h, 100, 64]), Ok(Some(9)));
+    assert_eq!(&env.memory[100..109], b"input.txt");
+    assert_eq!(call(&mut env, SYSCALL_FS_READDIR, &[h, 100, 64]), Ok(Some(3)));
+    assert_eq!(&env.memory[100..103], b"sub");
+    assert_eq!(call(&mut env, SYSCALL_FS_READDIR, &[h, 100, 64]), Ok(Some(0)));
+}
+
+#[test]
+fn off_by_default() {
+    let mut env = StdEnvironment::builder().memory_size(256).standard_syscalls().build().unwrap();
+    assert_eq!(call(&mut env, SYSCALL_FS_OPEN, &[0, 1, FS_READ as i64]), Err(ExecuteError::NotSupported));
+}
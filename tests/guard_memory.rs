@@ -0,0 +1,104 @@
+// Tests for linear memory protected by guard pages.
+
+#![cfg(feature = "guard-memory")]
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::guard_memory::GuardedMemory;
+use hexagon_e::module::Opcode;
+
+use common::*;
+
+fn guarded_env() -> TestEnv {
+    let mut env = TestEnv::default();
+    env.guarded = Some(GuardedMemory::new(4096).unwrap());
+    env
+}
+
+fn run_guarded(code: &Code) -> (ExecuteResult<()>, TestEnv) {
+    run_in(guarded_env(), &module(&code.0), false)
+}
+
+#[test]
+fn in_bounds() {
+    let mut c = Code::new();
+    c.i32_const(4088).i64_const(0x1122334455667788).op_u32(Opcode::I64Store, 0)
+        .i32_const(4092).op_u32(Opcode::I32Load, 0).op(Opcode::Halt);
+    let (ret, env) = run_guarded(&c);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.stack_values(), vec![0x11223344]);
+    assert_eq!(env.get_memory()[4088], 0x88);
+}
+
+#[test]
+fn out_of_bounds_load() {
+    // The fault is reported by the load itself, not at a later branch.
+    let mut c = Code::new();
+    c.i32_const(4096).op_u32(Opcode::I32Load8U, 0).op(Opcode::Halt);
+    assert_eq!(run_guarded(&c).0, Err(ExecuteError::Bounds));
+
+    // Straddling the end of memory.
+    let mut c = Code::new();
+    c.i32_const(4090).op_u32(Opcode::I64Load, 0).op(Opcode::Halt);
+    assert_eq!(run_guarded(&c).0, Err(ExecuteError::Bounds));
+
+    // The largest address and offset.
+    let mut c = Code::new();
+    c.i32_const(0xffffffff).op_u32(Opcode::I64Load, 0xffffffff).op(Opcode::Halt);
+    assert_eq!(run_guarded(&c).0, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn out_of_bounds_store() {
+    // A store crossing the end of memory leaves the bytes below it untouched.
+    let mut c = Code::new();
+    c.i32_const(4092).i64_const(!0).op_u32(Opcode::I64Store, 0).op(Opcode::Halt);
+    let (ret, env) = run_guarded(&c);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+    assert_eq!(&env.get_memory()[4092..], &[0, 0, 0, 0]);
+
+    let mut c = Code::new();
+    c.i32_const(8192).i32_const(1).op_u32(Opcode::I32Store8, 0).op(Opcode::Halt);
+    assert_eq!(run_guarded(&c).0, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn guard_rearmed() {
+    // Discarded stores are not visible to later loads, and the guard still catches
+    // them after growing.
+    let mut c = Code::new();
+    c.i32_const(4096).i32_const(7).op_u32(Opcode::I32Store, 0).op(Opcode::Halt);
+    let (ret, env) = run_guarded(&c);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+
+    let mut c = Code::new();
+    c.i32_const(4096).op_u32(Opcode::I32Load, 0).op(Opcode::Halt);
+    let (ret, mut env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+
+    env.grow_memory(1).unwrap();
+    env.get_stack().set_pos(0).unwrap();
+    let (ret, env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.stack_values(), vec![0]);
+
+    let mut c = Code::new();
+    c.i32_const(8192).op_u32(Opcode::I32Load, 0).op(Opcode::Halt);
+    assert_eq!(run_in(env, &module(&c.0), false).0, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn many_regions() {
+    // More live memories than a single chunk of the region list holds.
+    let mems: Vec<GuardedMemory> = (0..100).map(|_| GuardedMemory::new(4096).unwrap()).collect();
+    let mut env = TestEnv::default();
+    env.guarded = mems.into_iter().last();
+
+    let mut c = Code::new();
+    c.i32_const(4096).op_u32(Opcode::I32Load, 0).op(Opcode::Halt);
+    assert_eq!(run_in(env, &module(&c.0), false).0, Err(ExecuteError::Bounds));
+}
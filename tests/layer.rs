@@ -0,0 +1,223 @@
+// Tests for stacking `Layer`s on a base environment.
+
+extern crate hexagon_e;
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::fixed_environment::FixedEnvironment;
+use hexagon_e::layer::{Hooks, Layer};
+use hexagon_e::module::Opcode;
+
+use common::*;
+
+// Adds the numbers on top of the stack for native id 1.
+struct Natives;
+
+impl<E: Environment> Hooks<E> for Natives {
+    const TRACE_STACK: bool = false;
+
+    fn do_native_invoke(&mut self, env: &mut E, id: usize) -> ExecuteResult<Option<i64>> {
+        match id {
+            1 => {
+                let b = env.get_stack().prev()?.get();
+                let a = env.get_stack().prev()?.get();
+                Ok(Some(a + b))
+            },
+            _ => env.do_native_invoke(id)
+        }
+    }
+}
+
+struct Fuel {
+    left: Cell<u64>,
+    max_memory: usize
+}
+
+impl<E: Environment> Hooks<E> for Fuel {
+    const TRACE_STACK: bool = false;
+
+    fn trace_opcode(&self, env: &E, op: &Opcode) -> ExecuteResult<()> {
+        match self.left.get() {
+            0 => Err(ExecuteError::ExecutionLimit),
+            n => {
+                self.left.set(n - 1);
+                env.trace_opcode(op)
+            }
+        }
+    }
+
+    fn grow_memory(&mut self, env: &mut E, len_inc: usize) -> ExecuteResult<()> {
+        if env.get_memory().len() + len_inc > self.max_memory {
+            return Err(ExecuteError::MemoryLimit);
+        }
+        env.grow_memory(len_inc)
+    }
+}
+
+#[derive(Default)]
+struct Tracer {
+    ops: RefCell<Vec<String>>,
+    natives: Vec<usize>
+}
+
+impl<E: Environment> Hooks<E> for Tracer {
+    const TRACE_STACK: bool = false;
+
+    fn trace_opcode(&self, env: &E, op: &Opcode) -> ExecuteResult<()> {
+        self.ops.borrow_mut().push(format!("{:?}", op));
+        env.trace_opcode(op)
+    }
+
+    fn do_native_invoke(&mut self, env: &mut E, id: usize) -> ExecuteResult<Option<i64>> {
+        self.natives.push(id);
+        env.do_native_invoke(id)
+    }
+}
+
+type Stacked<'a> = Layer<Layer<Layer<FixedEnvironment<'a>, Natives>, Fuel>, Tracer>;
+
+// Each layer opts out of tracing the operand stack, as does `FixedEnvironment`.
+const _: () = assert!(!<Stacked as Environment>::TRACE_STACK);
+
+// Records the top of the operand stack before each opcode.
+#[derive(Default)]
+struct TopRecorder {
+    tops: RefCell<Vec<i64>>
+}
+
+impl<E: Environment> Hooks<E> for TopRecorder {
+    fn trace_opcode(&self, env: &E, op: &Opcode) -> ExecuteResult<()> {
+        if let Ok(v) = env.get_stack().tail_many(1) {
+            self.tops.borrow_mut().push(v[0].get());
+        }
+        env.trace_opcode(op)
+    }
+}
+
+// Hooks trace the stack unless they opt out.
+const _: () = assert!(<Layer<FixedEnvironment<'static>, TopRecorder> as Environment>::TRACE_STACK);
+
+fn run(code: &Code, fuel: u64) -> (ExecuteResult<()>, Vec<i64>, Vec<String>, Vec<usize>, usize) {
+    let mut memory = [0u8; 64];
+    let mut slots = [0i64; 0];
+    let stack: [Cell<i64>; 16] = Default::default();
+    let call_stack: [Cell<i64>; 16] = Default::default();
+    let base = FixedEnvironment::new(&mut memory, 16, &mut slots, &stack, &call_stack).unwrap();
+
+    let env: Stacked = Layer::new(
+        Layer::new(
+            Layer::new(base, Natives),
+            Fuel { left: Cell::new(fuel), max_memory: 32 }
+        ),
+        Tracer::default()
+    );
+
+    let (ret, env) = run_in(env, &module(&code.0), false);
+
+    let n = env.get_stack().get_pos();
+    let values = (0..n).map(|i| env.get_stack().at(i).unwrap().get()).collect();
+    let ops = env.hooks.ops.borrow().clone();
+    (ret, values, ops, env.hooks.natives.clone(), env.get_memory().len())
+}
+
+#[test]
+fn stacked_layers() {
+    let mut code = Code::new();
+    code.i32_const(2);
+    code.i32_const(3);
+    code.native_invoke(1);
+    code.i32_const(16);
+    code.op(Opcode::GrowMemory);
+    code.op(Opcode::Halt);
+
+    let (ret, values, ops, natives, memory_len) = run(&code, 100);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(values, vec![5, 16]);
+    assert_eq!(ops, vec!["I32Const", "I32Const", "NativeInvoke", "I32Const", "GrowMemory", "Halt"]);
+    assert_eq!(natives, vec![1]);
+    assert_eq!(memory_len, 32);
+
+    // Out of fuel: the outer tracer still sees the opcode that failed.
+    let (ret, _, ops, natives, _) = run(&code, 2);
+    assert_eq!(ret, Err(ExecuteError::ExecutionLimit));
+    assert_eq!(ops.len(), 3);
+    assert!(natives.is_empty());
+}
+
+#[test]
+fn hooks_forward_by_default() {
+    let mut code = Code::new();
+    code.i32_const(17);
+    code.op(Opcode::GrowMemory);
+    code.op(Opcode::Halt);
+
+    // Past what `Fuel` allows
+    assert_eq!(run(&code, 100).0, Err(ExecuteError::MemoryLimit));
+
+    // Unknown ids reach `FixedEnvironment`, which has no native handler.
+    let mut code = Code::new();
+    code.native_invoke(2);
+    code.op(Opcode::Halt);
+    let (ret, _, _, natives, _) = run(&code, 100);
+    assert_eq!(ret, Err(ExecuteError::InvalidNativeInvoke));
+    assert_eq!(natives, vec![2]);
+}
+
+#[test]
+fn hooks_see_the_stack_by_default() {
+    let mut code = Code::new();
+    code.i32_const(5).i32_const(6).op(Opcode::I32Add).op(Opcode::Halt);
+
+    let stack: [Cell<i64>; 16] = Default::default();
+    let call_stack: [Cell<i64>; 16] = Default::default();
+    let base = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap();
+    let (ret, env) = run_in(Layer::new(base, TopRecorder::default()), &module(&code.0), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.hooks.tops.into_inner(), vec![5, 6, 11]);
+}
+
+// Pushes the number of opcodes run so far before every `Dup` and drops the top value
+// before every `I32Add`, below a fuel meter that does not trace the stack.
+#[derive(Default)]
+struct StackEditor {
+    n_ops: Cell<i64>
+}
+
+impl<E: Environment> Hooks<E> for StackEditor {
+    fn trace_opcode(&self, env: &E, op: &Opcode) -> ExecuteResult<()> {
+        self.n_ops.set(self.n_ops.get() + 1);
+        match *op {
+            Opcode::Dup => env.get_stack().next()?.set(self.n_ops.get()),
+            Opcode::I32Add => {
+                env.get_stack().prev()?;
+            },
+            _ => {}
+        }
+        env.trace_opcode(op)
+    }
+}
+
+#[test]
+fn hooks_change_the_stack() {
+    let mut code = Code::new();
+    code.i32_const(10).i32_const(20).i32_const(30).op(Opcode::I32Add);
+    code.op(Opcode::Dup).op(Opcode::I32Add).op(Opcode::Halt);
+
+    let stack: [Cell<i64>; 16] = Default::default();
+    let call_stack: [Cell<i64>; 16] = Default::default();
+    let base = FixedEnvironment::new(&mut [], 0, &mut [], &stack, &call_stack).unwrap();
+    let env = Layer::new(
+        Layer::new(base, StackEditor::default()),
+        Fuel { left: Cell::new(100), max_memory: 0 }
+    );
+
+    let (ret, env) = run_in(env, &module(&code.0), false);
+    assert_eq!(ret, Ok(()));
+    // [10, 20, 30] -> [10, 20] -> [30] -> [30, 5] -> [30, 5, 5] -> [30, 5] -> [35]
+    let values: Vec<i64> = (0..env.get_stack().get_pos()).map(|i| env.get_stack().at(i).unwrap().get()).collect();
+    assert_eq!(values, vec![35]);
+}
@@ -0,0 +1,259 @@
+// Tests for memory-mapped I/O regions.
+
+#![cfg(feature = "std")]
+
+extern crate hexagon_e;
+
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::mmio::{find_region, MmioDevice, MmioRegion};
+use hexagon_e::module::{Module, Opcode};
+#[cfg(feature = "simd")]
+use hexagon_e::simd::SimdOpcode;
+use hexagon_e::std_environment::StdEnvironment;
+
+use common::*;
+
+// Kind of access, offset, width and stored value
+type Access = (char, usize, usize, u64);
+type Log = Rc<RefCell<Vec<Access>>>;
+
+// Reads return 0x8877665544332211 shifted by the offset.
+struct Device(Log);
+
+impl MmioDevice for Device {
+    fn read(&mut self, offset: usize, width: usize) -> ExecuteResult<u64> {
+        self.0.borrow_mut().push(('r', offset, width, 0));
+        Ok(0x8877665544332211u64 >> (offset * 8))
+    }
+
+    fn write(&mut self, offset: usize, width: usize, val: u64) -> ExecuteResult<()> {
+        self.0.borrow_mut().push(('w', offset, width, val));
+        Ok(())
+    }
+}
+
+fn run(code: &Code, verified: bool) -> (ExecuteResult<()>, Vec<i64>, Vec<u8>, Vec<Access>) {
+    run_module(&module(&code.0), verified)
+}
+
+fn run_module(module: &Module, verified: bool) -> (ExecuteResult<()>, Vec<i64>, Vec<u8>, Vec<Access>) {
+    let log = Log::default();
+    let env = StdEnvironment::builder()
+        .memory_size(64)
+        .mmio_device(16, 8, Device(log.clone()))
+        .mmio_device(32, 4, Device(log.clone()))
+        .build()
+        .unwrap();
+
+    let (ret, env) = run_in(env, module, verified);
+
+    let stack = env.get_stack();
+    let values = (0..stack.get_pos()).map(|i| stack.at(i).unwrap().get()).collect();
+    let log = log.borrow().clone();
+    (ret, values, env.memory.clone(), log)
+}
+
+#[test]
+fn loads_and_stores() {
+    let mut code = Code::new();
+    // Within the first region
+    code.i32_const(16);
+    code.op_u32(Opcode::I64Load, 0);
+    code.i32_const(10);
+    code.op_u32(Opcode::I32Load8S, 10);
+    code.i32_const(18);
+    code.i32_const(0xabcd);
+    code.op_u32(Opcode::I32Store16, 0);
+    // The second region, with the offset immediate
+    code.i32_const(0);
+    code.i64_const(0x1_0000_0001);
+    code.op_u32(Opcode::I64Store32, 32);
+    // Normal memory
+    code.i32_const(40);
+    code.i32_const(7);
+    code.op_u32(Opcode::I32Store8, 0);
+    code.i32_const(40);
+    code.op_u32(Opcode::I32Load, 0);
+    code.op(Opcode::Halt);
+
+    for &verified in &[false, true] {
+        let (ret, values, memory, log) = run(&code, verified);
+        assert_eq!(ret, Ok(()));
+        assert_eq!(values, vec![0x8877665544332211u64 as i64, 0x55, 7]);
+        assert_eq!(log, vec![
+            ('r', 0, 8, 0),
+            ('r', 4, 1, 0),
+            ('w', 2, 2, 0xabcd),
+            ('w', 0, 4, 1)
+        ]);
+        // The bytes underneath are untouched.
+        assert!(memory[16..24].iter().all(|&b| b == 0));
+        assert!(memory[32..36].iter().all(|&b| b == 0));
+        assert_eq!(memory[40], 7);
+    }
+}
+
+#[test]
+fn sign_extension() {
+    let mut code = Code::new();
+    code.i32_const(23);
+    code.op_u32(Opcode::I64Load8S, 0);
+    code.i32_const(22);
+    code.op_u32(Opcode::I32Load16U, 0);
+    code.op(Opcode::Halt);
+
+    let (ret, values, _, _) = run(&code, false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(values, vec![0x88u8 as i8 as i64, 0x8877]);
+}
+
+#[test]
+fn partial_overlap() {
+    for &(addr, op) in &[(14u32, Opcode::I32Load), (22, Opcode::I32Load), (30, Opcode::I64Load)] {
+        let mut code = Code::new();
+        code.i32_const(addr);
+        code.op_u32(op, 0);
+        code.op(Opcode::Halt);
+
+        let (ret, _, _, log) = run(&code, false);
+        assert_eq!(ret, Err(ExecuteError::Bounds));
+        assert!(log.is_empty());
+    }
+
+    // Adjacent to a region
+    let regions = [MmioRegion::new(16, 8, 0)];
+    assert_eq!(find_region(&regions, 12, 4), Ok(None));
+    assert_eq!(find_region(&regions, 24, 8), Ok(None));
+    assert_eq!(find_region(&regions, 20, 4), Ok(Some(regions[0])));
+    assert_eq!(find_region(&regions, usize::MAX, 2), Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn overlapping_regions_are_refused() {
+    let log = Log::default();
+    let ret = StdEnvironment::builder()
+        .mmio_device(16, 8, Device(log.clone()))
+        .mmio_device(20, 8, Device(log.clone()))
+        .build();
+    assert_eq!(ret.err(), Some(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn bulk_memory_traps() {
+    // dest, src / value and len
+    let cases = [
+        (Opcode::MemCopy, 20u32, 0u32, 2u32),
+        (Opcode::MemCopy, 0, 30, 4),
+        (Opcode::MemFill, 8, 0xff, 12),
+        (Opcode::MemoryInit, 34, 0, 2)
+    ];
+    let data_segments = [2u8, 0, 0, 0, 0xaa, 0xbb];
+
+    for &(op, dest, arg, len) in &cases {
+        let mut code = Code::new();
+        code.i32_const(dest).i32_const(arg).i32_const(len);
+        match op {
+            Opcode::MemoryInit => code.op_u32(op, 0),
+            _ => code.op(op)
+        };
+        code.op(Opcode::Halt);
+
+        let mut m = module(&code.0);
+        m.data_segments = &data_segments;
+        let (ret, _, memory, log) = run_module(&m, false);
+        assert_eq!(ret, Err(ExecuteError::MmioAccess));
+        assert!(log.is_empty());
+        assert!(memory.iter().all(|&b| b == 0));
+    }
+
+    // Empty ranges and ranges next to a region are fine.
+    let mut code = Code::new();
+    code.i32_const(16).i32_const(0xff).i32_const(0).op(Opcode::MemFill);
+    code.i32_const(8).i32_const(0xff).i32_const(8).op(Opcode::MemFill);
+    code.i32_const(24).i32_const(8).i32_const(8).op(Opcode::MemCopy);
+    code.op(Opcode::Halt);
+
+    let (ret, _, memory, log) = run(&code, false);
+    assert_eq!(ret, Ok(()));
+    assert!(log.is_empty());
+    assert!(memory[8..16].iter().all(|&b| b == 0xff));
+    assert!(memory[16..24].iter().all(|&b| b == 0));
+    assert!(memory[24..32].iter().all(|&b| b == 0xff));
+}
+
+#[test]
+fn atomics_trap() {
+    let ops: &[(Opcode, u32, u32)] = &[
+        (Opcode::I32AtomicLoad, 16, 1),
+        (Opcode::I64AtomicLoad, 32, 1),
+        (Opcode::I32AtomicStore, 20, 2),
+        (Opcode::I64AtomicStore, 32, 2),
+        (Opcode::I32AtomicRmwAdd, 32, 2),
+        (Opcode::I64AtomicRmwXchg, 16, 2),
+        (Opcode::I32AtomicRmwCmpxchg, 16, 3),
+        (Opcode::I64AtomicRmwCmpxchg, 32, 3),
+        (Opcode::MemoryAtomicWait32, 16, 3),
+        (Opcode::MemoryAtomicNotify, 32, 2)
+    ];
+
+    for &(op, addr, n_operands) in ops {
+        let mut code = Code::new();
+        code.i32_const(addr);
+        for _ in 1..n_operands {
+            code.i32_const(0);
+        }
+        code.op_u32(op, 0);
+        code.op(Opcode::Halt);
+
+        for &verified in &[false, true] {
+            let (ret, _, _, log) = run(&code, verified);
+            assert_eq!(ret, Err(ExecuteError::MmioAccess));
+            assert!(log.is_empty());
+        }
+    }
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn v128_loads_and_stores() {
+    let mut code = Code::new();
+    // The high half in the first region
+    code.i32_const(8);
+    code.simd(SimdOpcode::V128Load).u32(0);
+    // The low half in the first region
+    code.i32_const(16);
+    code.v128_const(0x100f0e0d0c0b0a09_0807060504030201);
+    code.simd(SimdOpcode::V128Store).u32(0);
+    code.op(Opcode::Halt);
+
+    let (ret, values, memory, log) = run(&code, false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(values, vec![0, 0x8877665544332211u64 as i64]);
+    assert_eq!(log, vec![
+        ('r', 0, 8, 0),
+        ('w', 0, 8, 0x0807060504030201)
+    ]);
+    assert!(memory[16..24].iter().all(|&b| b == 0));
+    assert_eq!(&memory[24..32], &[9, 10, 11, 12, 13, 14, 15, 16]);
+
+    // Partially within the second region
+    for &op in &[SimdOpcode::V128Load, SimdOpcode::V128Store] {
+        let mut code = Code::new();
+        code.i32_const(28);
+        if let SimdOpcode::V128Store = op {
+            code.i64_const(0).i64_const(0);
+        }
+        code.simd(op).u32(0);
+        code.op(Opcode::Halt);
+
+        let (ret, _, _, log) = run(&code, false);
+        assert_eq!(ret, Err(ExecuteError::Bounds));
+        assert!(log.is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+// Tests for argument and result conversion of typed native functions.
+
+#![cfg(feature = "std")]
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::module::Opcode;
+use hexagon_e::native::NativeRegistry;
+use hexagon_e::std_environment::StdEnvironment;
+
+use common::*;
+
+// Calls native `id` of `natives` with `args` on the operand stack.
+fn call(natives: &NativeRegistry<TestEnv>, id: usize, args: &[i64]) -> ExecuteResult<Option<i64>> {
+    let mut env = TestEnv::default();
+    env.push(args);
+    let f = natives.get(id)?;
+    f(&mut env)
+}
+
+// The number of arguments and results.
+fn signature(natives: &NativeRegistry<TestEnv>, id: usize) -> Option<(usize, usize)> {
+    natives.signature(id).map(|s| (s.n_args, s.n_results))
+}
+
+#[test]
+fn argument_order() {
+    let mut natives = NativeRegistry::new();
+    natives.register(0, |_: &mut TestEnv, a: i64, b: i64| a - b);
+    assert_eq!(call(&natives, 0, &[10, 3]), Ok(Some(7)));
+    assert_eq!(signature(&natives, 0), Some((2, 1)));
+}
+
+#[test]
+fn converts_32_bit_arguments() {
+    let mut natives = NativeRegistry::new();
+    natives.register(0, |_: &mut TestEnv, a: i32| a as i64);
+    natives.register(1, |_: &mut TestEnv, a: u32| a as i64);
+
+    // Zero- and sign-extended forms are both accepted.
+    assert_eq!(call(&natives, 0, &[0xffffffff]), Ok(Some(-1)));
+    assert_eq!(call(&natives, 0, &[-1]), Ok(Some(-1)));
+    assert_eq!(call(&natives, 1, &[-1]), Ok(Some(0xffffffff)));
+
+    assert_eq!(call(&natives, 0, &[0x100000000]), Err(ExecuteError::NativeArgumentMismatch));
+    assert_eq!(call(&natives, 0, &[-0x80000001]), Err(ExecuteError::NativeArgumentMismatch));
+    assert_eq!(call(&natives, 1, &[0x1_0000_0001]), Err(ExecuteError::NativeArgumentMismatch));
+}
+
+#[test]
+fn converts_other_arguments() {
+    let mut natives = NativeRegistry::new();
+    natives.register(0, |_: &mut TestEnv, a: bool| !a);
+    natives.register(1, |_: &mut TestEnv, a: f64, b: f32| a + b as f64);
+
+    assert_eq!(call(&natives, 0, &[0]), Ok(Some(1)));
+    assert_eq!(call(&natives, 0, &[1]), Ok(Some(0)));
+    assert_eq!(call(&natives, 0, &[2]), Err(ExecuteError::NativeArgumentMismatch));
+
+    let args = [1.5f64.to_bits() as i64, 0.25f32.to_bits() as i64];
+    assert_eq!(call(&natives, 1, &args), Ok(Some(1.75f64.to_bits() as i64)));
+}
+
+#[test]
+fn converts_results() {
+    let mut natives = NativeRegistry::new();
+    natives.register(0, |_: &mut TestEnv| -1i32);
+    natives.register(1, |_: &mut TestEnv| ());
+    natives.register(2, |_: &mut TestEnv| -> ExecuteResult<u32> { Err(ExecuteError::Fuse) });
+
+    // 32-bit results are zero-extended.
+    assert_eq!(call(&natives, 0, &[]), Ok(Some(0xffffffff)));
+    assert_eq!(call(&natives, 1, &[]), Ok(None));
+    assert_eq!(call(&natives, 2, &[]), Err(ExecuteError::Fuse));
+
+    assert_eq!(signature(&natives, 1), Some((0, 0)));
+    assert_eq!(signature(&natives, 2), Some((0, 1)));
+}
+
+#[test]
+fn too_few_operands() {
+    let mut natives = NativeRegistry::new();
+    natives.register(0, |_: &mut TestEnv, a: i64, b: i64| a + b);
+    assert_eq!(call(&natives, 0, &[1]), Err(ExecuteError::NativeArgumentMismatch));
+    assert_eq!(call(&natives, 0, &[]), Err(ExecuteError::NativeArgumentMismatch));
+
+    assert_eq!(call(&natives, 1, &[]), Err(ExecuteError::InvalidNativeInvoke));
+}
+
+#[test]
+fn native_invoke() {
+    let mut c = Code::new();
+    c.i32_const(40).i32_const(2).native_invoke(7).op(Opcode::Halt);
+    let env = StdEnvironment::builder()
+        .typed_native(7, |_: &mut StdEnvironment, a: i32, b: i32| a + b)
+        .build()
+        .unwrap();
+    let (ret, env) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_stack().get_pos(), 1);
+    assert_eq!(env.get_stack().at(0).unwrap().get(), 42);
+
+    // Missing operands fail the run.
+    let mut c = Code::new();
+    c.i32_const(40).native_invoke(7).op(Opcode::Halt);
+    let env = StdEnvironment::builder()
+        .typed_native(7, |_: &mut StdEnvironment, a: i32, b: i32| a + b)
+        .build()
+        .unwrap();
+    let (ret, _) = run_in(env, &module(&c.0), false);
+    assert_eq!(ret, Err(ExecuteError::NativeArgumentMismatch));
+}
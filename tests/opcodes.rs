@@ -0,0 +1,399 @@
+// Behavior tests for individual opcodes. Programs run both unverified and verified.
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::module::Opcode;
+
+use common::*;
+
+fn f32_result(v: &[i64]) -> f32 {
+    f32::from_bits(v[0] as u32)
+}
+
+fn f64_result(v: &[i64]) -> f64 {
+    f64::from_bits(v[0] as u64)
+}
+
+fn trunc_f32(op: Opcode, v: f32) -> (ExecuteResult<()>, Vec<i64>) {
+    let mut c = Code::new();
+    c.f32_const(v).op(op).op(Opcode::Halt);
+    run_both(&c)
+}
+
+fn trunc_f64(op: Opcode, v: f64) -> (ExecuteResult<()>, Vec<i64>) {
+    let mut c = Code::new();
+    c.f64_const(v).op(op).op(Opcode::Halt);
+    run_both(&c)
+}
+
+#[test]
+fn float_truncation() {
+    assert_eq!(trunc_f32(Opcode::I32TruncF32S, -2.9), (Ok(()), vec![-2]));
+    assert_eq!(trunc_f64(Opcode::I32TruncF64U, 4294967295.9), (Ok(()), vec![0xffffffff]));
+    assert_eq!(trunc_f64(Opcode::I32TruncF64S, -2147483648.9), (Ok(()), vec![-2147483648]));
+    assert_eq!(trunc_f64(Opcode::I64TruncF64S, -9223372036854775808.0), (Ok(()), vec![i64::MIN]));
+    assert_eq!(trunc_f32(Opcode::I64TruncF32U, -0.9), (Ok(()), vec![0]));
+
+    for &op in &[Opcode::I32TruncF32S, Opcode::I32TruncF32U, Opcode::I64TruncF32S, Opcode::I64TruncF32U] {
+        assert_eq!(trunc_f32(op, f32::NAN).0, Err(ExecuteError::InvalidConversion));
+        assert_eq!(trunc_f32(op, f32::INFINITY).0, Err(ExecuteError::InvalidConversion));
+    }
+    for &op in &[Opcode::I32TruncF64S, Opcode::I32TruncF64U, Opcode::I64TruncF64S, Opcode::I64TruncF64U] {
+        assert_eq!(trunc_f64(op, f64::NAN).0, Err(ExecuteError::InvalidConversion));
+        assert_eq!(trunc_f64(op, f64::NEG_INFINITY).0, Err(ExecuteError::InvalidConversion));
+    }
+
+    // Just outside the range.
+    assert_eq!(trunc_f64(Opcode::I32TruncF64S, 2147483648.0).0, Err(ExecuteError::InvalidConversion));
+    assert_eq!(trunc_f64(Opcode::I32TruncF64S, -2147483649.0).0, Err(ExecuteError::InvalidConversion));
+    assert_eq!(trunc_f64(Opcode::I32TruncF64U, 4294967296.0).0, Err(ExecuteError::InvalidConversion));
+    assert_eq!(trunc_f64(Opcode::I32TruncF64U, -1.0).0, Err(ExecuteError::InvalidConversion));
+    assert_eq!(trunc_f32(Opcode::I64TruncF32S, 9223372036854775808.0).0, Err(ExecuteError::InvalidConversion));
+    assert_eq!(trunc_f64(Opcode::I64TruncF64U, 18446744073709551616.0).0, Err(ExecuteError::InvalidConversion));
+}
+
+fn float_binop_f32(op: Opcode, a: f32, b: f32) -> f32 {
+    let mut c = Code::new();
+    c.f32_const(a).f32_const(b).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    assert_eq!(ret, Ok(()));
+    f32_result(&v)
+}
+
+fn float_binop_f64(op: Opcode, a: f64, b: f64) -> f64 {
+    let mut c = Code::new();
+    c.f64_const(a).f64_const(b).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    assert_eq!(ret, Ok(()));
+    f64_result(&v)
+}
+
+#[test]
+fn float_min_max() {
+    assert_eq!(float_binop_f32(Opcode::F32Min, 1.5, -2.0), -2.0);
+    assert_eq!(float_binop_f64(Opcode::F64Max, 1.5, -2.0), 1.5);
+
+    // -0 is less than +0 in either order.
+    assert_eq!(float_binop_f32(Opcode::F32Min, 0.0, -0.0).to_bits(), (-0.0f32).to_bits());
+    assert_eq!(float_binop_f32(Opcode::F32Min, -0.0, 0.0).to_bits(), (-0.0f32).to_bits());
+    assert_eq!(float_binop_f32(Opcode::F32Max, -0.0, 0.0).to_bits(), 0.0f32.to_bits());
+    assert_eq!(float_binop_f64(Opcode::F64Min, 0.0, -0.0).to_bits(), (-0.0f64).to_bits());
+    assert_eq!(float_binop_f64(Opcode::F64Max, 0.0, -0.0).to_bits(), 0.0f64.to_bits());
+    assert_eq!(float_binop_f64(Opcode::F64Max, -0.0, 0.0).to_bits(), 0.0f64.to_bits());
+
+    // NaN in either operand gives NaN.
+    assert!(float_binop_f32(Opcode::F32Min, f32::NAN, 1.0).is_nan());
+    assert!(float_binop_f32(Opcode::F32Max, 1.0, f32::NAN).is_nan());
+    assert!(float_binop_f64(Opcode::F64Min, 1.0, f64::NAN).is_nan());
+    assert!(float_binop_f64(Opcode::F64Max, f64::NAN, 1.0).is_nan());
+}
+
+// Like `run_both`, but returns linear memory.
+fn run_mem(code: &Code) -> (ExecuteResult<()>, Vec<u8>) {
+    let (ret, env) = run_in(TestEnv::default(), &module(&code.0), false);
+    let (ret_verified, env_verified) = run_in(TestEnv::default(), &module(&code.0), true);
+    assert_eq!((ret, &env.mem), (ret_verified, &env_verified.mem));
+    (ret, env.mem)
+}
+
+fn store_bytes(c: &mut Code, at: u32, bytes: &[u8]) {
+    for (i, &b) in bytes.iter().enumerate() {
+        c.i32_const(at + i as u32).i32_const(b as u32).op_u32(Opcode::I32Store8, 0);
+    }
+}
+
+#[test]
+fn mem_copy_overlapping() {
+    // Forwards: dest above src.
+    let mut c = Code::new();
+    store_bytes(&mut c, 10, &[1, 2, 3, 4, 5]);
+    c.i32_const(12).i32_const(10).i32_const(5).op(Opcode::MemCopy).op(Opcode::Halt);
+    let (ret, mem) = run_mem(&c);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(&mem[10..17], &[1, 2, 1, 2, 3, 4, 5]);
+
+    // Backwards: dest below src.
+    let mut c = Code::new();
+    store_bytes(&mut c, 10, &[1, 2, 3, 4, 5]);
+    c.i32_const(8).i32_const(10).i32_const(5).op(Opcode::MemCopy).op(Opcode::Halt);
+    let (ret, mem) = run_mem(&c);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(&mem[8..15], &[1, 2, 3, 4, 5, 4, 5]);
+}
+
+#[test]
+fn mem_copy_out_of_bounds() {
+    for &(dest, src) in &[(250, 0), (0, 250), (0xffffffff, 0)] {
+        let mut c = Code::new();
+        store_bytes(&mut c, 0, &[9; 8]);
+        c.i32_const(dest).i32_const(src).i32_const(8).op(Opcode::MemCopy).op(Opcode::Halt);
+        let (ret, mem) = run_mem(&c);
+        assert_eq!(ret, Err(ExecuteError::Bounds));
+        assert_eq!(&mem[0..8], &[9; 8]);
+        assert_eq!(&mem[248..], &[0; 8]);
+    }
+}
+
+#[test]
+fn mem_fill() {
+    let mut c = Code::new();
+    c.i32_const(4).i32_const(0x1ab).i32_const(3).op(Opcode::MemFill).op(Opcode::Halt);
+    let (ret, mem) = run_mem(&c);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(&mem[3..8], &[0, 0xab, 0xab, 0xab, 0]);
+
+    // Nothing is written if the range does not fit.
+    let mut c = Code::new();
+    c.i32_const(250).i32_const(7).i32_const(7).op(Opcode::MemFill).op(Opcode::Halt);
+    let (ret, mem) = run_mem(&c);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+    assert!(mem.iter().all(|&b| b == 0));
+
+    let mut c = Code::new();
+    c.i32_const(0xffffffff).i32_const(7).i32_const(2).op(Opcode::MemFill).op(Opcode::Halt);
+    assert_eq!(run_mem(&c).0, Err(ExecuteError::Bounds));
+
+    // An empty fill at the end of memory is fine.
+    let mut c = Code::new();
+    c.i32_const(256).i32_const(7).i32_const(0).op(Opcode::MemFill).op(Opcode::Halt);
+    assert_eq!(run_mem(&c).0, Ok(()));
+}
+
+// Runs `code` on local memory and, with the `shared-memory` feature, on shared memory.
+fn run_atomic(code: &Code) -> ExecuteResult<Vec<i64>> {
+    let (ret, v) = run_both(code);
+    #[cfg(feature = "shared-memory")]
+    {
+        let mut env = TestEnv::default();
+        env.shared = Some(shared_memory(256));
+        let (shared_ret, env) = run_in(env, &module(&code.0), false);
+        assert_eq!((shared_ret, env.stack_values()), (ret, v.clone()));
+    }
+    ret.map(|_| v)
+}
+
+#[test]
+fn misaligned_atomics() {
+    for &(addr, offset) in &[(1, 0), (0, 2), (6, 0)] {
+        let mut c = Code::new();
+        c.i32_const(addr).op_u32(Opcode::I64AtomicLoad, offset).op(Opcode::Halt);
+        assert_eq!(run_atomic(&c), Err(ExecuteError::MisalignedAtomic));
+    }
+
+    let ops: Vec<fn(&mut Code, u32)> = vec![
+        |c, at| { c.i32_const(at).op_u32(Opcode::I32AtomicLoad, 0); },
+        |c, at| { c.i32_const(at).i32_const(1).op_u32(Opcode::I32AtomicStore, 0); },
+        |c, at| { c.i32_const(at).i64_const(1).op_u32(Opcode::I64AtomicStore, 0); },
+        |c, at| { c.i32_const(at).i32_const(1).op_u32(Opcode::I32AtomicRmwAdd, 0); },
+        |c, at| { c.i32_const(at).i64_const(1).op_u32(Opcode::I64AtomicRmwXchg, 0); },
+        |c, at| { c.i32_const(at).i32_const(0).i32_const(1).op_u32(Opcode::I32AtomicRmwCmpxchg, 0); },
+        |c, at| { c.i32_const(at).i64_const(0).i64_const(1).op_u32(Opcode::I64AtomicRmwCmpxchg, 0); },
+        |c, at| { c.i32_const(at).i32_const(1).op_u32(Opcode::MemoryAtomicNotify, 0); },
+        |c, at| { c.i32_const(at).i32_const(0).i64_const(0).op_u32(Opcode::MemoryAtomicWait32, 0); }
+    ];
+    for op in &ops {
+        let mut c = Code::new();
+        op(&mut c, 2);
+        c.op(Opcode::Halt);
+        assert_eq!(run_atomic(&c), Err(ExecuteError::MisalignedAtomic));
+
+        // Bounds are checked before alignment.
+        let mut c = Code::new();
+        op(&mut c, 257);
+        c.op(Opcode::Halt);
+        assert_eq!(run_atomic(&c), Err(ExecuteError::Bounds));
+    }
+
+    // Aligned accesses succeed.
+    let mut c = Code::new();
+    c.i32_const(8).i32_const(5).op_u32(Opcode::I32AtomicRmwAdd, 0)
+        .i32_const(8).i64_const(7).op_u32(Opcode::I64AtomicRmwAdd, 0)
+        .i32_const(0).op_u32(Opcode::I64AtomicLoad, 8).op(Opcode::Halt);
+    assert_eq!(run_atomic(&c), Ok(vec![0, 5, 12]));
+}
+
+// Atomics see memory in the same little-endian byte order as plain loads and stores.
+#[test]
+fn atomics_are_little_endian() {
+    let mut c = Code::new();
+    c.i32_const(8).i32_const(0x01020304).op_u32(Opcode::I32Store, 0)
+        .i32_const(8).op_u32(Opcode::I32AtomicLoad, 0)
+        .i32_const(16).i32_const(0x0a0b0c0d).op_u32(Opcode::I32AtomicStore, 0)
+        .i32_const(16).op_u32(Opcode::I32Load8U, 0)
+        .i32_const(24).i64_const(0xff).op_u32(Opcode::I64Store, 0)
+        .i32_const(24).i64_const(1).op_u32(Opcode::I64AtomicRmwAdd, 0)
+        .i32_const(25).op_u32(Opcode::I32Load8U, 0)
+        .i32_const(24).i64_const(0x100).i64_const(0x0102).op_u32(Opcode::I64AtomicRmwCmpxchg, 0)
+        .i32_const(24).op_u32(Opcode::I32Load16U, 0)
+        .i32_const(24).i32_const(0xff00).op_u32(Opcode::I32AtomicRmwAnd, 0)
+        .i32_const(24).op_u32(Opcode::I32Load8U, 0)
+        .op(Opcode::Halt);
+    assert_eq!(run_atomic(&c), Ok(vec![0x01020304, 0x0d, 0xff, 1, 0x100, 0x0102, 0x0102, 0]));
+}
+
+fn extend_i32(op: Opcode, v: u32) -> ExecuteResult<Vec<i64>> {
+    let mut c = Code::new();
+    c.i32_const(v).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    ret.map(|_| v)
+}
+
+fn extend_i64(op: Opcode, v: u64) -> ExecuteResult<Vec<i64>> {
+    let mut c = Code::new();
+    c.i64_const(v).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    ret.map(|_| v)
+}
+
+#[test]
+fn sign_extension() {
+    // i32 results are kept sign-extended to 64 bits.
+    assert_eq!(extend_i32(Opcode::I32Extend8S, 0x80), Ok(vec![-128]));
+    assert_eq!(extend_i32(Opcode::I32Extend8S, 0x7f), Ok(vec![0x7f]));
+    assert_eq!(extend_i32(Opcode::I32Extend8S, 0x1234_56ff), Ok(vec![-1]));
+    assert_eq!(extend_i32(Opcode::I32Extend16S, 0x8000), Ok(vec![-32768]));
+    assert_eq!(extend_i32(Opcode::I32Extend16S, 0xffff_7fff), Ok(vec![0x7fff]));
+
+    assert_eq!(extend_i64(Opcode::I64Extend8S, 0x80), Ok(vec![-128]));
+    assert_eq!(extend_i64(Opcode::I64Extend8S, 0xffff_ffff_ffff_ff7f), Ok(vec![0x7f]));
+    assert_eq!(extend_i64(Opcode::I64Extend16S, 0x1_8000), Ok(vec![-32768]));
+    assert_eq!(extend_i64(Opcode::I64Extend16S, 0x7fff), Ok(vec![0x7fff]));
+    assert_eq!(extend_i64(Opcode::I64Extend32S, 0xffff_ffff), Ok(vec![-1]));
+    assert_eq!(extend_i64(Opcode::I64Extend32S, 0xffff_ffff_7fff_ffff), Ok(vec![0x7fff_ffff]));
+}
+
+fn binop_i32(op: Opcode, a: u32, b: u32) -> ExecuteResult<Vec<i64>> {
+    let mut c = Code::new();
+    c.i32_const(a).i32_const(b).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    ret.map(|_| v)
+}
+
+fn binop_i64(op: Opcode, a: u64, b: u64) -> ExecuteResult<Vec<i64>> {
+    let mut c = Code::new();
+    c.i64_const(a).i64_const(b).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    ret.map(|_| v)
+}
+
+#[test]
+fn checked_arithmetic() {
+    let overflow = Err(ExecuteError::IntegerOverflow);
+
+    assert_eq!(binop_i32(Opcode::I32AddCheckedS, 0x7fffffff, 1), overflow);
+    assert_eq!(binop_i32(Opcode::I32AddCheckedS, 0x7ffffffe, 1), Ok(vec![0x7fffffff]));
+    assert_eq!(binop_i32(Opcode::I32AddCheckedU, 0xffffffff, 1), overflow);
+    assert_eq!(binop_i32(Opcode::I32SubCheckedS, 0x80000000, 1), overflow);
+    assert_eq!(binop_i32(Opcode::I32SubCheckedU, 0, 1), overflow);
+    assert_eq!(binop_i32(Opcode::I32SubCheckedU, 1, 1), Ok(vec![0]));
+    assert_eq!(binop_i32(Opcode::I32MulCheckedS, 0x10000, 0x8000), overflow);
+    assert_eq!(binop_i32(Opcode::I32MulCheckedS, -0x10000i32 as u32, 0x8000), Ok(vec![-0x80000000]));
+    assert_eq!(binop_i32(Opcode::I32MulCheckedU, 0x10000, 0x10000), overflow);
+
+    assert_eq!(binop_i64(Opcode::I64AddCheckedS, i64::MAX as u64, 1), overflow);
+    assert_eq!(binop_i64(Opcode::I64AddCheckedU, u64::MAX, 1), overflow);
+    assert_eq!(binop_i64(Opcode::I64SubCheckedS, i64::MIN as u64, 1), overflow);
+    assert_eq!(binop_i64(Opcode::I64SubCheckedU, 0, 1), overflow);
+    assert_eq!(binop_i64(Opcode::I64MulCheckedS, -1i64 as u64, i64::MIN as u64), overflow);
+    assert_eq!(binop_i64(Opcode::I64MulCheckedU, 1 << 32, 1 << 32), overflow);
+    assert_eq!(binop_i64(Opcode::I64MulCheckedU, 1 << 31, 1 << 32), Ok(vec![1 << 63]));
+}
+
+#[test]
+fn overflow_flags() {
+    assert_eq!(binop_i32(Opcode::I32AddOverflowingS, 0x7fffffff, 1), Ok(vec![-0x80000000, 1]));
+    assert_eq!(binop_i32(Opcode::I32AddOverflowingS, 1, 1), Ok(vec![2, 0]));
+    assert_eq!(binop_i32(Opcode::I32AddOverflowingU, 0xffffffff, 2), Ok(vec![1, 1]));
+    assert_eq!(binop_i32(Opcode::I32SubOverflowingS, 0x80000000, 1), Ok(vec![0x7fffffff, 1]));
+    assert_eq!(binop_i32(Opcode::I32SubOverflowingU, 0, 1), Ok(vec![0xffffffff, 1]));
+    assert_eq!(binop_i32(Opcode::I32MulOverflowingS, 0x10000, 0x8000), Ok(vec![-0x80000000, 1]));
+    assert_eq!(binop_i32(Opcode::I32MulOverflowingU, 0x10000, 0xffff), Ok(vec![0xffff0000, 0]));
+
+    assert_eq!(binop_i64(Opcode::I64AddOverflowingS, i64::MAX as u64, 1), Ok(vec![i64::MIN, 1]));
+    assert_eq!(binop_i64(Opcode::I64AddOverflowingU, u64::MAX, 1), Ok(vec![0, 1]));
+    assert_eq!(binop_i64(Opcode::I64SubOverflowingS, 5, 7), Ok(vec![-2, 0]));
+    assert_eq!(binop_i64(Opcode::I64SubOverflowingU, 5, 7), Ok(vec![-2, 1]));
+    assert_eq!(binop_i64(Opcode::I64MulOverflowingS, 1 << 62, 2), Ok(vec![i64::MIN, 1]));
+    assert_eq!(binop_i64(Opcode::I64MulOverflowingU, 1 << 62, 2), Ok(vec![i64::MIN, 0]));
+}
+
+#[test]
+fn wide_multiply() {
+    // Low half first, then the high half.
+    assert_eq!(binop_i64(Opcode::I64MulWideU, u64::MAX, u64::MAX), Ok(vec![1, -2]));
+    assert_eq!(binop_i64(Opcode::I64MulWideU, 1 << 63, 4), Ok(vec![0, 2]));
+    assert_eq!(binop_i64(Opcode::I64MulWideS, -1i64 as u64, -1i64 as u64), Ok(vec![1, 0]));
+    assert_eq!(binop_i64(Opcode::I64MulWideS, -1i64 as u64, 1), Ok(vec![-1, -1]));
+    assert_eq!(binop_i64(Opcode::I64MulWideS, i64::MIN as u64, 2), Ok(vec![0, -1]));
+    assert_eq!(binop_i64(Opcode::I64MulWideS, i64::MIN as u64, i64::MIN as u64), Ok(vec![0, 1 << 62]));
+}
+
+fn carry_op(op: Opcode, a: u64, b: u64, carry: u64) -> ExecuteResult<Vec<i64>> {
+    let mut c = Code::new();
+    c.i64_const(a).i64_const(b).i64_const(carry).op(op).op(Opcode::Halt);
+    let (ret, v) = run_both(&c);
+    ret.map(|_| v)
+}
+
+#[test]
+fn carry_arithmetic() {
+    assert_eq!(carry_op(Opcode::I64AddWithCarry, 1, 2, 0), Ok(vec![3, 0]));
+    assert_eq!(carry_op(Opcode::I64AddWithCarry, u64::MAX, 0, 1), Ok(vec![0, 1]));
+    assert_eq!(carry_op(Opcode::I64AddWithCarry, u64::MAX, u64::MAX, 1), Ok(vec![-1, 1]));
+    // Any non-zero carry counts as 1.
+    assert_eq!(carry_op(Opcode::I64AddWithCarry, 1, 2, 7), Ok(vec![4, 0]));
+
+    assert_eq!(carry_op(Opcode::I64SubWithBorrow, 3, 2, 0), Ok(vec![1, 0]));
+    assert_eq!(carry_op(Opcode::I64SubWithBorrow, 0, 0, 1), Ok(vec![-1, 1]));
+    assert_eq!(carry_op(Opcode::I64SubWithBorrow, 0, u64::MAX, 1), Ok(vec![0, 1]));
+    assert_eq!(carry_op(Opcode::I64SubWithBorrow, 5, 2, -1i64 as u64), Ok(vec![2, 0]));
+}
+
+fn run_hooked(code: &Code, hook: OpcodeHook, verified: bool) -> (ExecuteResult<()>, Vec<i64>) {
+    let mut env = TestEnv::default();
+    env.opcode_hook = Some(hook);
+    let (ret, env) = run_in(env, &module(&code.0), verified);
+    (ret, env.stack_values())
+}
+
+// Hooks see the cached top of the stack, and the VM sees what they leave there.
+#[test]
+fn hooks_change_the_stack() {
+    let mut c = Code::new();
+    c.i32_const(5).i32_const(6).op(Opcode::I32Add).op(Opcode::Halt);
+
+    fn overwrite(env: &TestEnv, op: &Opcode) {
+        if let Opcode::I32Add = *op {
+            let stack = env.get_stack();
+            assert_eq!(stack.tail_many(1).unwrap()[0].get(), 6);
+            stack.tail_many(1).unwrap()[0].set(100);
+        }
+    }
+    fn push(env: &TestEnv, op: &Opcode) {
+        if let Opcode::I32Add = *op {
+            env.push(&[1]);
+        }
+    }
+    fn pop(env: &TestEnv, op: &Opcode) {
+        if let Opcode::I32Add = *op {
+            env.get_stack().prev().unwrap();
+        }
+    }
+
+    for &verified in &[false, true] {
+        assert_eq!(run_hooked(&c, overwrite, verified), (Ok(()), vec![105]));
+    }
+    assert_eq!(run_hooked(&c, push, false), (Ok(()), vec![5, 7]));
+    assert_eq!(run_hooked(&c, pop, false).0, Err(ExecuteError::Bounds));
+
+    let mut c = Code::new();
+    c.i32_const(4).i32_const(5).i32_const(6).op(Opcode::I32Add).op(Opcode::Halt);
+    assert_eq!(run_hooked(&c, pop, false), (Ok(()), vec![9]));
+
+    // Verified code relies on the heights the verifier computed.
+    assert_eq!(run_hooked(&c, push, true).0, Err(ExecuteError::Bounds));
+}
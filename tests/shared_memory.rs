@@ -0,0 +1,153 @@
+// Tests for shared linear memory and `StdEnvironment`s sharing it across threads.
+
+#![cfg(all(feature = "std", feature = "shared-memory"))]
+
+extern crate hexagon_e;
+
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::module::Opcode;
+use hexagon_e::shared_memory::{AtomicRmwOp, SharedMemoryBuffer};
+use hexagon_e::std_environment::StdEnvironment;
+
+use common::*;
+
+fn run_shared(memory: &Arc<SharedMemoryBuffer>, code: &Code) -> (ExecuteResult<()>, Vec<i64>) {
+    let env = StdEnvironment::builder()
+        .shared_memory(memory.clone())
+        .build()
+        .unwrap();
+    let (ret, env) = run_in(env, &module(&code.0), false);
+
+    let stack = env.get_stack();
+    (ret, (0..stack.get_pos()).map(|i| stack.at(i).unwrap().get()).collect())
+}
+
+#[test]
+fn wait_and_notify_across_threads() {
+    let memory = Arc::new(SharedMemoryBuffer::new(64, 64).unwrap());
+
+    // Waits on address 0 while it holds 0, then reads address 8.
+    let mut waiter = Code::new();
+    waiter.i32_const(0).i32_const(0).i64_const(-1i64 as u64).op_u32(Opcode::MemoryAtomicWait32, 0);
+    waiter.i32_const(8).op_u32(Opcode::I32Load, 0);
+    waiter.op(Opcode::Halt);
+
+    // Stores to address 8 and notifies address 0 until a waiter has been woken.
+    let mut notifier = Code::new();
+    notifier.i32_const(8).i32_const(42).op_u32(Opcode::I32Store, 0);
+    let retry = notifier.pos();
+    notifier.i32_const(0).i32_const(1).op_u32(Opcode::MemoryAtomicNotify, 0);
+    notifier.i32_const(0).op(Opcode::I32Eq).op_u32(Opcode::JmpIf, retry);
+    notifier.op(Opcode::Halt);
+
+    let waiter_thread = {
+        let memory = memory.clone();
+        thread::spawn(move || run_shared(&memory, &waiter))
+    };
+    let notifier_thread = {
+        let memory = memory.clone();
+        thread::spawn(move || run_shared(&memory, &notifier))
+    };
+
+    assert_eq!(waiter_thread.join().unwrap(), (Ok(()), vec![0, 42]));
+    assert_eq!(notifier_thread.join().unwrap(), (Ok(()), vec![]));
+}
+
+#[test]
+fn wait_without_notify() {
+    let memory = Arc::new(SharedMemoryBuffer::new(64, 64).unwrap());
+
+    let mut code = Code::new();
+    code.i32_const(16).i64_const(5).op_u32(Opcode::I64AtomicStore, 0);
+    // Not equal
+    code.i32_const(16).i64_const(4).i64_const(-1i64 as u64).op_u32(Opcode::MemoryAtomicWait64, 0);
+    // Times out after 1ms
+    code.i32_const(16).i64_const(5).i64_const(1000000).op_u32(Opcode::MemoryAtomicWait64, 0);
+    // Nobody is waiting.
+    code.i32_const(16).i32_const(1).op_u32(Opcode::MemoryAtomicNotify, 0);
+    code.op(Opcode::Halt);
+
+    assert_eq!(run_shared(&memory, &code), (Ok(()), vec![1, 2, 0]));
+    assert_eq!(memory.memory().read_u64(16), Ok(5));
+}
+
+#[test]
+fn memory_is_shared() {
+    let memory = Arc::new(SharedMemoryBuffer::new(16, 32).unwrap());
+
+    let mut code = Code::new();
+    code.i32_const(4).i32_const(0x1234).op_u32(Opcode::I32Store16, 0);
+    code.i32_const(16).op(Opcode::GrowMemory);
+    code.op(Opcode::CurrentMemory);
+    code.op(Opcode::Halt);
+    assert_eq!(run_shared(&memory, &code), (Ok(()), vec![16, 32]));
+
+    let mut code = Code::new();
+    code.i32_const(4).op_u32(Opcode::I32AtomicLoad, 0);
+    code.op(Opcode::CurrentMemory);
+    code.op(Opcode::Halt);
+    assert_eq!(run_shared(&memory, &code), (Ok(()), vec![0x1234, 32]));
+
+    // Beyond the capacity
+    let mut code = Code::new();
+    code.i32_const(1).op(Opcode::GrowMemory).op(Opcode::Halt);
+    assert_eq!(run_shared(&memory, &code).0, Err(ExecuteError::MemoryLimit));
+}
+
+#[test]
+fn accesses_of_mixed_widths() {
+    let buffer = SharedMemoryBuffer::new(32, 32).unwrap();
+    let mem = buffer.memory();
+
+    // Across the first two cells
+    mem.write_u64(4, 0x0807060504030201).unwrap();
+    assert_eq!(mem.atomic_load_u64(0).unwrap(), 0x04030201_00000000);
+    assert_eq!(mem.atomic_load_u32(8).unwrap(), 0x08070605);
+    assert_eq!(mem.read_u16(7).unwrap(), 0x0504);
+
+    assert_eq!(mem.atomic_rmw_u32(4, AtomicRmwOp::Add, 1).unwrap(), 0x04030201);
+    assert_eq!(mem.read_u32(4).unwrap(), 0x04030202);
+    assert_eq!(mem.atomic_cmpxchg_u32(12, 1, 2).unwrap(), 0);
+    assert_eq!(mem.atomic_cmpxchg_u32(12, 0, 0xaabbccdd).unwrap(), 0);
+    assert_eq!(mem.atomic_load_u64(8).unwrap(), 0xaabbccdd_08070605);
+    assert_eq!(mem.atomic_load_u32(0).unwrap(), 0);
+
+    // Byte ranges against a plain copy
+    let mut expected: Vec<u8> = (1..33).collect();
+    mem.write_bytes(0, &expected).unwrap();
+    mem.copy_range(1, 4, 19).unwrap();
+    expected.copy_within(1..20, 4);
+    mem.copy_range(9, 2, 21).unwrap();
+    expected.copy_within(9..30, 2);
+    mem.fill_range(3, 0xee, 10).unwrap();
+    for b in &mut expected[3..13] {
+        *b = 0xee;
+    }
+    let actual: Vec<u8> = (0..32).map(|i| mem.read_u8(i).unwrap()).collect();
+    assert_eq!(actual, expected);
+}
+
+#[test]
+fn atomics_on_halves_of_a_cell() {
+    let buffer = Arc::new(SharedMemoryBuffer::new(8, 8).unwrap());
+
+    let threads: Vec<_> = [0, 4].iter().map(|&addr| {
+        let buffer = buffer.clone();
+        thread::spawn(move || {
+            for _ in 0..10000 {
+                buffer.memory().atomic_rmw_u32(addr, AtomicRmwOp::Add, 1).unwrap();
+            }
+        })
+    }).collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(buffer.memory().atomic_load_u64(0).unwrap(), 10000 | (10000 << 32));
+}
@@ -0,0 +1,301 @@
+// Behavior tests for the SIMD opcodes. Programs run both unverified and verified, and
+// expected vectors are spelled out lane by lane.
+
+#![cfg(feature = "simd")]
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::error::*;
+use hexagon_e::module::Opcode;
+use hexagon_e::simd::SimdOpcode;
+
+use common::*;
+
+// Packs `lanes`, lane 0 first, into a v128.
+fn pack<T: Copy + Into<u128>>(lanes: &[T]) -> u128 {
+    let bits = 128 / lanes.len();
+    lanes.iter().enumerate().fold(0, |v, (i, &x)| v | (x.into() << (i * bits)))
+}
+
+// The two operand stack cells of `v`, low half first.
+fn cells(v: u128) -> Vec<i64> {
+    vec![v as u64 as i64, (v >> 64) as u64 as i64]
+}
+
+fn run_code(c: &mut Code) -> (ExecuteResult<()>, Vec<i64>) {
+    c.op(Opcode::Halt);
+    run_both(c)
+}
+
+fn unop(op: SimdOpcode, a: u128) -> Vec<i64> {
+    let mut c = Code::new();
+    c.v128_const(a).simd(op);
+    let (ret, values) = run_code(&mut c);
+    assert_eq!(ret, Ok(()));
+    values
+}
+
+fn binop(op: SimdOpcode, a: u128, b: u128) -> Vec<i64> {
+    let mut c = Code::new();
+    c.v128_const(a).v128_const(b).simd(op);
+    let (ret, values) = run_code(&mut c);
+    assert_eq!(ret, Ok(()));
+    values
+}
+
+fn shift(op: SimdOpcode, a: u128, n: u32) -> Vec<i64> {
+    let mut c = Code::new();
+    c.v128_const(a).i32_const(n).simd(op);
+    let (ret, values) = run_code(&mut c);
+    assert_eq!(ret, Ok(()));
+    values
+}
+
+fn extract(op: SimdOpcode, a: u128, lane: u8) -> (ExecuteResult<()>, Vec<i64>) {
+    let mut c = Code::new();
+    c.v128_const(a).simd(op).u8(lane);
+    run_code(&mut c)
+}
+
+fn replace(op: SimdOpcode, a: u128, lane: u8, x: u64) -> (ExecuteResult<()>, Vec<i64>) {
+    let mut c = Code::new();
+    c.v128_const(a).i64_const(x).simd(op).u8(lane);
+    run_code(&mut c)
+}
+
+fn splat(op: SimdOpcode, x: u64) -> Vec<i64> {
+    let mut c = Code::new();
+    c.i64_const(x).simd(op);
+    let (ret, values) = run_code(&mut c);
+    assert_eq!(ret, Ok(()));
+    values
+}
+
+// Bytes 0, 1, .., 15.
+fn iota() -> u128 {
+    pack(&[0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15])
+}
+
+#[test]
+fn two_cell_layout() {
+    let v = 0x1716151413121110_0706050403020100;
+    assert_eq!(run_code(Code::new().v128_const(v)), (Ok(()), vec![0x0706050403020100, 0x1716151413121110]));
+
+    // The high half is on top.
+    assert_eq!(run_code(Code::new().v128_const(v).op(Opcode::Drop)), (Ok(()), vec![0x0706050403020100]));
+
+    assert_eq!(splat(SimdOpcode::I8x16Splat, 0x1ab), vec![0xabababababababab_u64 as i64; 2]);
+    assert_eq!(splat(SimdOpcode::I16x8Splat, 0x1abcd), vec![0xabcdabcdabcdabcd_u64 as i64; 2]);
+    assert_eq!(splat(SimdOpcode::I32x4Splat, 0x1_deadbeef), vec![0xdeadbeefdeadbeef_u64 as i64; 2]);
+    assert_eq!(splat(SimdOpcode::I64x2Splat, 0x0123456789abcdef), vec![0x0123456789abcdef; 2]);
+}
+
+#[test]
+fn extract_lanes() {
+    let v = pack(&[0u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0xff, 0x80]);
+
+    assert_eq!(extract(SimdOpcode::I8x16ExtractLaneS, v, 1), (Ok(()), vec![1]));
+    assert_eq!(extract(SimdOpcode::I8x16ExtractLaneS, v, 15), (Ok(()), vec![-128]));
+    assert_eq!(extract(SimdOpcode::I8x16ExtractLaneU, v, 15), (Ok(()), vec![0x80]));
+    assert_eq!(extract(SimdOpcode::I16x8ExtractLaneS, v, 7), (Ok(()), vec![0x80ff - 0x10000]));
+    assert_eq!(extract(SimdOpcode::I16x8ExtractLaneU, v, 7), (Ok(()), vec![0x80ff]));
+    assert_eq!(extract(SimdOpcode::I16x8ExtractLaneU, v, 0), (Ok(()), vec![0x0100]));
+    assert_eq!(extract(SimdOpcode::I32x4ExtractLane, v, 3), (Ok(()), vec![0x80ff0d0c]));
+    assert_eq!(extract(SimdOpcode::I32x4ExtractLane, v, 1), (Ok(()), vec![0x07060504]));
+    assert_eq!(extract(SimdOpcode::I64x2ExtractLane, v, 0), (Ok(()), vec![0x0706050403020100]));
+    assert_eq!(extract(SimdOpcode::I64x2ExtractLane, v, 1), (Ok(()), vec![0x80ff0d0c0b0a0908_u64 as i64]));
+
+    for &(op, lanes) in &[
+        (SimdOpcode::I8x16ExtractLaneS, 16), (SimdOpcode::I8x16ExtractLaneU, 16),
+        (SimdOpcode::I16x8ExtractLaneS, 8), (SimdOpcode::I16x8ExtractLaneU, 8),
+        (SimdOpcode::I32x4ExtractLane, 4), (SimdOpcode::I64x2ExtractLane, 2)
+    ] {
+        assert_eq!(extract(op, v, lanes).0, Err(ExecuteError::Bounds));
+    }
+}
+
+#[test]
+fn replace_lanes() {
+    let v = iota();
+
+    assert_eq!(replace(SimdOpcode::I8x16ReplaceLane, v, 3, 0x1ff),
+        (Ok(()), cells(pack(&[0u8, 1, 2, 0xff, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]))));
+    assert_eq!(replace(SimdOpcode::I16x8ReplaceLane, v, 7, 0xabcd),
+        (Ok(()), cells(pack(&[0x0100u16, 0x0302, 0x0504, 0x0706, 0x0908, 0x0b0a, 0x0d0c, 0xabcd]))));
+    assert_eq!(replace(SimdOpcode::I32x4ReplaceLane, v, 0, 0xffffffff_12345678),
+        (Ok(()), cells(pack(&[0x12345678u32, 0x07060504, 0x0b0a0908, 0x0f0e0d0c]))));
+    assert_eq!(replace(SimdOpcode::I64x2ReplaceLane, v, 1, u64::MAX),
+        (Ok(()), cells(pack(&[0x0706050403020100u64, u64::MAX]))));
+
+    assert_eq!(replace(SimdOpcode::I8x16ReplaceLane, v, 16, 0).0, Err(ExecuteError::Bounds));
+    assert_eq!(replace(SimdOpcode::I16x8ReplaceLane, v, 8, 0).0, Err(ExecuteError::Bounds));
+    assert_eq!(replace(SimdOpcode::I32x4ReplaceLane, v, 4, 0).0, Err(ExecuteError::Bounds));
+    assert_eq!(replace(SimdOpcode::I64x2ReplaceLane, v, 2, 0).0, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn shuffle() {
+    // Byte i of `a` is i and byte i of `b` is 16 + i, so each lane shows its selector.
+    let a = iota();
+    let b = a + pack(&[16u8; 16]);
+    let selectors = [31u8, 0, 17, 2, 15, 16, 7, 7, 24, 8, 30, 1, 0, 31, 19, 12];
+
+    let mut c = Code::new();
+    c.v128_const(a).v128_const(b).simd(SimdOpcode::I8x16Shuffle);
+    for &s in &selectors {
+        c.u8(s);
+    }
+    assert_eq!(run_code(&mut c), (Ok(()), cells(pack(&selectors))));
+
+    let mut c = Code::new();
+    c.v128_const(a).v128_const(b).simd(SimdOpcode::I8x16Shuffle);
+    for i in 0..16 {
+        c.u8(if i == 5 { 32 } else { 0 });
+    }
+    assert_eq!(run_code(&mut c).0, Err(ExecuteError::Bounds));
+}
+
+#[test]
+fn swizzle() {
+    let a = pack(&[
+        0xa0u8, 0xa1, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+        0xa8, 0xa9, 0xaa, 0xab, 0xac, 0xad, 0xae, 0xaf
+    ]);
+    // Selectors from 16 on give zero lanes.
+    let s = pack(&[15u8, 0, 16, 255, 1, 1, 128, 14, 3, 31, 2, 9, 17, 8, 0, 15]);
+
+    assert_eq!(binop(SimdOpcode::I8x16Swizzle, a, s), cells(pack(&[
+        0xafu8, 0xa0, 0, 0, 0xa1, 0xa1, 0, 0xae,
+        0xa3, 0, 0xa2, 0xa9, 0, 0xa8, 0xa0, 0xaf
+    ])));
+}
+
+#[test]
+fn shifts() {
+    // Shift counts are taken modulo the lane width.
+    let v = pack(&[0x81u8, 0x01, 0x7f, 0xff, 0x81, 0x01, 0x7f, 0xff, 0x81, 0x01, 0x7f, 0xff, 0x81, 0x01, 0x7f, 0xff]);
+    let shl = cells(pack(&[0x02u8, 0x02, 0xfe, 0xfe, 0x02, 0x02, 0xfe, 0xfe, 0x02, 0x02, 0xfe, 0xfe, 0x02, 0x02, 0xfe, 0xfe]));
+    assert_eq!(shift(SimdOpcode::I8x16Shl, v, 1), shl);
+    assert_eq!(shift(SimdOpcode::I8x16Shl, v, 9), shl);
+    assert_eq!(shift(SimdOpcode::I8x16ShrS, v, 1),
+        cells(pack(&[0xc0u8, 0x00, 0x3f, 0xff, 0xc0, 0x00, 0x3f, 0xff, 0xc0, 0x00, 0x3f, 0xff, 0xc0, 0x00, 0x3f, 0xff])));
+    assert_eq!(shift(SimdOpcode::I8x16ShrU, v, 1),
+        cells(pack(&[0x40u8, 0x00, 0x3f, 0x7f, 0x40, 0x00, 0x3f, 0x7f, 0x40, 0x00, 0x3f, 0x7f, 0x40, 0x00, 0x3f, 0x7f])));
+
+    let v = pack(&[0x8001u16, 0x7fff, 0x8001, 0x7fff, 0x8001, 0x7fff, 0x8001, 0x7fff]);
+    let shl = cells(pack(&[0x0010u16, 0xfff0, 0x0010, 0xfff0, 0x0010, 0xfff0, 0x0010, 0xfff0]));
+    assert_eq!(shift(SimdOpcode::I16x8Shl, v, 4), shl);
+    assert_eq!(shift(SimdOpcode::I16x8Shl, v, 20), shl);
+    assert_eq!(shift(SimdOpcode::I16x8ShrS, v, 4),
+        cells(pack(&[0xf800u16, 0x07ff, 0xf800, 0x07ff, 0xf800, 0x07ff, 0xf800, 0x07ff])));
+    assert_eq!(shift(SimdOpcode::I16x8ShrU, v, 4),
+        cells(pack(&[0x0800u16, 0x07ff, 0x0800, 0x07ff, 0x0800, 0x07ff, 0x0800, 0x07ff])));
+
+    let v = pack(&[0x80000001u32, 0x10, 0x80000001, 0x10]);
+    assert_eq!(shift(SimdOpcode::I32x4Shl, v, 31), cells(pack(&[0x80000000u32, 0, 0x80000000, 0])));
+    assert_eq!(shift(SimdOpcode::I32x4Shl, v, 32), cells(v));
+    assert_eq!(shift(SimdOpcode::I32x4ShrS, v, 31), cells(pack(&[0xffffffffu32, 0, 0xffffffff, 0])));
+    assert_eq!(shift(SimdOpcode::I32x4ShrU, v, 31), cells(pack(&[1u32, 0, 1, 0])));
+
+    let v = pack(&[0x8000000000000001u64, 2]);
+    assert_eq!(shift(SimdOpcode::I64x2Shl, v, 1), cells(pack(&[2u64, 4])));
+    assert_eq!(shift(SimdOpcode::I64x2ShrS, v, 63), cells(pack(&[u64::MAX, 0])));
+    assert_eq!(shift(SimdOpcode::I64x2ShrU, v, 64), cells(v));
+}
+
+#[test]
+fn lane_arithmetic() {
+    let a = pack(&[0xffu8, 0x7f, 0x80, 0x01, 0xff, 0x7f, 0x80, 0x01, 0xff, 0x7f, 0x80, 0x01, 0xff, 0x7f, 0x80, 0x01]);
+    let b = pack(&[0x01u8, 0x01, 0x80, 0x02, 0x01, 0x01, 0x80, 0x02, 0x01, 0x01, 0x80, 0x02, 0x01, 0x01, 0x80, 0x02]);
+    assert_eq!(binop(SimdOpcode::I8x16Add, a, b),
+        cells(pack(&[0x00u8, 0x80, 0x00, 0x03, 0x00, 0x80, 0x00, 0x03, 0x00, 0x80, 0x00, 0x03, 0x00, 0x80, 0x00, 0x03])));
+    assert_eq!(binop(SimdOpcode::I8x16Sub, a, b),
+        cells(pack(&[0xfeu8, 0x7e, 0x00, 0xff, 0xfe, 0x7e, 0x00, 0xff, 0xfe, 0x7e, 0x00, 0xff, 0xfe, 0x7e, 0x00, 0xff])));
+    assert_eq!(binop(SimdOpcode::I8x16MinS, a, b),
+        cells(pack(&[0xffu8, 0x01, 0x80, 0x01, 0xff, 0x01, 0x80, 0x01, 0xff, 0x01, 0x80, 0x01, 0xff, 0x01, 0x80, 0x01])));
+    assert_eq!(binop(SimdOpcode::I8x16MinU, a, b),
+        cells(pack(&[0x01u8, 0x01, 0x80, 0x01, 0x01, 0x01, 0x80, 0x01, 0x01, 0x01, 0x80, 0x01, 0x01, 0x01, 0x80, 0x01])));
+
+    let a = pack(&[0x0100u16, 0xffff, 0x0100, 0xffff, 0x0100, 0xffff, 0x0100, 0xffff]);
+    let b = pack(&[0x0100u16, 0x0002, 0x0100, 0x0002, 0x0100, 0x0002, 0x0100, 0x0002]);
+    assert_eq!(binop(SimdOpcode::I16x8Mul, a, b),
+        cells(pack(&[0x0000u16, 0xfffe, 0x0000, 0xfffe, 0x0000, 0xfffe, 0x0000, 0xfffe])));
+
+    let a = pack(&[0xffffffffu32, 1, 5, 0x80000000]);
+    let b = pack(&[0u32, 2, 5, 0x7fffffff]);
+    assert_eq!(binop(SimdOpcode::I32x4LtS, a, b), cells(pack(&[0xffffffffu32, 0xffffffff, 0, 0xffffffff])));
+    assert_eq!(binop(SimdOpcode::I32x4LtU, a, b), cells(pack(&[0u32, 0xffffffff, 0, 0])));
+    assert_eq!(binop(SimdOpcode::I32x4MaxS, a, b), cells(pack(&[0u32, 2, 5, 0x7fffffff])));
+
+    assert_eq!(unop(SimdOpcode::I64x2Neg, pack(&[1u64, 1 << 63])), cells(pack(&[u64::MAX, 1 << 63])));
+    assert_eq!(binop(SimdOpcode::V128AndNot, pack(&[0xff00ff00u32; 4]), pack(&[0x0ff00ff0u32; 4])),
+        cells(pack(&[0xf000f000u32; 4])));
+}
+
+#[test]
+fn lane_tests() {
+    assert_eq!(unop(SimdOpcode::V128AnyTrue, 0), vec![0]);
+    assert_eq!(unop(SimdOpcode::V128AnyTrue, 1 << 127), vec![1]);
+    assert_eq!(unop(SimdOpcode::I8x16AllTrue, pack(&[1u8; 16])), vec![1]);
+    assert_eq!(unop(SimdOpcode::I8x16AllTrue, pack(&[1u8, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0])), vec![0]);
+    assert_eq!(unop(SimdOpcode::I16x8AllTrue, pack(&[0x0100u16; 8])), vec![1]);
+    assert_eq!(unop(SimdOpcode::I32x4AllTrue, pack(&[1u32, 2, 3, 0])), vec![0]);
+    assert_eq!(unop(SimdOpcode::I32x4AllTrue, pack(&[1u32, 2, 3, 4])), vec![1]);
+    assert_eq!(unop(SimdOpcode::I64x2AllTrue, pack(&[1u64 << 63, 0])), vec![0]);
+}
+
+// Runs `code` both unverified and verified in 256 bytes of memory, which must give the
+// same results.
+fn run_memory(code: &Code) -> (ExecuteResult<()>, Vec<i64>, Vec<u8>) {
+    let (ret, env) = run_in(TestEnv::default(), &module(&code.0), false);
+    let checked = (ret, env.stack_values(), env.mem);
+    let (ret, env) = run_in(TestEnv::default(), &module(&code.0), true);
+    assert_eq!((ret, env.stack_values(), env.mem), checked);
+    checked
+}
+
+#[test]
+fn load_and_store() {
+    let v = 0x1f1e1d1c1b1a1918_1716151413121110;
+
+    let mut c = Code::new();
+    c.i32_const(16).v128_const(v).simd(SimdOpcode::V128Store).u32(4);
+    c.i32_const(20).simd(SimdOpcode::V128Load).u32(0);
+    c.op(Opcode::Halt);
+
+    let (ret, values, memory) = run_memory(&c);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(values, cells(v));
+    assert!(memory[..20].iter().all(|&b| b == 0));
+    assert_eq!(&memory[20..36], &[
+        0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+        0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f
+    ]);
+    assert!(memory[36..].iter().all(|&b| b == 0));
+
+    // The last 16 bytes
+    let mut c = Code::new();
+    c.i32_const(240).v128_const(v).simd(SimdOpcode::V128Store).u32(0);
+    c.i32_const(232).simd(SimdOpcode::V128Load).u32(8);
+    c.op(Opcode::Halt);
+    assert_eq!(run_memory(&c).1, cells(v));
+}
+
+#[test]
+fn load_and_store_out_of_bounds() {
+    // Past the end by one byte, through the address or the offset, and wrapping around.
+    for &(addr, offset) in &[(241, 0), (0, 241), (256, 0), (0xffffffff, 1), (1, 0xffffffff)] {
+        let mut c = Code::new();
+        c.i32_const(addr).simd(SimdOpcode::V128Load).u32(offset).op(Opcode::Halt);
+        assert_eq!(run_memory(&c).0, Err(ExecuteError::Bounds));
+
+        // Neither half is written.
+        let mut c = Code::new();
+        c.i32_const(addr).v128_const(!0).simd(SimdOpcode::V128Store).u32(offset).op(Opcode::Halt);
+        let (ret, _, memory) = run_memory(&c);
+        assert_eq!(ret, Err(ExecuteError::Bounds));
+        assert!(memory.iter().all(|&b| b == 0));
+    }
+}
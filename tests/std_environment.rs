@@ -0,0 +1,79 @@
+// Tests for the limits of `StdEnvironment` and of VMs created over it.
+
+#![cfg(feature = "std")]
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::module::Opcode;
+use hexagon_e::std_environment::StdEnvironment;
+use hexagon_e::vm::{Limits, VirtualMachine};
+
+use common::*;
+
+fn limited() -> StdEnvironment {
+    let limits = Limits { max_memory: 100, max_slots: 8, max_call_depth: 4, max_locals: 2 };
+    StdEnvironment::builder()
+        .memory_size(64)
+        .slots(4)
+        .limits(limits)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn initial_sizes_within_limits() {
+    let limits = Limits { max_memory: 63, ..Limits::default() };
+    let ret = StdEnvironment::builder().memory_size(64).limits(limits).build();
+    assert_eq!(ret.err(), Some(ExecuteError::MemoryLimit));
+
+    let limits = Limits { max_slots: 3, ..Limits::default() };
+    let ret = StdEnvironment::builder().slots(4).limits(limits).build();
+    assert_eq!(ret.err(), Some(ExecuteError::SlotLimit));
+}
+
+#[test]
+fn vm_enforces_memory_and_slots() {
+    let run_code = |c: &mut Code| {
+        c.op(Opcode::Halt);
+        run_in(limited(), &module(&c.0), false)
+    };
+
+    let (ret, env) = run_code(Code::new().i32_const(37).op(Opcode::GrowMemory));
+    assert_eq!(ret, Err(ExecuteError::MemoryLimit));
+    assert_eq!(env.memory.len(), 64);
+    let (ret, env) = run_code(Code::new().i32_const(36).op(Opcode::GrowMemory));
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.memory.len(), 100);
+
+    let (ret, env) = run_code(Code::new().op_u32(Opcode::ResetSlots, 9));
+    assert_eq!(ret, Err(ExecuteError::SlotLimit));
+    assert_eq!(env.slots.len(), 4);
+    let (ret, env) = run_code(Code::new().op_u32(Opcode::ResetSlots, 8));
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.slots.len(), 8);
+}
+
+#[test]
+fn vm_starts_with_the_limits() {
+    // f calls itself forever.
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Halt);
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Return);
+    let m = module(&c.0);
+
+    let mut vm = VirtualMachine::new(&m, limited());
+    assert_eq!(vm.limits.max_call_depth, 4);
+    assert_eq!(vm.run(), Err(ExecuteError::ExecutionLimit));
+    assert_eq!(vm.env.get_call_stack().get_pos(), 4 * 2);
+
+    // Verified too, with more locals than allowed.
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 14, 0, 3).op(Opcode::Halt);
+    c.op(Opcode::Return);
+    let (ret, _) = run_in(limited(), &module(&c.0), true);
+    assert_eq!(ret, Err(ExecuteError::ExecutionLimit));
+}
@@ -0,0 +1,219 @@
+// Conformance tests for the standard syscalls, covering what `syscalls.md` leaves
+// implementation-defined.
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::module::Opcode;
+use hexagon_e::syscall::{self, LogLevel, RandomSource, SeededRandom, VirtualClock};
+use hexagon_e::syscall::{SYSCALL_ABORT, SYSCALL_CLOCK_MONOTONIC, SYSCALL_CLOCK_WALL, SYSCALL_EXIT, SYSCALL_LOG, SYSCALL_RANDOM};
+use hexagon_e::vm::VirtualMachine;
+
+use common::*;
+
+fn new_env() -> TestEnv {
+    let mut env = TestEnv::new(0, 16, 16);
+    env.mem = b"hello\xffworld".to_vec();
+    env.native_handler = Some(syscall::dispatch);
+    env
+}
+
+fn with_env<F: FnOnce(&mut TestEnv)>(args: &[i64], f: F) {
+    let mut env = new_env();
+    env.push(args);
+    f(&mut env);
+}
+
+fn log(args: &[i64]) -> (ExecuteResult<Option<i64>>, Vec<(LogLevel, String)>) {
+    let mut ret = None;
+    with_env(args, |env| {
+        let r = syscall::dispatch(env, SYSCALL_LOG);
+        ret = Some((r, env.logs.borrow().clone()));
+    });
+    ret.unwrap()
+}
+
+#[test]
+fn log_levels() {
+    assert_eq!(log(&[1, 0, 5]), (Ok(None), vec![(LogLevel::Error, "hello".to_string())]));
+    assert_eq!(log(&[3, 6, 5]), (Ok(None), vec![(LogLevel::Warning, "world".to_string())]));
+    assert_eq!(log(&[6, 0, 5]), (Ok(None), vec![(LogLevel::Info, "hello".to_string())]));
+}
+
+#[test]
+fn log_unknown_level() {
+    for &level in &[0, 2, 4, 5, 7, -1] {
+        assert_eq!(log(&[level, 0, 5]), (Err(ExecuteError::InvalidInput), vec![]));
+    }
+}
+
+#[test]
+fn log_invalid_utf8() {
+    assert_eq!(log(&[6, 0, 6]), (Err(ExecuteError::InvalidInput), vec![]));
+    assert_eq!(log(&[6, 5, 1]), (Err(ExecuteError::InvalidInput), vec![]));
+}
+
+#[test]
+fn log_out_of_bounds() {
+    // Past the end of memory
+    assert_eq!(log(&[6, 6, 6]), (Err(ExecuteError::Bounds), vec![]));
+    assert_eq!(log(&[6, 12, 0]), (Err(ExecuteError::Bounds), vec![]));
+    // Wrapping around
+    assert_eq!(log(&[6, 0xffffffff, 2]), (Err(ExecuteError::Bounds), vec![]));
+    // Negative values are taken as u32
+    assert_eq!(log(&[6, 0, -1]), (Err(ExecuteError::Bounds), vec![]));
+    assert_eq!(log(&[6, -1, 1]), (Err(ExecuteError::Bounds), vec![]));
+}
+
+#[test]
+fn log_empty_text() {
+    assert_eq!(log(&[6, 11, 0]), (Ok(None), vec![(LogLevel::Info, "".to_string())]));
+}
+
+#[test]
+fn log_missing_arguments() {
+    assert_eq!(log(&[6, 0]), (Err(ExecuteError::Bounds), vec![]));
+}
+
+#[test]
+fn reserved_and_user_ids() {
+    with_env(&[], |env| {
+        assert_eq!(syscall::dispatch(env, 6), Err(ExecuteError::InvalidNativeInvoke));
+        assert_eq!(syscall::dispatch(env, 65535), Err(ExecuteError::InvalidNativeInvoke));
+        assert_eq!(syscall::dispatch(env, 65536), Ok(Some(65537)));
+        assert_eq!(syscall::dispatch(env, 100000), Ok(Some(100001)));
+        assert_eq!(env.user_calls, vec![65536, 100000]);
+    });
+}
+
+fn syscall_code(args: &[u32], id: usize) -> Vec<u8> {
+    let mut code = Code::new();
+    for &v in args {
+        code.i32_const(v);
+    }
+    code.native_invoke(id as u32).op(Opcode::Halt);
+    code.0
+}
+
+#[test]
+fn log_from_vm() {
+    let code = syscall_code(&[6, 6, 5], SYSCALL_LOG);
+
+    let (ret, env) = run_in(new_env(), &module(&code), false);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.get_stack().get_pos(), 0);
+    assert_eq!(*env.logs.borrow(), vec![(LogLevel::Info, "world".to_string())]);
+}
+
+#[test]
+fn clock_and_random_not_supported() {
+    with_env(&[0, 4], |env| {
+        assert_eq!(syscall::dispatch(env, SYSCALL_CLOCK_MONOTONIC), Err(ExecuteError::NotSupported));
+        assert_eq!(syscall::dispatch(env, SYSCALL_CLOCK_WALL), Err(ExecuteError::NotSupported));
+        assert_eq!(syscall::dispatch(env, SYSCALL_RANDOM), Err(ExecuteError::NotSupported));
+        assert_eq!(env.mem, b"hello\xffworld".to_vec());
+    });
+}
+
+#[test]
+fn virtual_clock() {
+    with_env(&[], |env| {
+        env.clock = Some(VirtualClock::new(1_000_000, 10));
+        assert_eq!(syscall::dispatch(env, SYSCALL_CLOCK_MONOTONIC), Ok(Some(0)));
+        assert_eq!(syscall::dispatch(env, SYSCALL_CLOCK_MONOTONIC), Ok(Some(10)));
+        assert_eq!(syscall::dispatch(env, SYSCALL_CLOCK_WALL), Ok(Some(1_000_020)));
+        assert_eq!(syscall::dispatch(env, SYSCALL_CLOCK_MONOTONIC), Ok(Some(30)));
+    });
+}
+
+fn random(seed: u64, args: &[i64]) -> (ExecuteResult<Option<i64>>, Vec<u8>) {
+    let mut ret = None;
+    with_env(args, |env| {
+        env.random = Some(SeededRandom::new(seed));
+        let r = syscall::dispatch(env, SYSCALL_RANDOM);
+        ret = Some((r, env.mem.clone()));
+    });
+    ret.unwrap()
+}
+
+#[test]
+fn seeded_random() {
+    let (r, a) = random(42, &[1, 9]);
+    assert_eq!(r, Ok(None));
+    assert_eq!(a[0], b'h');
+    assert_eq!(a[10], b'd');
+    assert_ne!(&a[1..10], b"ello\xffwor");
+
+    // Reproducible for the same seed, and different for another one
+    assert_eq!(random(42, &[1, 9]).1, a);
+    assert_ne!(random(43, &[1, 9]).1, a);
+
+    let mut expected = [0u8; 9];
+    SeededRandom::new(42).fill(&mut expected).unwrap();
+    assert_eq!(&a[1..10], &expected);
+}
+
+#[test]
+fn random_out_of_bounds() {
+    let mem = b"hello\xffworld".to_vec();
+    assert_eq!(random(1, &[6, 7]), (Err(ExecuteError::Bounds), mem.clone()));
+    assert_eq!(random(1, &[-1, 2]), (Err(ExecuteError::Bounds), mem.clone()));
+    assert_eq!(random(1, &[11, 0]), (Ok(None), mem));
+}
+
+#[test]
+fn failures_keep_arguments() {
+    let cases: &[(usize, &[i64])] = &[
+        (SYSCALL_LOG, &[6, 0, 6]),
+        (SYSCALL_LOG, &[0, 0, 5]),
+        (SYSCALL_RANDOM, &[6, 7]),
+        (SYSCALL_ABORT, &[0, 6])
+    ];
+    for &(id, args) in cases {
+        with_env(args, |env| {
+            env.random = Some(SeededRandom::new(1));
+            assert!(syscall::dispatch(env, id).is_err());
+            assert_eq!(env.stack_values(), args);
+            assert_eq!(env.mem, b"hello\xffworld".to_vec());
+        });
+    }
+
+    with_env(&[1, 9], |env| {
+        assert_eq!(syscall::dispatch(env, SYSCALL_RANDOM), Err(ExecuteError::NotSupported));
+        assert_eq!(env.stack_values(), vec![1, 9]);
+
+        env.random = Some(SeededRandom::new(1));
+        assert_eq!(syscall::dispatch(env, SYSCALL_RANDOM), Ok(None));
+        assert!(env.stack_values().is_empty());
+    });
+}
+
+fn run_to_exit(code: &[u8]) -> (ExecuteResult<i32>, Vec<u8>) {
+    let module = module(code);
+    let mut vm = VirtualMachine::new(&module, new_env());
+    let ret = vm.run_to_exit();
+    (ret, vm.env.mem.clone())
+}
+
+#[test]
+fn exit_status() {
+    assert_eq!(run_to_exit(&syscall_code(&[42], SYSCALL_EXIT)).0, Ok(42));
+    assert_eq!(run_to_exit(&syscall_code(&[0xffffffff], SYSCALL_EXIT)).0, Ok(-1));
+    assert_eq!(run_to_exit(&[Opcode::Halt as u8]).0, Ok(0));
+}
+
+#[test]
+fn abort_message() {
+    let (ret, mem) = run_to_exit(&syscall_code(&[6, 5], SYSCALL_ABORT));
+    let err = ret.unwrap_err();
+    assert_eq!(err, ExecuteError::Abort { message_base: 6, message_len: 5 });
+    assert_eq!(err.abort_message(&mem), Some("world"));
+    assert_eq!(ExecuteError::Bounds.abort_message(&mem), None);
+
+    // The message is checked like `log` text
+    assert_eq!(run_to_exit(&syscall_code(&[0, 6], SYSCALL_ABORT)).0, Err(ExecuteError::InvalidInput));
+    assert_eq!(run_to_exit(&syscall_code(&[8, 5], SYSCALL_ABORT)).0, Err(ExecuteError::Bounds));
+}
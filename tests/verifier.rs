@@ -0,0 +1,208 @@
+// Tests for bytecode verification and the unchecked execution path it enables.
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::layer::{Hooks, Layer};
+use hexagon_e::module::Opcode;
+use hexagon_e::verifier::{self, CodeInfo};
+
+use common::*;
+
+// Pushes a value from the tracing hook on the first `Nop`.
+struct StackPusher;
+
+impl<E: Environment> Hooks<E> for StackPusher {
+    fn trace_opcode(&self, env: &E, op: &Opcode) -> ExecuteResult<()> {
+        if let Opcode::Nop = *op {
+            env.get_stack().next()?.set(42);
+        }
+        env.trace_opcode(op)
+    }
+}
+
+#[test]
+fn host_stack_changes() {
+    let mut c = Code::new();
+    c.i32_const(1).op(Opcode::Nop).op(Opcode::Drop).op(Opcode::Halt);
+    let m = module(&c.0);
+
+    // Checked code stays within bounds without relying on the heights.
+    let (ret, _) = run_in(Layer::new(TestEnv::default(), StackPusher), &m, false);
+    assert_eq!(ret, Ok(()));
+
+    let (ret, _) = run_in(Layer::new(TestEnv::default(), StackPusher), &m, true);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+}
+
+fn verify(code: &Code) -> ExecuteResult<()> {
+    let m = module(&code.0);
+    let mut info = vec![CodeInfo::default(); code.0.len()];
+    verifier::verify(&m, &mut info, |_| None).map(|_| ())
+}
+
+// f(a, b) = a * b + 1, called with (6, 7). Returns the code and the entry of f.
+fn call_program() -> (Code, usize) {
+    let mut c = Code::new();
+    c.i32_const(6).i32_const(7);
+    let call = c.pos();
+    c.call(Opcode::CallDirect, 0, 2, 0).op(Opcode::Halt);
+    let f = c.pos();
+    c.op_u32(Opcode::GetLocal, 0).op_u32(Opcode::GetLocal, 1).op(Opcode::I32Mul)
+        .i32_const(1).op(Opcode::I32Add).op(Opcode::Return);
+    c.patch(call + 1, f);
+    (c, f as usize)
+}
+
+// Sums 1..=n for n = 10 in a loop through slot 0.
+fn loop_program() -> Code {
+    let mut c = Code::new();
+    c.op_u32(Opcode::ResetSlots, 1).i32_const(0).i32_const(10);
+    let head = c.pos();
+    // acc n
+    c.op(Opcode::Dup).op_u32(Opcode::SetSlot, 0).op(Opcode::I32Add)
+        .op_u32(Opcode::GetSlot, 0).i32_const(1).op(Opcode::I32Sub)
+        .op(Opcode::Dup).op_u32(Opcode::JmpIf, head).op(Opcode::Drop).op(Opcode::Halt);
+    c
+}
+
+#[test]
+fn accepts_valid_code() {
+    let (c, f) = call_program();
+    let m = module(&c.0);
+    let mut info = vec![CodeInfo::default(); c.0.len()];
+    let v = verifier::verify(&m, &mut info, |_| None).unwrap();
+
+    assert_eq!(v.max_depth(0), Ok(2));
+    assert_eq!(v.max_depth(f), Ok(2));
+    assert_eq!(v.n_results(f), Some(1));
+    assert_eq!(v.function_at(f + 5), Some(f));
+    assert_eq!(v.height_delta(f, f + 10), Ok(2));
+
+    assert_eq!(verify(&loop_program()), Ok(()));
+}
+
+#[test]
+fn rejects_unbalanced_jump_targets() {
+    let mut c = Code::new();
+    c.i32_const(1).op_u32(Opcode::JmpIf, 0);
+    let at = c.pos() - 4;
+    c.i32_const(5);
+    let target = c.pos();
+    c.op(Opcode::Halt);
+    c.patch(at, target);
+    assert_eq!(verify(&c), Err(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn rejects_stack_underflow() {
+    let mut c = Code::new();
+    c.op(Opcode::Drop).op(Opcode::Halt);
+    assert_eq!(verify(&c), Err(ExecuteError::InvalidInput));
+
+    let mut c = Code::new();
+    c.i32_const(1).op(Opcode::I32Add).op(Opcode::Halt);
+    assert_eq!(verify(&c), Err(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn verifies_callees_entered_by_pending_calls() {
+    // A's call to B is still pending when it enters B, which comes before A.
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 18, 0, 0).op(Opcode::Halt);
+    c.op(Opcode::Drop).op(Opcode::Drop).op(Opcode::Drop).op(Opcode::Return);
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Return);
+
+    assert_eq!(verify(&c), Err(ExecuteError::InvalidInput));
+    assert_eq!(run(&c).0, Err(ExecuteError::Bounds));
+    assert_eq!(run_in(TestEnv::default(), &module(&c.0), true).0, Err(ExecuteError::InvalidInput));
+}
+
+#[test]
+fn requires_headroom() {
+    // Checked before running the entry function.
+    let (c, _) = call_program();
+    let m = module(&c.0);
+
+    let (ret, env) = run_in(TestEnv::new(0, 1, 16), &m, true);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+    assert!(env.stack_values().is_empty());
+
+    let (ret, env) = run_in(TestEnv::new(0, 2, 16), &m, true);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.stack_values(), vec![43]);
+
+    // And before entering a callee that needs more than its caller.
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Halt);
+    c.i32_const(1).i32_const(2).op(Opcode::I32Add).op(Opcode::Return);
+    let m = module(&c.0);
+
+    let (ret, env) = run_in(TestEnv::new(0, 1, 16), &m, true);
+    assert_eq!(ret, Err(ExecuteError::Bounds));
+    assert!(env.stack_values().is_empty());
+
+    let (ret, env) = run_in(TestEnv::new(0, 2, 16), &m, true);
+    assert_eq!(ret, Ok(()));
+    assert_eq!(env.stack_values(), vec![3]);
+}
+
+#[test]
+fn rejects_unknown_call_targets() {
+    let mut c = Code::new();
+    c.i32_const(10).i32_const(1).op(Opcode::I32Add).i32_const(0)
+        .op_u32(Opcode::Call, 0).op(Opcode::Halt);
+    assert_eq!(verify(&c), Err(ExecuteError::InvalidInput));
+
+    // Constant operands name the callee.
+    let mut c = Code::new();
+    c.i32_const(16).i32_const(0).op_u32(Opcode::Call, 0).op(Opcode::Halt).op(Opcode::Return);
+    assert_eq!(verify(&c), Ok(()));
+}
+
+#[test]
+fn rejects_return_call_with_extra_values() {
+    // f pushes two values before a tail call that takes one.
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Halt);
+    assert_eq!(c.pos(), 14);
+    c.i32_const(1).i32_const(2);
+    let at = c.pos();
+    c.call(Opcode::ReturnCall, 0, 1, 0);
+    let g = c.pos();
+    c.op(Opcode::Return);
+    c.patch(at + 1, g);
+    assert_eq!(verify(&c), Err(ExecuteError::InvalidInput));
+
+    // Dropping the extra value makes it valid.
+    let mut c = Code::new();
+    c.call(Opcode::CallDirect, 14, 0, 0).op(Opcode::Halt);
+    c.i32_const(1).i32_const(2).op(Opcode::Drop);
+    let at = c.pos();
+    c.call(Opcode::ReturnCall, 0, 1, 0);
+    let g = c.pos();
+    c.op(Opcode::Return);
+    c.patch(at + 1, g);
+    assert_eq!(verify(&c), Ok(()));
+}
+
+#[test]
+fn verified_matches_checked() {
+    assert_eq!(run_both(&call_program().0), (Ok(()), vec![43]));
+    assert_eq!(run_both(&loop_program()), (Ok(()), vec![55]));
+
+    let mut c = Code::new();
+    c.i32_const(8).i64_const(0x1122334455667788).op_u32(Opcode::I64Store, 0)
+        .i32_const(12).op_u32(Opcode::I32Load16U, 0)
+        .i32_const(7).op(Opcode::I32DivU).op(Opcode::Halt);
+    assert_eq!(run_both(&c), (Ok(()), vec![0x3344 / 7]));
+
+    // Both fail the same way at run time.
+    let mut c = Code::new();
+    c.i32_const(1).i32_const(0).op(Opcode::I32DivU).op(Opcode::Halt);
+    let (ret, _) = run_both(&c);
+    assert!(ret.is_err());
+}
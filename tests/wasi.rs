@@ -0,0 +1,153 @@
+// Tests for the WASI preview1 subset.
+
+extern crate hexagon_e;
+
+mod common;
+
+use hexagon_e::environment::Environment;
+use hexagon_e::error::*;
+use hexagon_e::syscall::{RandomSource, SeededRandom, VirtualClock};
+use hexagon_e::wasi::*;
+
+use common::*;
+
+fn with_env<F: FnOnce(&mut TestEnv)>(f: F) {
+    let mut env = TestEnv::new(1024, 16, 16);
+    f(&mut env);
+}
+
+fn call(env: &mut TestEnv, id: usize, args: &[i64]) -> ExecuteResult<Option<i64>> {
+    env.push(args);
+    let ret = dispatch(env, id);
+    // Only failures other than exiting leave the arguments.
+    let left = match ret {
+        Ok(_) | Err(ExecuteError::Exit(_)) => 0,
+        Err(_) => args.len()
+    };
+    assert_eq!(env.get_stack().get_pos(), left);
+    env.get_stack().set_pos(0).unwrap();
+    ret
+}
+
+fn write_u32(env: &mut TestEnv, addr: usize, v: u32) {
+    env.mem[addr..addr + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn read_u32(env: &TestEnv, addr: usize) -> u32 {
+    let mut v = [0; 4];
+    v.copy_from_slice(&env.mem[addr..addr + 4]);
+    u32::from_le_bytes(v)
+}
+
+#[test]
+fn fd_write_iovecs() {
+    with_env(|env| {
+        env.mem[100..105].copy_from_slice(b"hello");
+        env.mem[200..207].copy_from_slice(b" world\n");
+        write_u32(env, 0, 100);
+        write_u32(env, 4, 5);
+        write_u32(env, 8, 200);
+        write_u32(env, 12, 7);
+
+        assert_eq!(call(env, WASI_FD_WRITE, &[1, 0, 2, 16]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(env.stdout, b"hello world\n");
+        assert_eq!(read_u32(env, 16), 12);
+
+        assert_eq!(call(env, WASI_FD_WRITE, &[2, 0, 1, 16]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(env.stderr, b"hello");
+
+        assert_eq!(call(env, WASI_FD_WRITE, &[0, 0, 1, 16]), Ok(Some(ERRNO_BADF as i64)));
+        assert_eq!(call(env, WASI_FD_WRITE, &[3, 0, 1, 16]), Ok(Some(ERRNO_BADF as i64)));
+
+        // A buffer past the end of memory
+        write_u32(env, 20, 1020);
+        write_u32(env, 24, 5);
+        assert_eq!(call(env, WASI_FD_WRITE, &[1, 20, 1, 16]), Ok(Some(ERRNO_FAULT as i64)));
+        assert_eq!(call(env, WASI_FD_WRITE, &[1, 1020, 1, 16]), Ok(Some(ERRNO_FAULT as i64)));
+    });
+}
+
+#[test]
+fn fd_read_stops_at_short_read() {
+    with_env(|env| {
+        env.stdin = b"abcdefg".to_vec();
+        write_u32(env, 0, 100);
+        write_u32(env, 4, 4);
+        write_u32(env, 8, 200);
+        write_u32(env, 12, 8);
+        write_u32(env, 16, 300);
+        write_u32(env, 20, 8);
+
+        assert_eq!(call(env, WASI_FD_READ, &[0, 0, 3, 24]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(read_u32(env, 24), 7);
+        assert_eq!(&env.mem[100..104], b"abcd");
+        assert_eq!(&env.mem[200..204], b"efg\0");
+
+        assert_eq!(call(env, WASI_FD_READ, &[0, 0, 3, 24]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(read_u32(env, 24), 0);
+        assert_eq!(call(env, WASI_FD_READ, &[1, 0, 3, 24]), Ok(Some(ERRNO_BADF as i64)));
+    });
+}
+
+#[test]
+fn args() {
+    with_env(|env| {
+        env.args = vec!["prog".to_string(), "--flag".to_string()];
+
+        assert_eq!(call(env, WASI_ARGS_SIZES_GET, &[0, 4]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(read_u32(env, 0), 2);
+        assert_eq!(read_u32(env, 4), 12);
+
+        assert_eq!(call(env, WASI_ARGS_GET, &[16, 100]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(read_u32(env, 16), 100);
+        assert_eq!(read_u32(env, 20), 105);
+        assert_eq!(&env.mem[100..112], b"prog\0--flag\0");
+
+        // No environment variables
+        assert_eq!(call(env, WASI_ENVIRON_SIZES_GET, &[0, 4]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!((read_u32(env, 0), read_u32(env, 4)), (0, 0));
+
+        assert_eq!(call(env, WASI_ARGS_GET, &[1022, 100]), Ok(Some(ERRNO_FAULT as i64)));
+    });
+}
+
+#[test]
+fn clock_and_random() {
+    with_env(|env| {
+        assert_eq!(call(env, WASI_CLOCK_TIME_GET, &[1, 0, 0]), Ok(Some(ERRNO_NOTSUP as i64)));
+        assert_eq!(call(env, WASI_RANDOM_GET, &[0, 8]), Ok(Some(ERRNO_NOTSUP as i64)));
+
+        env.clock = Some(VirtualClock::new(5000, 10));
+        assert_eq!(call(env, WASI_CLOCK_TIME_GET, &[1, 0, 0]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(call(env, WASI_CLOCK_TIME_GET, &[0, 0, 8]), Ok(Some(ERRNO_SUCCESS as i64)));
+        assert_eq!(&env.mem[0..16], &[0, 0, 0, 0, 0, 0, 0, 0, 0x92, 0x13, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(call(env, WASI_CLOCK_TIME_GET, &[2, 0, 0]), Ok(Some(ERRNO_NOTSUP as i64)));
+        assert_eq!(call(env, WASI_CLOCK_TIME_GET, &[4, 0, 0]), Ok(Some(ERRNO_INVAL as i64)));
+
+        env.random = Some(SeededRandom::new(7));
+        assert_eq!(call(env, WASI_RANDOM_GET, &[100, 300]), Ok(Some(ERRNO_SUCCESS as i64)));
+        let mut expected = vec![0; 300];
+        SeededRandom::new(7).fill(&mut expected).unwrap();
+        assert_eq!(&env.mem[100..400], &expected[..]);
+        assert_eq!(call(env, WASI_RANDOM_GET, &[1000, 100]), Ok(Some(ERRNO_FAULT as i64)));
+    });
+}
+
+#[test]
+fn proc_exit() {
+    with_env(|env| {
+        assert_eq!(call(env, WASI_PROC_EXIT, &[3]), Err(ExecuteError::Exit(3)));
+        assert_eq!(call(env, WASI_FD_WRITE, &[1, 0]), Err(ExecuteError::Bounds));
+        assert_eq!(dispatch(env, WASI_BASE + 100), Err(ExecuteError::InvalidNativeInvoke));
+    });
+}
+
+#[cfg(feature = "shared-memory")]
+#[test]
+fn shared_memory_not_supported() {
+    with_env(|env| {
+        env.shared = Some(shared_memory(64));
+        assert_eq!(call(env, WASI_ARGS_SIZES_GET, &[0, 4]), Err(ExecuteError::NotSupported));
+        assert_eq!(call(env, WASI_FD_WRITE, &[1, 0, 1, 16]), Err(ExecuteError::NotSupported));
+    });
+}